@@ -0,0 +1,244 @@
+//! Keeping track of which trackers are answering, so that a client
+//! doesn't keep going back to one that isn't.
+//!
+//! Each tracker has a circuit breaker, which starts out closed. Once
+//! the tracker has failed `failure_threshold` times in a row, it trips
+//! open, and the tracker isn't tried again until it's backed off for a
+//! while. The backoff doubles every time it fails again, up to a
+//! limit. When the backoff's over, the breaker's half-open, and the
+//! next request sent to the tracker decides whether it closes again.
+
+use mogilefs_common::{MogError, MogResult};
+use rand;
+use std::cmp;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// How many failures in a row trip a tracker's breaker, by default.
+pub const DEFAULT_FAILURE_THRESHOLD: u32 = 3;
+
+/// How long, in milliseconds, a tracker is backed off for when its
+/// breaker first trips, by default.
+pub const DEFAULT_BASE_BACKOFF_MS: u64 = 500;
+
+/// The longest, in milliseconds, a tracker is ever backed off for, by
+/// default.
+pub const DEFAULT_MAX_BACKOFF_MS: u64 = 60000;
+
+/// The state of a tracker's circuit breaker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// The tracker's answering, as far as anyone knows.
+    Closed,
+    /// The tracker's failing, and won't be tried until its backoff is
+    /// over.
+    Open,
+    /// The tracker's backoff is over, and it'll be tried again.
+    HalfOpen,
+}
+
+/// How a tracker's been doing, for reporting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackerStatus {
+    pub addr: SocketAddr,
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    /// How long until the tracker may be tried again, if its breaker's
+    /// open.
+    pub retry_in: Option<Duration>,
+}
+
+#[derive(Debug)]
+struct TrackerHealth {
+    addr: SocketAddr,
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl TrackerHealth {
+    fn state(&self, now: Instant) -> CircuitState {
+        match self.open_until {
+            None => CircuitState::Closed,
+            Some(until) if now < until => CircuitState::Open,
+            Some(_) => CircuitState::HalfOpen,
+        }
+    }
+}
+
+/// The trackers a client can talk to, and how each has been doing.
+#[derive(Debug)]
+pub struct TrackerSet {
+    trackers: Vec<TrackerHealth>,
+    pub failure_threshold: u32,
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl TrackerSet {
+    pub fn new(addrs: Vec<SocketAddr>) -> TrackerSet {
+        TrackerSet {
+            trackers: addrs.into_iter().map(|addr| TrackerHealth { addr: addr, consecutive_failures: 0, open_until: None }).collect(),
+            failure_threshold: DEFAULT_FAILURE_THRESHOLD,
+            base_backoff: Duration::from_millis(DEFAULT_BASE_BACKOFF_MS),
+            max_backoff: Duration::from_millis(DEFAULT_MAX_BACKOFF_MS),
+        }
+    }
+
+    /// The tracker to try next: one whose breaker isn't open, which
+    /// has failed the fewest times lately, picked at random from those
+    /// which have failed as often. If every breaker's open, there's no
+    /// point trying any of them.
+    pub fn choose(&self, now: Instant) -> MogResult<SocketAddr> {
+        if self.trackers.is_empty() {
+            return Err(MogError::NoTrackers);
+        }
+
+        let available: Vec<&TrackerHealth> = self.trackers.iter()
+            .filter(|t| t.state(now) != CircuitState::Open)
+            .collect();
+        let fewest_failures = try!(available.iter().map(|t| t.consecutive_failures).min().ok_or(MogError::NoConnection));
+        let best = available.into_iter().filter(|t| t.consecutive_failures == fewest_failures);
+
+        let mut rng = rand::thread_rng();
+        let mut sample = rand::sample(&mut rng, best, 1);
+        Ok(sample.pop().unwrap().addr)
+    }
+
+    /// Note that a request to a tracker worked, closing its breaker.
+    pub fn record_success(&mut self, addr: &SocketAddr) {
+        if let Some(tracker) = self.trackers.iter_mut().find(|t| &t.addr == addr) {
+            if tracker.open_until.is_some() {
+                info!("Tracker {} is answering again", addr);
+            }
+            tracker.consecutive_failures = 0;
+            tracker.open_until = None;
+        }
+    }
+
+    /// Note that a request to a tracker failed, which trips its
+    /// breaker (again) if it's failed too often.
+    pub fn record_failure(&mut self, addr: &SocketAddr, now: Instant) {
+        let threshold = self.failure_threshold;
+        let backoff = {
+            let failures = self.trackers.iter().find(|t| &t.addr == addr).map(|t| t.consecutive_failures + 1).unwrap_or(0);
+            if failures < threshold {
+                None
+            } else {
+                Some(self.backoff(failures - threshold))
+            }
+        };
+
+        if let Some(tracker) = self.trackers.iter_mut().find(|t| &t.addr == addr) {
+            tracker.consecutive_failures += 1;
+            if let Some(backoff) = backoff {
+                warn!("Backing off tracker {} for {:?} after {} failures in a row", addr, backoff, tracker.consecutive_failures);
+                tracker.open_until = Some(now + backoff);
+            }
+        }
+    }
+
+    /// How each tracker's doing.
+    pub fn statuses(&self, now: Instant) -> Vec<TrackerStatus> {
+        self.trackers.iter().map(|t| {
+            let state = t.state(now);
+            TrackerStatus {
+                addr: t.addr,
+                state: state,
+                consecutive_failures: t.consecutive_failures,
+                retry_in: match (state, t.open_until) {
+                    (CircuitState::Open, Some(until)) => Some(until - now),
+                    _ => None,
+                },
+            }
+        }).collect()
+    }
+
+    /// How long to back off for after tripping `retrips` more times
+    /// since the breaker first tripped.
+    fn backoff(&self, retrips: u32) -> Duration {
+        let doublings = cmp::min(retrips, 16);
+        cmp::min(self.base_backoff * 2u32.pow(doublings), self.max_backoff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mogilefs_common::MogError;
+    use std::net::SocketAddr;
+    use std::time::{Duration, Instant};
+    use super::*;
+
+    fn addrs() -> Vec<SocketAddr> {
+        vec![ "127.0.0.1:7001".parse().unwrap(), "127.0.0.1:7002".parse().unwrap() ]
+    }
+
+    fn tracker_set() -> TrackerSet {
+        let mut trackers = TrackerSet::new(addrs());
+        trackers.failure_threshold = 2;
+        trackers.base_backoff = Duration::from_secs(1);
+        trackers.max_backoff = Duration::from_secs(3);
+        trackers
+    }
+
+    #[test]
+    fn prefers_trackers_with_fewer_failures() {
+        let now = Instant::now();
+        let mut trackers = tracker_set();
+        trackers.record_failure(&addrs()[0], now);
+
+        for _ in 0..10 {
+            assert_eq!(addrs()[1], trackers.choose(now).unwrap());
+        }
+
+        trackers.record_success(&addrs()[0]);
+        assert_eq!(CircuitState::Closed, trackers.statuses(now)[0].state);
+        assert_eq!(0, trackers.statuses(now)[0].consecutive_failures);
+    }
+
+    #[test]
+    fn breaker_trips_and_backs_off() {
+        let now = Instant::now();
+        let mut trackers = tracker_set();
+        let addr = addrs()[0];
+
+        trackers.record_failure(&addr, now);
+        assert_eq!(CircuitState::Closed, trackers.statuses(now)[0].state);
+        trackers.record_failure(&addr, now);
+        assert_eq!(CircuitState::Open, trackers.statuses(now)[0].state);
+        assert_eq!(Some(Duration::from_secs(1)), trackers.statuses(now)[0].retry_in);
+
+        let later = now + Duration::from_secs(1);
+        assert_eq!(CircuitState::HalfOpen, trackers.statuses(later)[0].state);
+
+        // Each failure after that backs off for longer, up to the
+        // limit.
+        trackers.record_failure(&addr, later);
+        assert_eq!(Some(Duration::from_secs(2)), trackers.statuses(later)[0].retry_in);
+        trackers.record_failure(&addr, later);
+        assert_eq!(Some(Duration::from_secs(3)), trackers.statuses(later)[0].retry_in);
+
+        trackers.record_success(&addr);
+        assert_eq!(CircuitState::Closed, trackers.statuses(later)[0].state);
+    }
+
+    #[test]
+    fn no_trackers_available() {
+        let now = Instant::now();
+        let mut trackers = tracker_set();
+        for addr in addrs().iter() {
+            trackers.record_failure(addr, now);
+            trackers.record_failure(addr, now);
+        }
+
+        match trackers.choose(now) {
+            Err(MogError::NoConnection) => {},
+            r => panic!("Choosing from backed off trackers gave {:?}", r),
+        }
+        assert!(trackers.choose(now + Duration::from_secs(1)).is_ok());
+
+        match TrackerSet::new(vec![]).choose(now) {
+            Err(MogError::NoTrackers) => {},
+            r => panic!("Choosing from no trackers gave {:?}", r),
+        }
+    }
+}
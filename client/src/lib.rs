@@ -13,6 +13,7 @@ extern crate log;
 #[macro_use]
 extern crate lazy_static;
 
+use breaker::TrackerSet;
 use bufstream::BufStream;
 use chrono::UTC;
 use hyper::status::StatusCode;
@@ -20,8 +21,26 @@ use mogilefs_common::{Request, Response, MogError, MogResult, BufReadMb, ToArgs,
 use mogilefs_common::requests::*;
 use std::io::{self, Read, Write};
 use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
 use url::percent_encoding;
 
+pub use breaker::{CircuitState, TrackerStatus};
+
+mod breaker;
+
+/// How many times a request is sent, reconnecting in between, before
+/// giving up on it, by default.
+pub const DEFAULT_MAX_TRIES: usize = 3;
+
+/// How long to wait on a tracker before giving up on it. `None`, or
+/// a zero duration, means waiting as long as it takes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    pub connect: Option<Duration>,
+    pub read: Option<Duration>,
+    pub write: Option<Duration>,
+}
+
 pub struct MogClient {
     transport: MogClientTransport,
     statsd: Option<statsd::Client>,
@@ -35,6 +54,23 @@ impl MogClient {
         }
     }
 
+    /// Start building a client that's set up differently from the
+    /// defaults.
+    ///
+    /// ```no_run
+    /// # use mogilefs_client::MogClient;
+    /// # use std::time::Duration;
+    /// let client = MogClient::builder(&["127.0.0.1:7001"])
+    ///     .connect_timeout(Duration::from_secs(5))
+    ///     .read_timeout(Duration::from_secs(30))
+    ///     .build();
+    /// ```
+    pub fn builder<S: ToSocketAddrs>(trackers: &[S]) -> MogClientBuilder {
+        MogClientBuilder {
+            client: MogClient::new(trackers),
+        }
+    }
+
     pub fn report_stats_to(&mut self, host: &str, prefix: &str) -> MogResult<()> {
         debug!("Reporting stats to statsd at {:?} with prefix {:?}", host, prefix);
         match statsd::Client::new(host, prefix) {
@@ -108,6 +144,32 @@ impl MogClient {
         })
     }
 
+    /// Set how many times a request is sent, reconnecting to a
+    /// tracker in between, before giving up on it. A client that
+    /// fails over to other trackers itself wants just the one.
+    pub fn set_max_tries(&mut self, tries: usize) {
+        self.transport.max_tries = tries;
+    }
+
+    /// Set how many failures in a row it takes for a tracker to be
+    /// backed off from.
+    pub fn set_failure_threshold(&mut self, failures: u32) {
+        self.transport.trackers.failure_threshold = failures;
+    }
+
+    /// Set how long a tracker is backed off from when it first fails
+    /// too often, and the longest it'll ever be backed off from as it
+    /// keeps failing.
+    pub fn set_backoff(&mut self, base: Duration, max: Duration) {
+        self.transport.trackers.base_backoff = base;
+        self.transport.trackers.max_backoff = max;
+    }
+
+    /// How each of the trackers has been doing lately.
+    pub fn tracker_statuses(&self) -> Vec<TrackerStatus> {
+        self.transport.trackers.statuses(Instant::now())
+    }
+
     pub fn is_connected(&self) -> bool {
         self.transport.is_connected()
     }
@@ -117,17 +179,77 @@ impl MogClient {
     }
 }
 
+/// Builds a `MogClient`; see `MogClient::builder`.
+pub struct MogClientBuilder {
+    client: MogClient,
+}
+
+impl MogClientBuilder {
+    /// How long to wait for a connection to a tracker.
+    pub fn connect_timeout(mut self, timeout: Duration) -> MogClientBuilder {
+        self.client.transport.timeouts.connect = Some(timeout);
+        self
+    }
+
+    /// How long to wait for a tracker to answer a request.
+    pub fn read_timeout(mut self, timeout: Duration) -> MogClientBuilder {
+        self.client.transport.timeouts.read = Some(timeout);
+        self
+    }
+
+    /// How long to wait for a tracker to take a request.
+    pub fn write_timeout(mut self, timeout: Duration) -> MogClientBuilder {
+        self.client.transport.timeouts.write = Some(timeout);
+        self
+    }
+
+    /// All three timeouts at once.
+    pub fn timeouts(mut self, timeouts: Timeouts) -> MogClientBuilder {
+        self.client.transport.timeouts = timeouts;
+        self
+    }
+
+    /// See `MogClient::set_max_tries`.
+    pub fn max_tries(mut self, tries: usize) -> MogClientBuilder {
+        self.client.set_max_tries(tries);
+        self
+    }
+
+    /// See `MogClient::set_failure_threshold`.
+    pub fn failure_threshold(mut self, failures: u32) -> MogClientBuilder {
+        self.client.set_failure_threshold(failures);
+        self
+    }
+
+    /// See `MogClient::set_backoff`.
+    pub fn backoff(mut self, base: Duration, max: Duration) -> MogClientBuilder {
+        self.client.set_backoff(base, max);
+        self
+    }
+
+    pub fn build(self) -> MogClient {
+        self.client
+    }
+}
+
 #[derive(Debug)]
 struct MogClientTransport {
-    hosts: Vec<SocketAddr>,
+    trackers: TrackerSet,
     stream: Option<ConnectionState>,
+    /// The tracker the stream's connected to, or was last.
+    tracker: Option<SocketAddr>,
+    max_tries: usize,
+    timeouts: Timeouts,
 }
 
 impl MogClientTransport {
     fn new<S: ToSocketAddrs + Sized>(tracker_addrs: &[S]) -> MogClientTransport {
         MogClientTransport {
-            hosts: tracker_addrs.iter().flat_map(|a| a.to_socket_addrs().unwrap()).collect(),
+            trackers: TrackerSet::new(tracker_addrs.iter().flat_map(|a| a.to_socket_addrs().unwrap()).collect()),
             stream: Some(ConnectionState::new()),
+            tracker: None,
+            max_tries: DEFAULT_MAX_TRIES,
+            timeouts: Timeouts::default(),
         }
     }
 
@@ -138,12 +260,6 @@ impl MogClientTransport {
         }
     }
 
-    fn random_tracker_addr(&self) -> MogResult<SocketAddr> {
-        let mut rng = rand::thread_rng();
-        let mut sample = rand::sample(&mut rng, self.hosts.iter(), 1);
-        sample.pop().cloned().ok_or(MogError::NoTrackers)
-    }
-
     fn do_request<R: Request + ?Sized>(&mut self, request: &R) -> MogResult<Response> {
         let mut stream = self.stream.take().unwrap_or(ConnectionState::new());
         let req_line = format!("{} {}\r\n", request.op(), request.to_urlencoded_string());
@@ -152,9 +268,10 @@ impl MogClientTransport {
 
         loop {
             if !stream.is_connected() {
-                let tracker = try!(self.random_tracker_addr());
+                let tracker = try!(self.trackers.choose(Instant::now()));
                 debug!("Connecting to {:?}", tracker);
-                stream = stream.connect(&tracker);
+                stream = stream.connect(&tracker, &self.timeouts);
+                self.tracker = Some(tracker);
             }
 
             debug!("req_line = {:?}", req_line);
@@ -163,7 +280,16 @@ impl MogClientTransport {
             debug!("resp_line = {:?}", String::from_utf8_lossy(&resp_line));
             tries += 1;
 
-            if stream.is_connected() || tries >= 3 { break; }
+            // An error response still means the tracker's answering.
+            if let Some(tracker) = self.tracker {
+                if stream.is_connected() {
+                    self.trackers.record_success(&tracker);
+                } else {
+                    self.trackers.record_failure(&tracker, Instant::now());
+                }
+            }
+
+            if stream.is_connected() || tries >= self.max_tries { break; }
         }
 
 
@@ -171,6 +297,12 @@ impl MogClientTransport {
         self.stream = Some(stream);
 
         match err {
+            // Sockets with a timeout say so with WouldBlock on some
+            // platforms, and TimedOut on others.
+            Some(ref err) if err.kind() == io::ErrorKind::TimedOut || err.kind() == io::ErrorKind::WouldBlock => {
+                let tracker = self.tracker.map(|t| t.to_string()).unwrap_or("tracker".to_string());
+                Err(MogError::Timeout(tracker))
+            },
             Some(err) => Err(MogError::Io(err)),
             None => {
                 if resp_line.ends_with(b"\r\n") {
@@ -183,6 +315,14 @@ impl MogClientTransport {
     }
 }
 
+/// Sockets won't take a timeout of zero, which means no timeout here.
+fn nonzero(timeout: Option<Duration>) -> Option<Duration> {
+    match timeout {
+        Some(t) if t == Duration::new(0, 0) => None,
+        t => t,
+    }
+}
+
 fn response_from_bytes<R: Request + ?Sized>(request: &R, bytes: &[u8]) -> MogResult<Response> {
     let mut toks = bytes.splitn(2, |&b| b == b' ');
     let op = toks.next();
@@ -239,14 +379,24 @@ impl ConnectionState {
         }
     }
 
-    fn connect(self, addr: &SocketAddr) -> ConnectionState {
+    fn connect(self, addr: &SocketAddr, timeouts: &Timeouts) -> ConnectionState {
         use self::ConnectionState::*;
 
         match self {
             Connected(..) => self,
             _ => {
                 trace!("Opening connection to {:?}...", addr);
-                match TcpStream::connect(addr) {
+                let connected = match nonzero(timeouts.connect) {
+                    Some(timeout) => TcpStream::connect_timeout(addr, timeout),
+                    None => TcpStream::connect(addr),
+                };
+                let configured = connected.and_then(|stream| {
+                    try!(stream.set_read_timeout(nonzero(timeouts.read)));
+                    try!(stream.set_write_timeout(nonzero(timeouts.write)));
+                    Ok(stream)
+                });
+
+                match configured {
                     Ok(stream) => {
                         trace!("... connected to {:?}", addr);
                         Connected(BufStream::new(stream))
@@ -307,11 +457,12 @@ impl ConnectionState {
 
 #[cfg(test)]
 mod tests {
-    use mogilefs_common::Response;
+    use mogilefs_common::{MogError, Response};
     use mogilefs_common::requests::*;
     use std::env;
-    use std::io::{self, Cursor, Write};
-    use std::net::SocketAddr;
+    use std::io::{self, BufRead, BufReader, Cursor, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::thread;
     use std::str::FromStr;
     use super::*;
 
@@ -361,6 +512,63 @@ mod tests {
         assert!(conn.is_connected());
     }
 
+    #[test]
+    fn test_failing_tracker_backed_off() {
+        let dead = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let mut conn = MogClient::new(&[dead]);
+        assert!(conn.request(&Noop).is_err());
+
+        let statuses = conn.tracker_statuses();
+        assert_eq!(dead, statuses[0].addr);
+        assert_eq!(CircuitState::Open, statuses[0].state);
+        assert_eq!(3, statuses[0].consecutive_failures);
+
+        // It isn't tried again until it's been backed off from.
+        match conn.request(&Noop) {
+            Err(MogError::NoConnection) => {},
+            r => panic!("Request to a backed off tracker gave {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_hung_tracker_times_out() {
+        // A tracker which takes connections but never answers them.
+        let hung = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = hung.local_addr().unwrap();
+        let mut conn = MogClient::builder(&[addr])
+            .connect_timeout(Duration::from_secs(1))
+            .read_timeout(Duration::from_millis(100))
+            .max_tries(1)
+            .build();
+
+        match conn.request(&Noop) {
+            Err(MogError::Timeout(ref tracker)) => assert_eq!(&addr.to_string(), tracker),
+            r => panic!("Request to a hung tracker gave {:?}", r),
+        }
+        assert_eq!(1, conn.tracker_statuses()[0].consecutive_failures);
+    }
+
+    #[test]
+    fn test_zero_timeouts_never_time_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move|| {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            let mut line = String::new();
+            BufReader::new(stream).read_line(&mut line).unwrap();
+            writer.write_all(b"OK \r\n").unwrap();
+        });
+
+        let zero = Duration::new(0, 0);
+        let mut conn = MogClient::builder(&[addr])
+            .connect_timeout(zero)
+            .read_timeout(zero)
+            .write_timeout(zero)
+            .build();
+        assert!(conn.request(&Noop).is_ok());
+    }
+
     #[test]
     fn test_store_data() {
         let mut conn = test_conn!();
@@ -1,5 +1,5 @@
 use std::io::Read;
-use super::error::MogResult;
+use super::error::{MogError, MogResult};
 use super::request::{Request, Response};
 use super::requests::*;
 
@@ -15,6 +15,66 @@ pub trait Backend: Send + Sync {
     fn rename       (&self, &Rename)       -> MogResult<()>;
     fn list_keys    (&self, &ListKeys)     -> MogResult<ListKeysResponse>;
 
+    // These have default implementations that refuse the request, so
+    // that backends which don't support them don't need to implement
+    // them.
+
+    fn update_class(&self, req: &UpdateClass) -> MogResult<()> {
+        Err(MogError::UnknownCommand(Some(req.op().to_string())))
+    }
+
+    fn delete_domain(&self, req: &DeleteDomain) -> MogResult<DeleteDomain> {
+        Err(MogError::UnknownCommand(Some(req.op().to_string())))
+    }
+
+    fn delete_class(&self, req: &DeleteClass) -> MogResult<DeleteClass> {
+        Err(MogError::UnknownCommand(Some(req.op().to_string())))
+    }
+
+    fn get_domains(&self, req: &GetDomains) -> MogResult<GetDomainsResponse> {
+        Err(MogError::UnknownCommand(Some(req.op().to_string())))
+    }
+
+    fn get_hosts(&self, req: &GetHosts) -> MogResult<GetHostsResponse> {
+        Err(MogError::UnknownCommand(Some(req.op().to_string())))
+    }
+
+    fn get_devices(&self, req: &GetDevices) -> MogResult<GetDevicesResponse> {
+        Err(MogError::UnknownCommand(Some(req.op().to_string())))
+    }
+
+    fn create_host(&self, req: &CreateHost) -> MogResult<CreateHostResponse> {
+        Err(MogError::UnknownCommand(Some(req.op().to_string())))
+    }
+
+    fn update_host(&self, req: &UpdateHost) -> MogResult<CreateHostResponse> {
+        Err(MogError::UnknownCommand(Some(req.op().to_string())))
+    }
+
+    fn delete_host(&self, req: &DeleteHost) -> MogResult<()> {
+        Err(MogError::UnknownCommand(Some(req.op().to_string())))
+    }
+
+    fn create_device(&self, req: &CreateDevice) -> MogResult<()> {
+        Err(MogError::UnknownCommand(Some(req.op().to_string())))
+    }
+
+    fn set_state(&self, req: &SetState) -> MogResult<()> {
+        Err(MogError::UnknownCommand(Some(req.op().to_string())))
+    }
+
+    fn set_weight(&self, req: &SetWeight) -> MogResult<()> {
+        Err(MogError::UnknownCommand(Some(req.op().to_string())))
+    }
+
+    fn list_fids(&self, req: &ListFids) -> MogResult<ListFidsResponse> {
+        Err(MogError::UnknownCommand(Some(req.op().to_string())))
+    }
+
+    fn file_debug(&self, req: &FileDebug) -> MogResult<FileDebugResponse> {
+        Err(MogError::UnknownCommand(Some(req.op().to_string())))
+    }
+
     fn handle<R: Request + ?Sized>(&self, request: &R) -> MogResult<Response> where Self: Sized {
         request.perform(self)
     }
@@ -100,6 +160,62 @@ impl Backend for Box<Backend> {
     fn list_keys(&self, req: &ListKeys) -> MogResult<ListKeysResponse> {
         (&**self).list_keys(req)
     }
+
+    fn update_class(&self, req: &UpdateClass) -> MogResult<()> {
+        (&**self).update_class(req)
+    }
+
+    fn delete_domain(&self, req: &DeleteDomain) -> MogResult<DeleteDomain> {
+        (&**self).delete_domain(req)
+    }
+
+    fn delete_class(&self, req: &DeleteClass) -> MogResult<DeleteClass> {
+        (&**self).delete_class(req)
+    }
+
+    fn get_domains(&self, req: &GetDomains) -> MogResult<GetDomainsResponse> {
+        (&**self).get_domains(req)
+    }
+
+    fn get_hosts(&self, req: &GetHosts) -> MogResult<GetHostsResponse> {
+        (&**self).get_hosts(req)
+    }
+
+    fn get_devices(&self, req: &GetDevices) -> MogResult<GetDevicesResponse> {
+        (&**self).get_devices(req)
+    }
+
+    fn create_host(&self, req: &CreateHost) -> MogResult<CreateHostResponse> {
+        (&**self).create_host(req)
+    }
+
+    fn update_host(&self, req: &UpdateHost) -> MogResult<CreateHostResponse> {
+        (&**self).update_host(req)
+    }
+
+    fn delete_host(&self, req: &DeleteHost) -> MogResult<()> {
+        (&**self).delete_host(req)
+    }
+
+    fn create_device(&self, req: &CreateDevice) -> MogResult<()> {
+        (&**self).create_device(req)
+    }
+
+    fn set_state(&self, req: &SetState) -> MogResult<()> {
+        (&**self).set_state(req)
+    }
+
+    fn set_weight(&self, req: &SetWeight) -> MogResult<()> {
+        (&**self).set_weight(req)
+    }
+
+    fn list_fids(&self, req: &ListFids) -> MogResult<ListFidsResponse> {
+        (&**self).list_fids(req)
+    }
+
+    fn file_debug(&self, req: &FileDebug) -> MogResult<FileDebugResponse> {
+        (&**self).file_debug(req)
+    }
 }
 
 /// Middleware that wraps the handling of a Request.
@@ -162,4 +278,60 @@ impl Backend for BackendStack {
     fn list_keys(&self, req: &ListKeys) -> MogResult<ListKeysResponse> {
         self.backend.as_ref().unwrap().list_keys(req)
     }
+
+    fn update_class(&self, req: &UpdateClass) -> MogResult<()> {
+        self.backend.as_ref().unwrap().update_class(req)
+    }
+
+    fn delete_domain(&self, req: &DeleteDomain) -> MogResult<DeleteDomain> {
+        self.backend.as_ref().unwrap().delete_domain(req)
+    }
+
+    fn delete_class(&self, req: &DeleteClass) -> MogResult<DeleteClass> {
+        self.backend.as_ref().unwrap().delete_class(req)
+    }
+
+    fn get_domains(&self, req: &GetDomains) -> MogResult<GetDomainsResponse> {
+        self.backend.as_ref().unwrap().get_domains(req)
+    }
+
+    fn get_hosts(&self, req: &GetHosts) -> MogResult<GetHostsResponse> {
+        self.backend.as_ref().unwrap().get_hosts(req)
+    }
+
+    fn get_devices(&self, req: &GetDevices) -> MogResult<GetDevicesResponse> {
+        self.backend.as_ref().unwrap().get_devices(req)
+    }
+
+    fn create_host(&self, req: &CreateHost) -> MogResult<CreateHostResponse> {
+        self.backend.as_ref().unwrap().create_host(req)
+    }
+
+    fn update_host(&self, req: &UpdateHost) -> MogResult<CreateHostResponse> {
+        self.backend.as_ref().unwrap().update_host(req)
+    }
+
+    fn delete_host(&self, req: &DeleteHost) -> MogResult<()> {
+        self.backend.as_ref().unwrap().delete_host(req)
+    }
+
+    fn create_device(&self, req: &CreateDevice) -> MogResult<()> {
+        self.backend.as_ref().unwrap().create_device(req)
+    }
+
+    fn set_state(&self, req: &SetState) -> MogResult<()> {
+        self.backend.as_ref().unwrap().set_state(req)
+    }
+
+    fn set_weight(&self, req: &SetWeight) -> MogResult<()> {
+        self.backend.as_ref().unwrap().set_weight(req)
+    }
+
+    fn list_fids(&self, req: &ListFids) -> MogResult<ListFidsResponse> {
+        self.backend.as_ref().unwrap().list_fids(req)
+    }
+
+    fn file_debug(&self, req: &FileDebug) -> MogResult<FileDebugResponse> {
+        self.backend.as_ref().unwrap().file_debug(req)
+    }
 }
@@ -17,7 +17,16 @@ pub type MogResult<T> = Result<T, MogError>;
 /// The error types that mogilefsd can produce.
 #[derive(Debug)]
 pub enum MogError {
+    ClassExists(String),
+    ClassHasFiles(String),
+    ClassNotFound(String),
     DomainExists(String),
+    DomainNotEmpty(String),
+    DomainNotFound(String),
+    DeviceExists(String),
+    HostExists(String),
+    HostMismatch,
+    HostNotEmpty(String),
     Io(io::Error),
     KeyExists(String),
     NoClass,
@@ -26,22 +35,31 @@ pub enum MogError {
     NoDevid,
     NoDomain,
     NoFid,
+    NoHost,
+    NoIp,
     NoKey,
     NoPath,
+    NoPort,
     NoTrackers,
     InvalidMindevcount,
     Other(String, Option<String>),
     PoisonedMutex,
     RecvError,
     SendError,
+    StateTooHigh,
     UnknownCommand(Option<String>),
+    UnknownDevice(String),
+    UnknownFid(String),
+    UnknownHost(String),
     UnknownKey(String),
+    UnknownState(String),
     UnregDomain(String),
     UnregClass(String),
     UnknownCode(String),
     Utf8(Utf8Error),
     BadResponse,
     StorageError(Option<String>),
+    Timeout(String),
 }
 
 impl MogError {
@@ -51,13 +69,30 @@ impl MogError {
         use self::MogError::*;
 
         match *self {
+            ClassExists(..) => "class_exists",
+            ClassHasFiles(..) => "class_has_files",
+            ClassNotFound(..) => "class_not_found",
             DomainExists(..) => "domain_exists",
+            DomainNotEmpty(..) => "domain_not_empty",
+            DomainNotFound(..) => "domain_not_found",
+            DeviceExists(..) => "existing_devid",
+            HostExists(..) => "host_exists",
+            HostMismatch => "host_mismatch",
+            HostNotEmpty(..) => "host_not_empty",
             InvalidMindevcount => "invalid_mindevcount",
             KeyExists(..) => "key_exists",
             NoDomain => "no_domain",
+            NoHost => "no_host",
+            NoIp => "no_ip",
             NoKey => "no_key",
+            NoPort => "no_port",
+            StateTooHigh => "state_too_high",
             UnknownCommand(..) => "unknown_command",
+            UnknownDevice(..) => "unknown_device",
+            UnknownFid(..) => "unknown_fid",
+            UnknownHost(..) => "unknown_host",
             UnknownKey(..) => "unknown_key",
+            UnknownState(..) => "unknown_state",
             UnregClass(..) => "unreg_class",
             UnregDomain(..) => "unreg_domain",
 
@@ -78,6 +113,7 @@ impl MogError {
             Utf8(..) => "utf8_error",
             BadResponse => "bad_response",
             StorageError(..) => "storage_error",
+            Timeout(..) => "timeout",
         }
     }
 
@@ -94,16 +130,34 @@ impl MogError {
         });
 
         match op.map(|o| str::from_utf8(o)) {
+            Some(Ok("class_exists")) => ClassExists(msg.unwrap_or(String::new())),
+            Some(Ok("class_has_files")) => ClassHasFiles(msg.unwrap_or(String::new())),
+            Some(Ok("class_not_found")) => ClassNotFound(msg.unwrap_or(String::new())),
+            Some(Ok("domain_not_empty")) => DomainNotEmpty(msg.unwrap_or(String::new())),
+            Some(Ok("domain_not_found")) => DomainNotFound(msg.unwrap_or(String::new())),
+            Some(Ok("existing_devid")) => DeviceExists(msg.unwrap_or(String::new())),
+            Some(Ok("host_exists")) => HostExists(msg.unwrap_or(String::new())),
+            Some(Ok("host_mismatch")) => HostMismatch,
+            Some(Ok("host_not_empty")) => HostNotEmpty(msg.unwrap_or(String::new())),
             Some(Ok("invalid_mindevcount")) => InvalidMindevcount,
             Some(Ok("no_class")) => NoClass,
             Some(Ok("no_devid")) => NoDevid,
             Some(Ok("no_domain")) => NoDomain,
             Some(Ok("no_fid")) => NoFid,
+            Some(Ok("no_host")) => NoHost,
+            Some(Ok("no_ip")) => NoIp,
             Some(Ok("no_path")) => NoPath,
+            Some(Ok("no_port")) => NoPort,
+            Some(Ok("state_too_high")) => StateTooHigh,
             Some(Ok("unknown_command")) => UnknownCommand(msg),
+            Some(Ok("unknown_device")) => UnknownDevice(msg.unwrap_or(String::new())),
+            Some(Ok("unknown_fid")) => UnknownFid(msg.unwrap_or(String::new())),
+            Some(Ok("unknown_host")) => UnknownHost(msg.unwrap_or(String::new())),
             Some(Ok("unknown_key")) => UnknownKey(msg.unwrap_or(String::new())),
+            Some(Ok("unknown_state")) => UnknownState(msg.unwrap_or(String::new())),
             Some(Ok("unreg_domain")) => UnregDomain(msg.unwrap_or(String::new())),
             Some(Ok("unreg_class")) => UnregClass(msg.unwrap_or(String::new())),
+            Some(Ok("timeout")) => Timeout(msg.unwrap_or(String::new())),
             Some(Ok(s)) => Other(s.to_string(), msg),
             Some(Err(utf8e)) => Utf8(utf8e),
             None => UnknownCommand(None),
@@ -163,16 +217,31 @@ impl Display for MogError {
             UnregDomain(ref d) => write!(f, "Domain name {:?} invalid / not found", d),
             UnregClass(ref d) => write!(f, "Class name {:?} invalid / not found", d),
             DomainExists(ref d) => write!(f, "That domain already exists: {:?}", d),
+            DomainNotEmpty(ref d) => write!(f, "Domain {:?} still has files or classes, unable to delete", d),
+            DomainNotFound(ref d) => write!(f, "Domain not found: {:?}", d),
+
+            ClassExists(ref c) => write!(f, "That class already exists in that domain: {:?}", c),
+            ClassHasFiles(ref c) => write!(f, "Class {:?} still has files, unable to delete", c),
+            ClassNotFound(ref c) => write!(f, "Class not found: {:?}", c),
 
             UnknownKey(ref d) => write!(f, "Unknown key: {:?}", d),
             KeyExists(ref d) => write!(f, "Target key name {:?} already exists, can't overwrite.", d),
 
+            HostExists(ref h) => write!(f, "That host already exists: {:?}", h),
+            HostNotEmpty(ref h) => write!(f, "Unable to delete host {:?}; it contains devices still", h),
+            UnknownHost(ref h) => write!(f, "Host not found: {:?}", h),
+            DeviceExists(ref d) => write!(f, "That device already exists: {:?}", d),
+            UnknownDevice(ref d) => write!(f, "Device not found: {:?}", d),
+            UnknownFid(ref fid) => write!(f, "No such file ID: {}", fid),
+            UnknownState(ref s) => write!(f, "Invalid / unknown state: {:?}", s),
+
             UnknownCommand(ref d) => write!(f, "Unknown command: {:?}", d),
             NoContent(ref d) => write!(f, "No content for key: {:?}", d),
 
             Other(ref op, ref desc) => write!(f, "{} {}", op, desc.clone().unwrap_or_default()),
             UnknownCode(ref c) => write!(f, "Unknown code: {:?}", c),
             StorageError(ref os) => write!(f, "Storage error: {:?}", os),
+            Timeout(ref t) => write!(f, "Timed out talking to {}", t),
 
             _ => write!(f, "{}", self.description()),
         }
@@ -183,7 +252,16 @@ impl Error for MogError {
     fn description(&self) -> &str {
         use self::MogError::*;
         match *self {
+            ClassExists(..) => "That class already exists in that domain",
+            ClassHasFiles(..) => "Class still has files, unable to delete",
+            ClassNotFound(..) => "Class not found",
             DomainExists(..) => "Domain already exists",
+            DomainNotEmpty(..) => "Domain still has files or classes, unable to delete",
+            DomainNotFound(..) => "Domain not found",
+            DeviceExists(..) => "That device already exists",
+            HostExists(..) => "That host already exists",
+            HostMismatch => "The device specified doesn't belong to the host specified",
+            HostNotEmpty(..) => "Unable to delete host; it contains devices still",
             Io(ref io_err) => io_err.description(),
             KeyExists(..) => "Key already exists",
             InvalidMindevcount => "The mindevcount must be at least 1",
@@ -193,20 +271,29 @@ impl Error for MogError {
             NoDevid => "No device ID provided",
             NoDomain => "No domain provided",
             NoFid => "No file ID provided",
+            NoHost => "No host provided",
+            NoIp => "IP required to create host",
             NoKey => "No key provided",
             NoPath => "No path provided",
+            NoPort => "Port required to create host",
             NoTrackers => "No trackers provided",
             Other(..) => "Other error",
             PoisonedMutex => "Poisoned mutex",
             RecvError => "Error receiving response",
             SendError => "Error sending request",
+            StateTooHigh => "Status cannot go from dead to alive; must use down",
             UnknownCode(..) => "Unknown response code",
             UnknownCommand(..) => "Unknown command",
+            UnknownDevice(..) => "Device not found",
+            UnknownFid(..) => "No such file ID",
+            UnknownHost(..) => "Host not found",
             UnknownKey(..) => "Unknown key",
+            UnknownState(..) => "Invalid / unknown state",
             UnregDomain(..) => "Domain name invalid / not found",
             UnregClass(..) => "Class name invalid / not found",
             BadResponse => "Wrong response type",
             StorageError(..) => "Storage error",
+            Timeout(..) => "Timed out",
             Utf8(ref utf8_err) => utf8_err.description(),
         }
     }
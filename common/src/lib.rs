@@ -9,6 +9,7 @@ extern crate log;
 #[macro_use]
 extern crate matches;
 
+pub use args_hash::ArgsHash;
 pub use backend::{Backend, BackendStack, AroundMiddleware};
 pub use error::{MogError, MogResult};
 pub use request::{Request, Response, ToResponse, Renderable};
@@ -21,12 +22,24 @@ pub mod requests {
     pub use request::{CreateOpen, CreateOpenResponse};
     pub use request::CreateClose;
     pub use request::{CreateClass, CreateClassResponse};
+    pub use request::DeleteDomain;
+    pub use request::DeleteClass;
+    pub use request::{GetDomains, GetDomainsResponse, DomainInfo, ClassInfo};
     pub use request::{GetPaths, GetPathsResponse};
     pub use request::{FileInfo, FileInfoResponse};
     pub use request::Rename;
     pub use request::UpdateClass;
     pub use request::Delete;
     pub use request::{ListKeys, ListKeysResponse};
+    pub use request::{GetHosts, GetHostsResponse, HostInfo};
+    pub use request::{GetDevices, GetDevicesResponse, DeviceInfo};
+    pub use request::{CreateHost, UpdateHost, CreateHostResponse};
+    pub use request::DeleteHost;
+    pub use request::CreateDevice;
+    pub use request::SetState;
+    pub use request::SetWeight;
+    pub use request::{ListFids, ListFidsResponse, FidInfo};
+    pub use request::{FileDebug, FileDebugResponse};
     pub use request::Noop;
 }
 
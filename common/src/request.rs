@@ -48,12 +48,25 @@ impl FromBytes for Box<Request> {
             Some(Ok("create_open"))   => CreateOpen::from_bytes(args).map(|r| Box::new(r) as Box<Request>),
             Some(Ok("create_close"))  => CreateClose::from_bytes(args).map(|r| Box::new(r) as Box<Request>),
             Some(Ok("create_class"))  => CreateClass::from_bytes(args).map(|r| Box::new(r) as Box<Request>),
+            Some(Ok("delete_domain")) => DeleteDomain::from_bytes(args).map(|r| Box::new(r) as Box<Request>),
+            Some(Ok("delete_class"))  => DeleteClass::from_bytes(args).map(|r| Box::new(r) as Box<Request>),
+            Some(Ok("get_domains"))   => GetDomains::from_bytes(args).map(|r| Box::new(r) as Box<Request>),
             Some(Ok("file_info"))     => FileInfo::from_bytes(args).map(|r| Box::new(r) as Box<Request>),
             Some(Ok("get_paths"))     => GetPaths::from_bytes(args).map(|r| Box::new(r) as Box<Request>),
             Some(Ok("rename"))        => Rename::from_bytes(args).map(|r| Box::new(r) as Box<Request>),
             Some(Ok("updateclass"))   => UpdateClass::from_bytes(args).map(|r| Box::new(r) as Box<Request>),
             Some(Ok("delete"))        => Delete::from_bytes(args).map(|r| Box::new(r) as Box<Request>),
             Some(Ok("list_keys"))     => ListKeys::from_bytes(args).map(|r| Box::new(r) as Box<Request>),
+            Some(Ok("list_fids"))     => ListFids::from_bytes(args).map(|r| Box::new(r) as Box<Request>),
+            Some(Ok("file_debug"))    => FileDebug::from_bytes(args).map(|r| Box::new(r) as Box<Request>),
+            Some(Ok("get_hosts"))     => GetHosts::from_bytes(args).map(|r| Box::new(r) as Box<Request>),
+            Some(Ok("get_devices"))   => GetDevices::from_bytes(args).map(|r| Box::new(r) as Box<Request>),
+            Some(Ok("create_host"))   => CreateHost::from_bytes(args).map(|r| Box::new(r) as Box<Request>),
+            Some(Ok("update_host"))   => UpdateHost::from_bytes(args).map(|r| Box::new(r) as Box<Request>),
+            Some(Ok("delete_host"))   => DeleteHost::from_bytes(args).map(|r| Box::new(r) as Box<Request>),
+            Some(Ok("create_device")) => CreateDevice::from_bytes(args).map(|r| Box::new(r) as Box<Request>),
+            Some(Ok("set_state"))     => SetState::from_bytes(args).map(|r| Box::new(r) as Box<Request>),
+            Some(Ok("set_weight"))    => SetWeight::from_bytes(args).map(|r| Box::new(r) as Box<Request>),
             Some(Ok("noop"))          => Noop::from_bytes(args).map(|r| Box::new(r) as Box<Request>),
 
             Some(Ok(""))     => Err(MogError::UnknownCommand(None)),
@@ -71,9 +84,17 @@ pub enum Response {
     CreateDomain(CreateDomain),
     CreateOpen(CreateOpenResponse),
     CreateClass(CreateClassResponse),
+    DeleteDomain(DeleteDomain),
+    DeleteClass(DeleteClass),
+    GetDomains(GetDomainsResponse),
     FileInfo(FileInfoResponse),
     GetPaths(GetPathsResponse),
     ListKeys(ListKeysResponse),
+    GetHosts(GetHostsResponse),
+    GetDevices(GetDevicesResponse),
+    CreateHost(CreateHostResponse),
+    ListFids(ListFidsResponse),
+    FileDebug(FileDebugResponse),
 }
 
 impl Response {
@@ -85,9 +106,17 @@ impl Response {
             CreateDomain(r) => downcast(r),
             CreateOpen(r)   => downcast(r),
             CreateClass(r)  => downcast(r),
+            DeleteDomain(r) => downcast(r),
+            DeleteClass(r)  => downcast(r),
+            GetDomains(r)   => downcast(r),
             FileInfo(r)     => downcast(r),
             GetPaths(r)     => downcast(r),
             ListKeys(r)     => downcast(r),
+            GetHosts(r)     => downcast(r),
+            GetDevices(r)   => downcast(r),
+            CreateHost(r)   => downcast(r),
+            ListFids(r)     => downcast(r),
+            FileDebug(r)    => downcast(r),
         }
     }
 }
@@ -100,9 +129,17 @@ impl ToArgs for Response {
             &CreateDomain(ref r) => r.to_args(),
             &CreateOpen(ref r)   => r.to_args(),
             &CreateClass(ref r)  => r.to_args(),
+            &DeleteDomain(ref r) => r.to_args(),
+            &DeleteClass(ref r)  => r.to_args(),
+            &GetDomains(ref r)   => r.to_args(),
             &FileInfo(ref r)     => r.to_args(),
             &GetPaths(ref r)     => r.to_args(),
             &ListKeys(ref r)     => r.to_args(),
+            &GetHosts(ref r)     => r.to_args(),
+            &GetDevices(ref r)   => r.to_args(),
+            &CreateHost(ref r)   => r.to_args(),
+            &ListFids(ref r)     => r.to_args(),
+            &FileDebug(ref r)    => r.to_args(),
         }
     }
 }
@@ -201,6 +238,56 @@ impl ToArgs for CreateDomain {
     }
 }
 
+/// A `delete_domain` request.
+///
+/// Serves as its own response type. Looks like this:
+///
+/// ```text
+/// request = "delete_domain domain=test_domain_2\r\n"
+/// response = "OK domain=test_domain_2\r\n"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeleteDomain {
+    pub domain: String,
+}
+
+impl Request for DeleteDomain {
+    fn op(&self) -> &'static str { "delete_domain" }
+
+    fn response_from_bytes(&self, bytes: &[u8]) -> MogResult<Response> {
+        DeleteDomain::from_bytes(bytes).map(|r| r.to_response())
+    }
+
+    fn perform(&self, backend: &Backend) -> MogResult<Response> {
+        backend.delete_domain(self).map(|r| r.to_response())
+    }
+}
+
+impl ToResponse for DeleteDomain {
+    fn to_response(self) -> Response {
+        Response::DeleteDomain(self)
+    }
+}
+
+impl FromBytes for DeleteDomain {
+    fn from_bytes(bytes: &[u8]) -> MogResult<DeleteDomain> {
+        let mut args = ArgsHash::from_bytes(bytes);
+        let domain = try!(args.extract_domain());
+
+        Ok(DeleteDomain {
+            domain: domain,
+        })
+    }
+}
+
+impl ToArgs for DeleteDomain {
+    fn to_args(&self) -> Vec<(String, String)> {
+        vec!{
+            ("domain".to_string(), self.domain.clone()),
+        }
+    }
+}
+
 /// A `create_open` request.
 ///
 /// Looks like this:
@@ -519,6 +606,187 @@ impl ToArgs for CreateClassResponse {
 }
 
 
+/// A `delete_class` request.
+///
+/// Serves as its own response type. Looks like this:
+///
+/// ```text
+/// request = "delete_class domain=rn_development_public&class=filament\r\n"
+/// response = "OK domain=rn_development_public&class=filament\r\n"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeleteClass {
+    pub domain: String,
+    pub class: String,
+}
+
+impl Request for DeleteClass {
+    fn op(&self) -> &'static str { "delete_class" }
+
+    fn response_from_bytes(&self, bytes: &[u8]) -> MogResult<Response> {
+        DeleteClass::from_bytes(bytes).map(|r| r.to_response())
+    }
+
+    fn perform(&self, backend: &Backend) -> MogResult<Response> {
+        backend.delete_class(self).map(|r| r.to_response())
+    }
+}
+
+impl ToResponse for DeleteClass {
+    fn to_response(self) -> Response {
+        Response::DeleteClass(self)
+    }
+}
+
+impl FromBytes for DeleteClass {
+    fn from_bytes(bytes: &[u8]) -> MogResult<DeleteClass> {
+        let mut args = ArgsHash::from_bytes(bytes);
+        let domain = try!(args.extract_domain());
+        let class = try!(args.extract_required_string("class", MogError::NoClass));
+
+        Ok(DeleteClass {
+            domain: domain,
+            class: class,
+        })
+    }
+}
+
+impl ToArgs for DeleteClass {
+    fn to_args(&self) -> Vec<(String, String)> {
+        vec!{
+            ("domain".to_string(), self.domain.clone()),
+            ("class".to_string(), self.class.clone()),
+        }
+    }
+}
+
+/// A `get_domains` request.
+///
+/// Looks like this:
+///
+/// ```text
+/// request = "get_domains \r\n"
+/// response = "OK domains=1&domain1=test_domain_2&domain1classes=1&domain1class1name=default&domain1class1mindevcount=2&domain1class1replpolicy=MultipleHosts%28%29&domain1class1hashtype=NONE\r\n"
+/// ```
+#[derive(Debug, Clone)]
+pub struct GetDomains;
+
+impl Request for GetDomains {
+    fn op(&self) -> &'static str { "get_domains" }
+
+    fn response_from_bytes(&self, bytes: &[u8]) -> MogResult<Response> {
+        GetDomainsResponse::from_bytes(bytes).map(|r| r.to_response())
+    }
+
+    fn perform(&self, backend: &Backend) -> MogResult<Response> {
+        backend.get_domains(self).map(|r| r.to_response())
+    }
+}
+
+impl FromBytes for GetDomains {
+    fn from_bytes(_bytes: &[u8]) -> MogResult<GetDomains> {
+        Ok(GetDomains)
+    }
+}
+
+impl ToArgs for GetDomains {
+    fn to_args(&self) -> Vec<(String, String)> {
+        vec![]
+    }
+}
+
+/// The response to a `get_domains` request.
+///
+/// Looks like this:
+///
+/// ```text
+/// request = "get_domains \r\n"
+/// response = "OK domains=1&domain1=test_domain_2&domain1classes=1&domain1class1name=default&domain1class1mindevcount=2&domain1class1replpolicy=MultipleHosts%28%29&domain1class1hashtype=NONE\r\n"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetDomainsResponse(pub Vec<DomainInfo>);
+
+/// A single domain in a `get_domains` response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DomainInfo {
+    pub name: String,
+    pub classes: Vec<ClassInfo>,
+}
+
+/// A single class of a domain in a `get_domains` response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClassInfo {
+    pub name: String,
+    pub mindevcount: u64,
+    pub replpolicy: Option<String>,
+    pub hashtype: Option<String>,
+}
+
+impl ToResponse for GetDomainsResponse {
+    fn to_response(self) -> Response {
+        Response::GetDomains(self)
+    }
+}
+
+impl FromBytes for GetDomainsResponse {
+    fn from_bytes(bytes: &[u8]) -> MogResult<GetDomainsResponse> {
+        let mut args = ArgsHash::from_bytes(bytes);
+        let domain_count = try!(args.extract_required_int("domains", MogError::Other("No domain count".to_string(), None)));
+        let mut response = GetDomainsResponse(Vec::new());
+
+        for i in 1..(domain_count + 1) {
+            let name = try!(args.extract_required_string(&format!("domain{}", i), MogError::NoDomain));
+            let class_count = args.extract_optional_int(&format!("domain{}classes", i)).unwrap_or(0);
+            let mut classes = Vec::new();
+
+            for j in 1..(class_count + 1) {
+                let prefix = format!("domain{}class{}", i, j);
+                let hashtype = args.extract_optional_string(&format!("{}hashtype", prefix));
+
+                classes.push(ClassInfo {
+                    name: try!(args.extract_required_string(&format!("{}name", prefix), MogError::NoClass)),
+                    mindevcount: try!(args.extract_required_int(&format!("{}mindevcount", prefix), MogError::InvalidMindevcount)),
+                    replpolicy: args.extract_optional_string(&format!("{}replpolicy", prefix)),
+                    hashtype: hashtype.and_then(|h| if h == "NONE" { None } else { Some(h) }),
+                });
+            }
+
+            response.0.push(DomainInfo {
+                name: name,
+                classes: classes,
+            });
+        }
+
+        Ok(response)
+    }
+}
+
+impl ToArgs for GetDomainsResponse {
+    fn to_args(&self) -> Vec<(String, String)> {
+        let mut args = vec!{
+            ("domains".to_string(), self.0.len().to_string()),
+        };
+
+        for (i, domain) in self.0.iter().enumerate() {
+            args.push((format!("domain{}", i + 1), domain.name.clone()));
+            args.push((format!("domain{}classes", i + 1), domain.classes.len().to_string()));
+
+            for (j, class) in domain.classes.iter().enumerate() {
+                let prefix = format!("domain{}class{}", i + 1, j + 1);
+                args.push((format!("{}name", prefix), class.name.clone()));
+                args.push((format!("{}mindevcount", prefix), class.mindevcount.to_string()));
+                args.push((format!("{}hashtype", prefix), class.hashtype.clone().unwrap_or("NONE".to_string())));
+
+                if let Some(ref replpolicy) = class.replpolicy {
+                    args.push((format!("{}replpolicy", prefix), replpolicy.clone()));
+                }
+            }
+        }
+
+        args
+    }
+}
+
 /// A `get_paths` request.
 ///
 /// Looks like this:
@@ -633,7 +901,7 @@ impl ToArgs for GetPathsResponse {
 ///
 /// ```text
 /// request = "file_info domain=test_domain_2&key=test/key/1\r\n"
-/// response = "OK fid=1927&devcount=1&length=4&domain=test_domain_2&class=default&key=test/key/1\r\n"
+/// response = "OK fid=1927&devcount=1&length=4&domain=test_domain_2&class=default&key=test/key/1&checksum=MD5:e2fc714c4727ee9395f324cd2e7f331f\r\n"
 /// ```
 #[derive(Debug, Clone)]
 pub struct FileInfo {
@@ -681,7 +949,7 @@ impl ToArgs for FileInfo {
 ///
 /// ```text
 /// request = "file_info domain=test_domain_2&key=test/key/1\r\n"
-/// response = "OK fid=1927&devcount=1&length=4&domain=test_domain_2&class=default&key=test/key/1\r\n"
+/// response = "OK fid=1927&devcount=1&length=4&domain=test_domain_2&class=default&key=test/key/1&checksum=MD5:e2fc714c4727ee9395f324cd2e7f331f\r\n"
 /// ```
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FileInfoResponse {
@@ -691,6 +959,8 @@ pub struct FileInfoResponse {
     pub domain: String,
     pub class: String,
     pub key: String,
+    /// The file's checksum, like `MD5:<hex>`, if its class keeps one.
+    pub checksum: Option<String>,
 }
 
 // impl Response for FileInfoResponse {}
@@ -712,6 +982,7 @@ impl FromBytes for FileInfoResponse {
             domain: try!(args.extract_required_string("domain", MogError::NoDomain)),
             class: try!(args.extract_required_string("class", MogError::NoClass)),
             key: try!(args.extract_required_string("key", MogError::NoKey)),
+            checksum: args.extract_optional_string("checksum").and_then(|c| if c == "NONE" { None } else { Some(c) }),
         })
     }
 }
@@ -725,178 +996,428 @@ impl ToArgs for FileInfoResponse {
             ("fid".to_string(), self.fid.to_string()),
             ("devcount".to_string(), self.devcount.to_string()),
             ("length".to_string(), self.length.to_string()),
+            ("checksum".to_string(), self.checksum.clone().unwrap_or("NONE".to_string())),
         }
     }
 }
 
-/// A `rename` request.
+/// A `list_fids` request.
 ///
 /// Looks like this:
 ///
 /// ```text
-/// request = "rename domain=test_domain_2&from_key=test/key/1&to_key=test/key/2\r\n"
-/// response = "OK \r\n"
+/// request = "list_fids from=1900&to=2000\r\n"
+/// response = "OK fid_count=1&fid_1_fid=1927&fid_1_domain=test_domain_2&fid_1_key=test/key/1&fid_1_class=default&fid_1_length=4&fid_1_devcount=1\r\n"
 /// ```
 #[derive(Debug, Clone)]
-pub struct Rename {
-    pub domain: String,
-    pub from_key: String,
-    pub to_key: String,
+pub struct ListFids {
+    pub from: u64,
+    pub to: u64,
 }
 
-impl Request for Rename {
-    fn op(&self) -> &'static str { "rename" }
+impl Request for ListFids {
+    fn op(&self) -> &'static str { "list_fids" }
 
-    fn response_from_bytes(&self, _bytes: &[u8]) -> MogResult<Response> {
-        Ok(Response::Empty)
+    fn response_from_bytes(&self, bytes: &[u8]) -> MogResult<Response> {
+        ListFidsResponse::from_bytes(bytes).map(|r| r.to_response())
     }
 
     fn perform(&self, backend: &Backend) -> MogResult<Response> {
-        backend.rename(self).map(|r| r.to_response())
+        backend.list_fids(self).map(|r| r.to_response())
     }
 }
 
-impl FromBytes for Rename {
-    fn from_bytes(bytes: &[u8]) -> MogResult<Rename> {
+impl FromBytes for ListFids {
+    fn from_bytes(bytes: &[u8]) -> MogResult<ListFids> {
         let mut args = ArgsHash::from_bytes(bytes);
-        let domain = try!(args.extract_domain());
-        let from_key = try!(args.extract_required_string("from_key", MogError::NoKey));
-        let to_key = try!(args.extract_required_string("to_key", MogError::NoKey));
+        let from = args.extract_optional_int("from").unwrap_or(0);
+        let to = args.extract_optional_int("to").unwrap_or(from.saturating_add(100));
 
-        Ok(Rename {
-            domain: domain,
-            from_key: from_key,
-            to_key: to_key,
+        Ok(ListFids {
+            from: from,
+            to: to,
         })
     }
 }
 
-impl ToArgs for Rename {
+impl ToArgs for ListFids {
     fn to_args(&self) -> Vec<(String, String)> {
         vec!{
-            ("domain".to_string(), self.domain.clone()),
-            ("from_key".to_string(), self.from_key.clone()),
-            ("to_key".to_string(), self.to_key.clone()),
+            ("from".to_string(), self.from.to_string()),
+            ("to".to_string(), self.to.to_string()),
         }
     }
 }
 
-/// An `updateclass` request.
+/// The response to a `list_fids` request.
 ///
 /// Looks like this:
 ///
 /// ```text
-/// request = "updateclass domain=test_domain_2&key=test/key/2&class=new_class\r\n"
-/// response = "OK \r\n"
+/// request = "list_fids from=1900&to=2000\r\n"
+/// response = "OK fid_count=1&fid_1_fid=1927&fid_1_domain=test_domain_2&fid_1_key=test/key/1&fid_1_class=default&fid_1_length=4&fid_1_devcount=1\r\n"
 /// ```
-#[derive(Debug, Clone)]
-pub struct UpdateClass {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListFidsResponse(pub Vec<FidInfo>);
+
+/// A single file in a `list_fids` response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FidInfo {
+    pub fid: u64,
     pub domain: String,
     pub key: String,
-    pub new_class: String,
+    pub class: String,
+    pub length: u64,
+    pub devcount: u64,
 }
 
-impl Request for UpdateClass {
-    fn op(&self) -> &'static str { "updateclass" }
-
-    fn response_from_bytes(&self, _bytes: &[u8]) -> MogResult<Response> {
-        Ok(Response::Empty)
-    }
-
-    fn perform(&self, _backend: &Backend) -> MogResult<Response> {
-        // backend.update_class(self).map(|r| r.to_response())
-        Ok(Response::Empty)
+impl ToResponse for ListFidsResponse {
+    fn to_response(self) -> Response {
+        Response::ListFids(self)
     }
 }
 
-impl FromBytes for UpdateClass {
-    fn from_bytes(bytes: &[u8]) -> MogResult<UpdateClass> {
+impl FromBytes for ListFidsResponse {
+    fn from_bytes(bytes: &[u8]) -> MogResult<ListFidsResponse> {
         let mut args = ArgsHash::from_bytes(bytes);
-        let domain = try!(args.extract_domain());
-        let key = try!(args.extract_key());
-        let class = try!(args.extract_required_string("class", MogError::NoClass));
+        let fid_count = try!(args.extract_required_int("fid_count", MogError::Other("No fid count".to_string(), None)));
+        let mut response = ListFidsResponse(Vec::new());
+
+        for i in 1..(fid_count + 1) {
+            let prefix = format!("fid_{}_", i);
+
+            response.0.push(FidInfo {
+                fid: try!(args.extract_required_int(&format!("{}fid", prefix), MogError::NoFid)),
+                domain: try!(args.extract_required_string(&format!("{}domain", prefix), MogError::NoDomain)),
+                key: try!(args.extract_required_string(&format!("{}key", prefix), MogError::NoKey)),
+                class: try!(args.extract_required_string(&format!("{}class", prefix), MogError::NoClass)),
+                length: try!(args.extract_required_int(&format!("{}length", prefix), MogError::Other("No file size".to_string(), None))),
+                devcount: try!(args.extract_required_int(&format!("{}devcount", prefix), MogError::Other("No device count".to_string(), None))),
+            });
+        }
 
-        Ok(UpdateClass {
-            domain: domain,
-            key: key,
-            new_class: class,
-        })
+        Ok(response)
     }
 }
 
-impl ToArgs for UpdateClass {
+impl ToArgs for ListFidsResponse {
     fn to_args(&self) -> Vec<(String, String)> {
-        vec!{
-            ("domain".to_string(), self.domain.clone()),
-            ("key".to_string(), self.key.clone()),
-            ("class".to_string(), self.new_class.clone()),
+        let mut args = vec!{
+            ("fid_count".to_string(), self.0.len().to_string()),
+        };
+
+        for (i, fid) in self.0.iter().enumerate() {
+            let prefix = format!("fid_{}_", i + 1);
+            args.push((format!("{}fid", prefix), fid.fid.to_string()));
+            args.push((format!("{}domain", prefix), fid.domain.clone()));
+            args.push((format!("{}key", prefix), fid.key.clone()));
+            args.push((format!("{}class", prefix), fid.class.clone()));
+            args.push((format!("{}length", prefix), fid.length.to_string()));
+            args.push((format!("{}devcount", prefix), fid.devcount.to_string()));
         }
+
+        args
     }
 }
 
-/// A `delete` request.
+/// A `file_debug` request.
 ///
-/// Looks like this:
+/// The file may be given either by fid, or by domain and key. Looks
+/// like this:
 ///
 /// ```text
-/// request = "delete domain=test_domain_2&key=test/key/2\r\n"
-/// response = "OK \r\n"
+/// request = "file_debug fid=1927\r\n"
+/// response = "OK fid_fid=1927&fid_domain=test_domain_2&fid_dkey=test/key/1&fid_class=default&fid_length=4&fid_devcount=1&devids=1\r\n"
 /// ```
 #[derive(Debug, Clone)]
-pub struct Delete {
-    pub domain: String,
-    pub key: String,
+pub struct FileDebug {
+    pub fid: Option<u64>,
+    pub domain: Option<String>,
+    pub key: Option<String>,
 }
 
-impl Request for Delete {
-    fn op(&self) -> &'static str { "delete" }
+impl Request for FileDebug {
+    fn op(&self) -> &'static str { "file_debug" }
 
-    fn response_from_bytes(&self, _bytes: &[u8]) -> MogResult<Response> {
-        Ok(Response::Empty)
+    fn response_from_bytes(&self, bytes: &[u8]) -> MogResult<Response> {
+        FileDebugResponse::from_bytes(bytes).map(|r| r.to_response())
     }
 
     fn perform(&self, backend: &Backend) -> MogResult<Response> {
-        backend.delete(self).map(|r| r.to_response())
+        backend.file_debug(self).map(|r| r.to_response())
     }
 }
 
-impl FromBytes for Delete {
-    fn from_bytes(bytes: &[u8]) -> MogResult<Delete> {
+impl FromBytes for FileDebug {
+    fn from_bytes(bytes: &[u8]) -> MogResult<FileDebug> {
         let mut args = ArgsHash::from_bytes(bytes);
-        let domain = try!(args.extract_domain());
-        let key = try!(args.extract_key());
+        let fid = args.extract_optional_int("fid");
+        let domain = args.extract_optional_string("domain");
+        let key = args.extract_optional_string("key");
 
-        Ok(Delete {
+        if fid.is_none() && (domain.is_none() || key.is_none()) {
+            return Err(MogError::Other("bad_params".to_string(), Some("Need fid or domain and key".to_string())));
+        }
+
+        Ok(FileDebug {
+            fid: fid,
             domain: domain,
             key: key,
         })
     }
 }
 
-impl ToArgs for Delete {
+impl ToArgs for FileDebug {
     fn to_args(&self) -> Vec<(String, String)> {
-        vec!{
-            ("domain".to_string(), self.domain.clone()),
-            ("key".to_string(), self.key.clone()),
+        let mut rv = vec![];
+
+        if let Some(fid) = self.fid {
+            rv.push(("fid".to_string(), fid.to_string()));
+        }
+
+        if let Some(ref domain) = self.domain {
+            rv.push(("domain".to_string(), domain.clone()));
+        }
+
+        if let Some(ref key) = self.key {
+            rv.push(("key".to_string(), key.clone()));
         }
+
+        rv
     }
 }
 
-/// A `list_keys` request.
+/// The response to a `file_debug` request.
 ///
 /// Looks like this:
 ///
 /// ```text
-/// request = "list_keys domain=development_public&prefix=Photo&after=&limit=10\r\n"
-/// response = "OK key_4=Photo/120418/image/thumb&key_6=Photo/12285/image/thumb&key_5=Photo/12285/image&key_count=10&key_10=Photo/126010/image/thumb&key_7=Photo/126009/image&key_8=Photo/126009/image/thumb&key_1=Photo/1105/image&key_3=Photo/120418/image&next_after=Photo/126010/image/thumb&key_2=Photo/1105/image/thumb&key_9=Photo/126010/image\r\n"
+/// request = "file_debug fid=1927\r\n"
+/// response = "OK fid_fid=1927&fid_domain=test_domain_2&fid_dkey=test/key/1&fid_class=default&fid_length=4&fid_devcount=1&devids=1\r\n"
 /// ```
-#[derive(Debug, Clone)]
-pub struct ListKeys {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileDebugResponse {
+    pub fid: u64,
     pub domain: String,
-    pub prefix: Option<String>,
-    pub after: Option<String>,
-    pub limit: Option<u64>,
-}
+    pub key: String,
+    pub class: String,
+    pub length: u64,
+    pub devcount: u64,
+    pub devids: Vec<u64>,
+}
+
+impl ToResponse for FileDebugResponse {
+    fn to_response(self) -> Response {
+        Response::FileDebug(self)
+    }
+}
+
+impl FromBytes for FileDebugResponse {
+    fn from_bytes(bytes: &[u8]) -> MogResult<FileDebugResponse> {
+        let mut args = ArgsHash::from_bytes(bytes);
+        let devids = match args.extract_optional_string("devids") {
+            Some(ref devids) => {
+                let parsed: Result<Vec<u64>, _> = devids.split(',').filter(|d| !d.is_empty()).map(|d| d.parse()).collect();
+                try!(parsed.map_err(|_| MogError::NoDevid))
+            },
+            None => vec![],
+        };
+
+        Ok(FileDebugResponse {
+            fid: try!(args.extract_required_int("fid_fid", MogError::NoFid)),
+            domain: try!(args.extract_required_string("fid_domain", MogError::NoDomain)),
+            key: try!(args.extract_required_string("fid_dkey", MogError::NoKey)),
+            class: try!(args.extract_required_string("fid_class", MogError::NoClass)),
+            length: try!(args.extract_required_int("fid_length", MogError::Other("No file size".to_string(), None))),
+            devcount: try!(args.extract_required_int("fid_devcount", MogError::Other("No device count".to_string(), None))),
+            devids: devids,
+        })
+    }
+}
+
+impl ToArgs for FileDebugResponse {
+    fn to_args(&self) -> Vec<(String, String)> {
+        let devids: Vec<String> = self.devids.iter().map(|d| d.to_string()).collect();
+
+        vec!{
+            ("fid_fid".to_string(), self.fid.to_string()),
+            ("fid_domain".to_string(), self.domain.clone()),
+            ("fid_dkey".to_string(), self.key.clone()),
+            ("fid_class".to_string(), self.class.clone()),
+            ("fid_length".to_string(), self.length.to_string()),
+            ("fid_devcount".to_string(), self.devcount.to_string()),
+            ("devids".to_string(), devids.join(",")),
+        }
+    }
+}
+
+/// A `rename` request.
+///
+/// Looks like this:
+///
+/// ```text
+/// request = "rename domain=test_domain_2&from_key=test/key/1&to_key=test/key/2\r\n"
+/// response = "OK \r\n"
+/// ```
+#[derive(Debug, Clone)]
+pub struct Rename {
+    pub domain: String,
+    pub from_key: String,
+    pub to_key: String,
+}
+
+impl Request for Rename {
+    fn op(&self) -> &'static str { "rename" }
+
+    fn response_from_bytes(&self, _bytes: &[u8]) -> MogResult<Response> {
+        Ok(Response::Empty)
+    }
+
+    fn perform(&self, backend: &Backend) -> MogResult<Response> {
+        backend.rename(self).map(|r| r.to_response())
+    }
+}
+
+impl FromBytes for Rename {
+    fn from_bytes(bytes: &[u8]) -> MogResult<Rename> {
+        let mut args = ArgsHash::from_bytes(bytes);
+        let domain = try!(args.extract_domain());
+        let from_key = try!(args.extract_required_string("from_key", MogError::NoKey));
+        let to_key = try!(args.extract_required_string("to_key", MogError::NoKey));
+
+        Ok(Rename {
+            domain: domain,
+            from_key: from_key,
+            to_key: to_key,
+        })
+    }
+}
+
+impl ToArgs for Rename {
+    fn to_args(&self) -> Vec<(String, String)> {
+        vec!{
+            ("domain".to_string(), self.domain.clone()),
+            ("from_key".to_string(), self.from_key.clone()),
+            ("to_key".to_string(), self.to_key.clone()),
+        }
+    }
+}
+
+/// An `updateclass` request.
+///
+/// Looks like this:
+///
+/// ```text
+/// request = "updateclass domain=test_domain_2&key=test/key/2&class=new_class\r\n"
+/// response = "OK \r\n"
+/// ```
+#[derive(Debug, Clone)]
+pub struct UpdateClass {
+    pub domain: String,
+    pub key: String,
+    pub new_class: String,
+}
+
+impl Request for UpdateClass {
+    fn op(&self) -> &'static str { "updateclass" }
+
+    fn response_from_bytes(&self, _bytes: &[u8]) -> MogResult<Response> {
+        Ok(Response::Empty)
+    }
+
+    fn perform(&self, backend: &Backend) -> MogResult<Response> {
+        backend.update_class(self).map(|r| r.to_response())
+    }
+}
+
+impl FromBytes for UpdateClass {
+    fn from_bytes(bytes: &[u8]) -> MogResult<UpdateClass> {
+        let mut args = ArgsHash::from_bytes(bytes);
+        let domain = try!(args.extract_domain());
+        let key = try!(args.extract_key());
+        let class = try!(args.extract_required_string("class", MogError::NoClass));
+
+        Ok(UpdateClass {
+            domain: domain,
+            key: key,
+            new_class: class,
+        })
+    }
+}
+
+impl ToArgs for UpdateClass {
+    fn to_args(&self) -> Vec<(String, String)> {
+        vec!{
+            ("domain".to_string(), self.domain.clone()),
+            ("key".to_string(), self.key.clone()),
+            ("class".to_string(), self.new_class.clone()),
+        }
+    }
+}
+
+/// A `delete` request.
+///
+/// Looks like this:
+///
+/// ```text
+/// request = "delete domain=test_domain_2&key=test/key/2\r\n"
+/// response = "OK \r\n"
+/// ```
+#[derive(Debug, Clone)]
+pub struct Delete {
+    pub domain: String,
+    pub key: String,
+}
+
+impl Request for Delete {
+    fn op(&self) -> &'static str { "delete" }
+
+    fn response_from_bytes(&self, _bytes: &[u8]) -> MogResult<Response> {
+        Ok(Response::Empty)
+    }
+
+    fn perform(&self, backend: &Backend) -> MogResult<Response> {
+        backend.delete(self).map(|r| r.to_response())
+    }
+}
+
+impl FromBytes for Delete {
+    fn from_bytes(bytes: &[u8]) -> MogResult<Delete> {
+        let mut args = ArgsHash::from_bytes(bytes);
+        let domain = try!(args.extract_domain());
+        let key = try!(args.extract_key());
+
+        Ok(Delete {
+            domain: domain,
+            key: key,
+        })
+    }
+}
+
+impl ToArgs for Delete {
+    fn to_args(&self) -> Vec<(String, String)> {
+        vec!{
+            ("domain".to_string(), self.domain.clone()),
+            ("key".to_string(), self.key.clone()),
+        }
+    }
+}
+
+/// A `list_keys` request.
+///
+/// Looks like this:
+///
+/// ```text
+/// request = "list_keys domain=development_public&prefix=Photo&after=&limit=10\r\n"
+/// response = "OK key_4=Photo/120418/image/thumb&key_6=Photo/12285/image/thumb&key_5=Photo/12285/image&key_count=10&key_10=Photo/126010/image/thumb&key_7=Photo/126009/image&key_8=Photo/126009/image/thumb&key_1=Photo/1105/image&key_3=Photo/120418/image&next_after=Photo/126010/image/thumb&key_2=Photo/1105/image/thumb&key_9=Photo/126010/image\r\n"
+/// ```
+#[derive(Debug, Clone)]
+pub struct ListKeys {
+    pub domain: String,
+    pub prefix: Option<String>,
+    pub after: Option<String>,
+    pub limit: Option<u64>,
+}
 
 impl Request for ListKeys {
     fn op(&self) -> &'static str { "list_keys" }
@@ -999,6 +1520,677 @@ impl ToArgs for ListKeysResponse {
     }
 }
 
+/// A `get_hosts` request.
+///
+/// Looks like this:
+///
+/// ```text
+/// request = "get_hosts \r\n"
+/// response = "OK hosts=1&host1_hostid=1&host1_status=alive&host1_http_port=7500&host1_hostname=storage1&host1_hostip=10.0.0.1\r\n"
+/// ```
+#[derive(Debug, Clone)]
+pub struct GetHosts {
+    pub hostid: Option<u64>,
+}
+
+impl Request for GetHosts {
+    fn op(&self) -> &'static str { "get_hosts" }
+
+    fn response_from_bytes(&self, bytes: &[u8]) -> MogResult<Response> {
+        GetHostsResponse::from_bytes(bytes).map(|r| r.to_response())
+    }
+
+    fn perform(&self, backend: &Backend) -> MogResult<Response> {
+        backend.get_hosts(self).map(|r| r.to_response())
+    }
+}
+
+impl FromBytes for GetHosts {
+    fn from_bytes(bytes: &[u8]) -> MogResult<GetHosts> {
+        let mut args = ArgsHash::from_bytes(bytes);
+
+        Ok(GetHosts {
+            hostid: args.extract_optional_int("hostid"),
+        })
+    }
+}
+
+impl ToArgs for GetHosts {
+    fn to_args(&self) -> Vec<(String, String)> {
+        let mut rv = vec![];
+
+        if let Some(hostid) = self.hostid {
+            rv.push(("hostid".to_string(), hostid.to_string()));
+        }
+
+        rv
+    }
+}
+
+/// The response to a `get_hosts` request.
+///
+/// Looks like this:
+///
+/// ```text
+/// request = "get_hosts \r\n"
+/// response = "OK hosts=1&host1_hostid=1&host1_status=alive&host1_http_port=7500&host1_hostname=storage1&host1_hostip=10.0.0.1\r\n"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetHostsResponse(pub Vec<HostInfo>);
+
+/// A single host in a `get_hosts` response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostInfo {
+    pub hostid: u64,
+    pub hostname: String,
+    pub hostip: String,
+    pub http_port: u64,
+    pub http_get_port: Option<u64>,
+    pub altip: Option<String>,
+    pub altmask: Option<String>,
+    pub status: String,
+}
+
+impl ToResponse for GetHostsResponse {
+    fn to_response(self) -> Response {
+        Response::GetHosts(self)
+    }
+}
+
+impl FromBytes for GetHostsResponse {
+    fn from_bytes(bytes: &[u8]) -> MogResult<GetHostsResponse> {
+        let mut args = ArgsHash::from_bytes(bytes);
+        let host_count = try!(args.extract_required_int("hosts", MogError::Other("No host count".to_string(), None)));
+        let mut response = GetHostsResponse(Vec::new());
+
+        for i in 1..(host_count + 1) {
+            let prefix = format!("host{}_", i);
+
+            response.0.push(HostInfo {
+                hostid: try!(args.extract_required_int(&format!("{}hostid", prefix), MogError::NoHost)),
+                hostname: try!(args.extract_required_string(&format!("{}hostname", prefix), MogError::NoHost)),
+                hostip: try!(args.extract_required_string(&format!("{}hostip", prefix), MogError::NoIp)),
+                http_port: try!(args.extract_required_int(&format!("{}http_port", prefix), MogError::NoPort)),
+                http_get_port: args.extract_optional_int(&format!("{}http_get_port", prefix)),
+                altip: args.extract_optional_string(&format!("{}altip", prefix)),
+                altmask: args.extract_optional_string(&format!("{}altmask", prefix)),
+                status: try!(args.extract_required_string(&format!("{}status", prefix), MogError::UnknownState(String::new()))),
+            });
+        }
+
+        Ok(response)
+    }
+}
+
+impl ToArgs for GetHostsResponse {
+    fn to_args(&self) -> Vec<(String, String)> {
+        let mut args = vec!{
+            ("hosts".to_string(), self.0.len().to_string()),
+        };
+
+        for (i, host) in self.0.iter().enumerate() {
+            let prefix = format!("host{}_", i + 1);
+            args.push((format!("{}hostid", prefix), host.hostid.to_string()));
+            args.push((format!("{}hostname", prefix), host.hostname.clone()));
+            args.push((format!("{}hostip", prefix), host.hostip.clone()));
+            args.push((format!("{}http_port", prefix), host.http_port.to_string()));
+            args.push((format!("{}status", prefix), host.status.clone()));
+
+            if let Some(http_get_port) = host.http_get_port {
+                args.push((format!("{}http_get_port", prefix), http_get_port.to_string()));
+            }
+
+            if let Some(ref altip) = host.altip {
+                args.push((format!("{}altip", prefix), altip.clone()));
+            }
+
+            if let Some(ref altmask) = host.altmask {
+                args.push((format!("{}altmask", prefix), altmask.clone()));
+            }
+        }
+
+        args
+    }
+}
+
+/// A `get_devices` request.
+///
+/// Looks like this:
+///
+/// ```text
+/// request = "get_devices \r\n"
+/// response = "OK devices=1&dev1_devid=1&dev1_hostid=1&dev1_status=alive&dev1_observed_state=writeable&dev1_weight=100&dev1_mb_total=1024&dev1_mb_used=12\r\n"
+/// ```
+#[derive(Debug, Clone)]
+pub struct GetDevices {
+    pub devid: Option<u64>,
+}
+
+impl Request for GetDevices {
+    fn op(&self) -> &'static str { "get_devices" }
+
+    fn response_from_bytes(&self, bytes: &[u8]) -> MogResult<Response> {
+        GetDevicesResponse::from_bytes(bytes).map(|r| r.to_response())
+    }
+
+    fn perform(&self, backend: &Backend) -> MogResult<Response> {
+        backend.get_devices(self).map(|r| r.to_response())
+    }
+}
+
+impl FromBytes for GetDevices {
+    fn from_bytes(bytes: &[u8]) -> MogResult<GetDevices> {
+        let mut args = ArgsHash::from_bytes(bytes);
+
+        Ok(GetDevices {
+            devid: args.extract_optional_int("devid"),
+        })
+    }
+}
+
+impl ToArgs for GetDevices {
+    fn to_args(&self) -> Vec<(String, String)> {
+        let mut rv = vec![];
+
+        if let Some(devid) = self.devid {
+            rv.push(("devid".to_string(), devid.to_string()));
+        }
+
+        rv
+    }
+}
+
+/// The response to a `get_devices` request.
+///
+/// Looks like this:
+///
+/// ```text
+/// request = "get_devices \r\n"
+/// response = "OK devices=1&dev1_devid=1&dev1_hostid=1&dev1_status=alive&dev1_observed_state=writeable&dev1_weight=100&dev1_mb_total=1024&dev1_mb_used=12\r\n"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetDevicesResponse(pub Vec<DeviceInfo>);
+
+/// A single device in a `get_devices` response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceInfo {
+    pub devid: u64,
+    pub hostid: u64,
+    pub status: String,
+    pub observed_state: Option<String>,
+    pub weight: Option<u64>,
+    pub mb_total: Option<u64>,
+    pub mb_used: Option<u64>,
+}
+
+impl ToResponse for GetDevicesResponse {
+    fn to_response(self) -> Response {
+        Response::GetDevices(self)
+    }
+}
+
+impl FromBytes for GetDevicesResponse {
+    fn from_bytes(bytes: &[u8]) -> MogResult<GetDevicesResponse> {
+        let mut args = ArgsHash::from_bytes(bytes);
+        let device_count = try!(args.extract_required_int("devices", MogError::Other("No device count".to_string(), None)));
+        let mut response = GetDevicesResponse(Vec::new());
+
+        for i in 1..(device_count + 1) {
+            let prefix = format!("dev{}_", i);
+
+            response.0.push(DeviceInfo {
+                devid: try!(args.extract_required_int(&format!("{}devid", prefix), MogError::NoDevid)),
+                hostid: try!(args.extract_required_int(&format!("{}hostid", prefix), MogError::NoHost)),
+                status: try!(args.extract_required_string(&format!("{}status", prefix), MogError::UnknownState(String::new()))),
+                observed_state: args.extract_optional_string(&format!("{}observed_state", prefix)),
+                weight: args.extract_optional_int(&format!("{}weight", prefix)),
+                mb_total: args.extract_optional_int(&format!("{}mb_total", prefix)),
+                mb_used: args.extract_optional_int(&format!("{}mb_used", prefix)),
+            });
+        }
+
+        Ok(response)
+    }
+}
+
+impl ToArgs for GetDevicesResponse {
+    fn to_args(&self) -> Vec<(String, String)> {
+        let mut args = vec!{
+            ("devices".to_string(), self.0.len().to_string()),
+        };
+
+        for (i, device) in self.0.iter().enumerate() {
+            let prefix = format!("dev{}_", i + 1);
+            args.push((format!("{}devid", prefix), device.devid.to_string()));
+            args.push((format!("{}hostid", prefix), device.hostid.to_string()));
+            args.push((format!("{}status", prefix), device.status.clone()));
+
+            if let Some(ref observed_state) = device.observed_state {
+                args.push((format!("{}observed_state", prefix), observed_state.clone()));
+            }
+
+            if let Some(weight) = device.weight {
+                args.push((format!("{}weight", prefix), weight.to_string()));
+            }
+
+            if let Some(mb_total) = device.mb_total {
+                args.push((format!("{}mb_total", prefix), mb_total.to_string()));
+            }
+
+            if let Some(mb_used) = device.mb_used {
+                args.push((format!("{}mb_used", prefix), mb_used.to_string()));
+            }
+        }
+
+        args
+    }
+}
+
+/// A `create_host` request.
+///
+/// Looks like this:
+///
+/// ```text
+/// request = "create_host host=storage2&ip=10.0.0.2&port=7500&status=alive\r\n"
+/// response = "OK hostid=2&hostname=storage2\r\n"
+/// ```
+#[derive(Debug, Clone)]
+pub struct CreateHost {
+    pub host: String,
+    pub ip: String,
+    pub port: u64,
+    pub getport: Option<u64>,
+    pub altip: Option<String>,
+    pub altmask: Option<String>,
+    pub status: Option<String>,
+}
+
+impl Request for CreateHost {
+    fn op(&self) -> &'static str { "create_host" }
+
+    fn response_from_bytes(&self, bytes: &[u8]) -> MogResult<Response> {
+        CreateHostResponse::from_bytes(bytes).map(|r| r.to_response())
+    }
+
+    fn perform(&self, backend: &Backend) -> MogResult<Response> {
+        backend.create_host(self).map(|r| r.to_response())
+    }
+}
+
+impl FromBytes for CreateHost {
+    fn from_bytes(bytes: &[u8]) -> MogResult<CreateHost> {
+        let mut args = ArgsHash::from_bytes(bytes);
+        let host = try!(args.extract_required_string("host", MogError::NoHost));
+        let ip = try!(args.extract_required_string("ip", MogError::NoIp));
+        let port = try!(args.extract_required_int("port", MogError::NoPort));
+
+        Ok(CreateHost {
+            host: host,
+            ip: ip,
+            port: port,
+            getport: args.extract_optional_int("getport"),
+            altip: args.extract_optional_string("altip"),
+            altmask: args.extract_optional_string("altmask"),
+            status: args.extract_optional_string("status"),
+        })
+    }
+}
+
+impl ToArgs for CreateHost {
+    fn to_args(&self) -> Vec<(String, String)> {
+        let mut rv = vec!{
+            ("host".to_string(), self.host.clone()),
+            ("ip".to_string(), self.ip.clone()),
+            ("port".to_string(), self.port.to_string()),
+        };
+
+        if let Some(getport) = self.getport {
+            rv.push(("getport".to_string(), getport.to_string()));
+        }
+
+        if let Some(ref altip) = self.altip {
+            rv.push(("altip".to_string(), altip.clone()));
+        }
+
+        if let Some(ref altmask) = self.altmask {
+            rv.push(("altmask".to_string(), altmask.clone()));
+        }
+
+        if let Some(ref status) = self.status {
+            rv.push(("status".to_string(), status.clone()));
+        }
+
+        rv
+    }
+}
+
+/// The response to a `create_host` or `update_host` request.
+///
+/// Looks like this:
+///
+/// ```text
+/// request = "create_host host=storage2&ip=10.0.0.2&port=7500&status=alive\r\n"
+/// response = "OK hostid=2&hostname=storage2\r\n"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreateHostResponse {
+    pub hostid: u64,
+    pub hostname: String,
+}
+
+impl ToResponse for CreateHostResponse {
+    fn to_response(self) -> Response {
+        Response::CreateHost(self)
+    }
+}
+
+impl FromBytes for CreateHostResponse {
+    fn from_bytes(bytes: &[u8]) -> MogResult<CreateHostResponse> {
+        let mut args = ArgsHash::from_bytes(bytes);
+
+        Ok(CreateHostResponse {
+            hostid: try!(args.extract_required_int("hostid", MogError::NoHost)),
+            hostname: try!(args.extract_required_string("hostname", MogError::NoHost)),
+        })
+    }
+}
+
+impl ToArgs for CreateHostResponse {
+    fn to_args(&self) -> Vec<(String, String)> {
+        vec!{
+            ("hostid".to_string(), self.hostid.to_string()),
+            ("hostname".to_string(), self.hostname.clone()),
+        }
+    }
+}
+
+/// An `update_host` request.
+///
+/// Only the fields which are present are changed. Looks like this:
+///
+/// ```text
+/// request = "update_host host=storage2&status=down\r\n"
+/// response = "OK hostid=2&hostname=storage2\r\n"
+/// ```
+#[derive(Debug, Clone)]
+pub struct UpdateHost {
+    pub host: String,
+    pub ip: Option<String>,
+    pub port: Option<u64>,
+    pub getport: Option<u64>,
+    pub altip: Option<String>,
+    pub altmask: Option<String>,
+    pub status: Option<String>,
+}
+
+impl Request for UpdateHost {
+    fn op(&self) -> &'static str { "update_host" }
+
+    fn response_from_bytes(&self, bytes: &[u8]) -> MogResult<Response> {
+        CreateHostResponse::from_bytes(bytes).map(|r| r.to_response())
+    }
+
+    fn perform(&self, backend: &Backend) -> MogResult<Response> {
+        backend.update_host(self).map(|r| r.to_response())
+    }
+}
+
+impl FromBytes for UpdateHost {
+    fn from_bytes(bytes: &[u8]) -> MogResult<UpdateHost> {
+        let mut args = ArgsHash::from_bytes(bytes);
+        let host = try!(args.extract_required_string("host", MogError::NoHost));
+
+        Ok(UpdateHost {
+            host: host,
+            ip: args.extract_optional_string("ip"),
+            port: args.extract_optional_int("port"),
+            getport: args.extract_optional_int("getport"),
+            altip: args.extract_optional_string("altip"),
+            altmask: args.extract_optional_string("altmask"),
+            status: args.extract_optional_string("status"),
+        })
+    }
+}
+
+impl ToArgs for UpdateHost {
+    fn to_args(&self) -> Vec<(String, String)> {
+        let mut rv = vec!{
+            ("host".to_string(), self.host.clone()),
+        };
+
+        if let Some(ref ip) = self.ip {
+            rv.push(("ip".to_string(), ip.clone()));
+        }
+
+        if let Some(port) = self.port {
+            rv.push(("port".to_string(), port.to_string()));
+        }
+
+        if let Some(getport) = self.getport {
+            rv.push(("getport".to_string(), getport.to_string()));
+        }
+
+        if let Some(ref altip) = self.altip {
+            rv.push(("altip".to_string(), altip.clone()));
+        }
+
+        if let Some(ref altmask) = self.altmask {
+            rv.push(("altmask".to_string(), altmask.clone()));
+        }
+
+        if let Some(ref status) = self.status {
+            rv.push(("status".to_string(), status.clone()));
+        }
+
+        rv
+    }
+}
+
+/// A `delete_host` request.
+///
+/// Looks like this:
+///
+/// ```text
+/// request = "delete_host host=storage2\r\n"
+/// response = "OK \r\n"
+/// ```
+#[derive(Debug, Clone)]
+pub struct DeleteHost {
+    pub host: String,
+}
+
+impl Request for DeleteHost {
+    fn op(&self) -> &'static str { "delete_host" }
+
+    fn response_from_bytes(&self, _bytes: &[u8]) -> MogResult<Response> {
+        Ok(Response::Empty)
+    }
+
+    fn perform(&self, backend: &Backend) -> MogResult<Response> {
+        backend.delete_host(self).map(|r| r.to_response())
+    }
+}
+
+impl FromBytes for DeleteHost {
+    fn from_bytes(bytes: &[u8]) -> MogResult<DeleteHost> {
+        let mut args = ArgsHash::from_bytes(bytes);
+        let host = try!(args.extract_required_string("host", MogError::NoHost));
+
+        Ok(DeleteHost {
+            host: host,
+        })
+    }
+}
+
+impl ToArgs for DeleteHost {
+    fn to_args(&self) -> Vec<(String, String)> {
+        vec!{
+            ("host".to_string(), self.host.clone()),
+        }
+    }
+}
+
+/// A `create_device` request.
+///
+/// Looks like this:
+///
+/// ```text
+/// request = "create_device hostname=storage2&devid=5&state=alive\r\n"
+/// response = "OK \r\n"
+/// ```
+#[derive(Debug, Clone)]
+pub struct CreateDevice {
+    pub hostname: String,
+    pub devid: u64,
+    pub state: Option<String>,
+}
+
+impl Request for CreateDevice {
+    fn op(&self) -> &'static str { "create_device" }
+
+    fn response_from_bytes(&self, _bytes: &[u8]) -> MogResult<Response> {
+        Ok(Response::Empty)
+    }
+
+    fn perform(&self, backend: &Backend) -> MogResult<Response> {
+        backend.create_device(self).map(|r| r.to_response())
+    }
+}
+
+impl FromBytes for CreateDevice {
+    fn from_bytes(bytes: &[u8]) -> MogResult<CreateDevice> {
+        let mut args = ArgsHash::from_bytes(bytes);
+        let hostname = try!(args.extract_required_string("hostname", MogError::NoHost));
+        let devid = try!(args.extract_required_int("devid", MogError::NoDevid));
+        let state = args.extract_optional_string("state");
+
+        Ok(CreateDevice {
+            hostname: hostname,
+            devid: devid,
+            state: state,
+        })
+    }
+}
+
+impl ToArgs for CreateDevice {
+    fn to_args(&self) -> Vec<(String, String)> {
+        let mut rv = vec!{
+            ("hostname".to_string(), self.hostname.clone()),
+            ("devid".to_string(), self.devid.to_string()),
+        };
+
+        if let Some(ref state) = self.state {
+            rv.push(("state".to_string(), state.clone()));
+        }
+
+        rv
+    }
+}
+
+/// A `set_state` request.
+///
+/// Looks like this:
+///
+/// ```text
+/// request = "set_state host=storage2&device=5&state=dead\r\n"
+/// response = "OK \r\n"
+/// ```
+#[derive(Debug, Clone)]
+pub struct SetState {
+    pub host: String,
+    pub device: u64,
+    pub state: String,
+}
+
+impl Request for SetState {
+    fn op(&self) -> &'static str { "set_state" }
+
+    fn response_from_bytes(&self, _bytes: &[u8]) -> MogResult<Response> {
+        Ok(Response::Empty)
+    }
+
+    fn perform(&self, backend: &Backend) -> MogResult<Response> {
+        backend.set_state(self).map(|r| r.to_response())
+    }
+}
+
+impl FromBytes for SetState {
+    fn from_bytes(bytes: &[u8]) -> MogResult<SetState> {
+        let mut args = ArgsHash::from_bytes(bytes);
+        let host = try!(args.extract_required_string("host", MogError::NoHost));
+        let device = try!(args.extract_required_int("device", MogError::NoDevid));
+        let state = try!(args.extract_required_string("state", MogError::UnknownState(String::new())));
+
+        Ok(SetState {
+            host: host,
+            device: device,
+            state: state,
+        })
+    }
+}
+
+impl ToArgs for SetState {
+    fn to_args(&self) -> Vec<(String, String)> {
+        vec!{
+            ("host".to_string(), self.host.clone()),
+            ("device".to_string(), self.device.to_string()),
+            ("state".to_string(), self.state.clone()),
+        }
+    }
+}
+
+/// A `set_weight` request.
+///
+/// Looks like this:
+///
+/// ```text
+/// request = "set_weight host=storage2&device=5&weight=50\r\n"
+/// response = "OK \r\n"
+/// ```
+#[derive(Debug, Clone)]
+pub struct SetWeight {
+    pub host: String,
+    pub device: u64,
+    pub weight: u64,
+}
+
+impl Request for SetWeight {
+    fn op(&self) -> &'static str { "set_weight" }
+
+    fn response_from_bytes(&self, _bytes: &[u8]) -> MogResult<Response> {
+        Ok(Response::Empty)
+    }
+
+    fn perform(&self, backend: &Backend) -> MogResult<Response> {
+        backend.set_weight(self).map(|r| r.to_response())
+    }
+}
+
+impl FromBytes for SetWeight {
+    fn from_bytes(bytes: &[u8]) -> MogResult<SetWeight> {
+        let mut args = ArgsHash::from_bytes(bytes);
+        let host = try!(args.extract_required_string("host", MogError::NoHost));
+        let device = try!(args.extract_required_int("device", MogError::NoDevid));
+        let weight = try!(args.extract_required_int("weight", MogError::Other("bad_params".to_string(), Some("Invalid weight".to_string()))));
+
+        Ok(SetWeight {
+            host: host,
+            device: device,
+            weight: weight,
+        })
+    }
+}
+
+impl ToArgs for SetWeight {
+    fn to_args(&self) -> Vec<(String, String)> {
+        vec!{
+            ("host".to_string(), self.host.clone()),
+            ("device".to_string(), self.device.to_string()),
+            ("weight".to_string(), self.weight.to_string()),
+        }
+    }
+}
+
 /// A `noop` request.
 ///
 /// Looks like this:
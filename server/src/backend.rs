@@ -1,20 +1,266 @@
-use mogilefs_common::MogResult;
-use std::io::{Read, Write};
+use mogilefs_common::{MogError, MogResult};
+use std::io::{self, Read, Seek, Write};
+use super::checksum::Md5Writer;
 use time::Tm;
 use url::Url;
 
 pub trait StorageBackend: Send + Sync {
+    /// A reader for a file's content, which the storage server can
+    /// seek around in to serve byte ranges.
+    type Content: Read + Seek + Send + 'static;
+
     fn url_for_key(&self, domain: &str, key: &str) -> Url;
 
-    fn file_metadata(&self, domain: &str, key: &str) -> MogResult<StorageMetadata>;
-    fn store_reader_content<R: Read>(&self, domain: &str, key: &str, reader: &mut R) -> MogResult<()>;
-    fn store_bytes_content(&self, domain: &str, key: &str, content: &[u8]) -> MogResult<()>;
-    fn get_content<W: Write>(&self, domain: &str, key: &str, writer: &mut W) -> MogResult<()>;
+    fn file_metadata(&self, path: &StoragePath) -> MogResult<StorageMetadata>;
+
+    /// The storage path for a fid, for requests which only name the
+    /// fid and device, as mogstored's URLs do.
+    fn fid_storage_path(&self, devid: Option<u64>, fid: u64) -> MogResult<StoragePath>;
+
+    /// The most content, in bytes, which may be stored for the file a
+    /// storage request refers to, if there's a limit.
+    fn content_limit(&self, path: &StoragePath) -> MogResult<Option<u64>>;
+
+    /// Whether the file a storage request refers to is still open,
+    /// which it has to be for content to be stored for it.
+    fn is_open_upload(&self, path: &StoragePath) -> MogResult<bool>;
+
+    fn store_reader_content<R: Read>(&self, path: &StoragePath, reader: &mut R) -> MogResult<()>;
+    fn store_bytes_content(&self, path: &StoragePath, content: &[u8]) -> MogResult<()>;
+
+    /// Store part of the content for a file which is still open,
+    /// starting `offset` bytes in, so that an upload can be sent a
+    /// piece at a time. `total` is how long the whole content will
+    /// be, if the client says.
+    fn store_reader_range<R: Read>(&self, path: &StoragePath, reader: &mut R, offset: u64, total: Option<u64>) -> MogResult<()>;
+
+    fn get_content(&self, path: &StoragePath) -> MogResult<Self::Content>;
+
+    /// Remove the content for the file a storage request refers to,
+    /// from the device it was addressed to (or every device), leaving
+    /// the file itself alone.
+    fn delete_content(&self, path: &StoragePath) -> MogResult<()>;
+
+    /// Remove the file a storage request refers to altogether.
+    fn delete_file(&self, path: &StoragePath) -> MogResult<()>;
+}
+
+/// The path, relative to a device, where mogstored keeps the content
+/// for a fid. The fid is zero-padded to ten digits and the first seven
+/// are used to spread the files out over a few levels of directories,
+/// so fid 1927 is kept in `0/000/001/0000001927.fid`.
+pub fn fid_path_segments(fid: u64) -> Vec<String> {
+    let padded = format!("{:010}", fid);
+    vec![
+        padded[0..1].to_string(),
+        padded[1..4].to_string(),
+        padded[4..7].to_string(),
+        format!("{}.fid", padded),
+    ]
+}
+
+/// The fid a path laid out like `fid_path_segments` refers to.
+pub fn fid_from_path_segments<S: AsRef<str>>(segments: &[S]) -> Option<u64> {
+    if segments.len() != 4 {
+        return None;
+    }
+
+    let name = segments[3].as_ref();
+    if !name.ends_with(".fid") {
+        return None;
+    }
+
+    let fid = match name[..(name.len() - 4)].parse::<u64>() {
+        Ok(fid) => fid,
+        Err(_) => return None,
+    };
+
+    let expected = fid_path_segments(fid);
+    if segments.iter().zip(expected.iter()).all(|(s, e)| s.as_ref() == e) {
+        Some(fid)
+    } else {
+        None
+    }
+}
+
+/// The file a storage URL's path refers to, however it's laid out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UrlPath {
+    /// mogstored's `/dev<N>/0/000/001/0000001927.fid`, with the
+    /// device and fid.
+    Fid(Option<u64>, u64),
+    /// `/dev<N>/d/<domain>/k/<key>`.
+    DomainKey(StoragePath),
+}
+
+/// Work out which file a storage URL's path refers to. Only a path
+/// which is exactly a device followed by a fid's segments is taken to
+/// be a fid, so that a key which happens to end like one isn't.
+pub fn parse_url_path(path: &[String]) -> Result<UrlPath, String> {
+    match fid_from_url_path(path) {
+        Some((devid, fid)) => Ok(UrlPath::Fid(devid, fid)),
+        None => storage_path_from_url_path(path).map(UrlPath::DomainKey),
+    }
+}
+
+/// The device and fid a mogstored-style path, like
+/// `/dev1/0/000/001/0000001927.fid`, refers to.
+fn fid_from_url_path(path: &[String]) -> Option<(Option<u64>, u64)> {
+    if path.len() != 5 || !path[0].starts_with("dev") {
+        return None;
+    }
+
+    let devid = match path[0][3..].parse::<u64>() {
+        Ok(devid) => devid,
+        Err(_) => return None,
+    };
+
+    fid_from_path_segments(&path[1..]).map(|fid| (Some(devid), fid))
+}
+
+/// The storage path a domain and key style path, like
+/// `/dev1/d/DOMAIN/k/KEY`, refers to.
+pub fn storage_path_from_url_path(path: &[String]) -> Result<StoragePath, String> {
+    let d_index = path.iter().position(|p| p == "d");
+    let k_index = path.iter().position(|p| p == "k");
+
+    match (d_index, k_index) {
+        (Some(d), Some(k)) if d < k => {
+            let domain = path[(d+1)..k].join("/");
+            let key = path[(k+1)..].join("/");
+
+            // A "dev<N>" segment just before the domain addresses a
+            // particular device.
+            let devid = if d > 0 && path[d-1].starts_with("dev") {
+                path[d-1][3..].parse::<u64>().ok()
+            } else {
+                None
+            };
+
+            Ok(StoragePath { devid: devid, fid: None, domain: domain, key: key })
+        },
+        _ => {
+            Err(format!("Could not extract domain or key from path: {:?}", path))
+        }
+    }
+}
+
+/// Copy the content of a storage request from `reader` to `writer`,
+/// a piece at a time, stopping with a `too_large` error as soon as
+/// there's more than `limit` bytes of it. Returns how many bytes were
+/// copied, and their hex MD5 digest.
+pub fn copy_content<R: Read, W: Write>(path: &StoragePath, reader: &mut R, writer: &mut W, limit: Option<u64>) -> MogResult<(u64, String)> {
+    let mut writer = Md5Writer::new(writer);
+    let copied = match limit {
+        Some(limit) => {
+            // One more byte than the limit is read, to tell whether
+            // there's too much; no file can have more than u64::MAX.
+            let copied = try!(io::copy(&mut reader.take(limit.saturating_add(1)), &mut writer));
+            if copied > limit {
+                return Err(too_large(path, limit));
+            }
+            copied
+        },
+        None => try!(io::copy(reader, &mut writer)),
+    };
+
+    Ok((copied, writer.md5_hex()))
+}
+
+/// Where a piece of content `length` bytes long, starting `offset`
+/// bytes in, ends, unless that's further than a file can go.
+pub fn range_end(path: &StoragePath, offset: u64, length: u64) -> MogResult<u64> {
+    offset.checked_add(length).ok_or_else(|| {
+        MogError::Other("bad_range".to_string(), Some(format!("Range of {:?} ends past the largest possible file", path.key)))
+    })
+}
+
+/// The error for content which is bigger than it's allowed to be.
+pub fn too_large(path: &StoragePath, limit: u64) -> MogError {
+    MogError::Other("too_large".to_string(), Some(format!("Content for {:?} is larger than {} bytes", path.key, limit)))
 }
 
 #[derive(Debug)]
 pub struct StorageMetadata {
     pub size: u64,
     pub mtime: Tm,
-    // Etag, Content-Type?
+    /// An opaque tag which changes whenever the content does.
+    pub etag: String,
+    /// The hex MD5 digest of the content, if it's known.
+    pub md5: Option<String>,
+    // Content-Type?
+}
+
+/// The file a storage request refers to, and the device it was
+/// addressed to. Without a device, any device holding a copy of the
+/// file will do. A fid picks out a particular version of the file,
+/// such as one that's still being uploaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoragePath {
+    pub devid: Option<u64>,
+    pub fid: Option<u64>,
+    pub domain: String,
+    pub key: String,
+}
+
+impl StoragePath {
+    pub fn new(domain: &str, key: &str) -> StoragePath {
+        StoragePath {
+            devid: None,
+            fid: None,
+            domain: domain.to_string(),
+            key: key.to_string(),
+        }
+    }
+
+    pub fn on_device(devid: u64, domain: &str, key: &str) -> StoragePath {
+        StoragePath {
+            devid: Some(devid),
+            fid: None,
+            domain: domain.to_string(),
+            key: key.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use super::*;
+
+    #[test]
+    fn fid_path_segments_round_trip() {
+        assert_eq!(vec![ "0", "000", "001", "0000001927.fid" ], fid_path_segments(1927));
+        assert_eq!(Some(1927), fid_from_path_segments(&fid_path_segments(1927)));
+        assert_eq!(Some(1927), fid_from_path_segments(&[ "0", "000", "001", "0000001927.fid" ]));
+        assert_eq!(None, fid_from_path_segments(&[ "0", "000", "002", "0000001927.fid" ]));
+        assert_eq!(None, fid_from_path_segments(&[ "0", "000", "001", "0000001927" ]));
+        assert_eq!(None, fid_from_path_segments(&[ "d", "test_domain", "k", "test/key/1" ]));
+    }
+
+    #[test]
+    fn copy_content_limits() {
+        let path = StoragePath::new("dom", "k1");
+        let mut copy = |limit| copy_content(&path, &mut Cursor::new(b"content"), &mut vec![], limit).map(|(copied, _)| copied);
+        assert_eq!(7, copy(None).unwrap());
+        assert_eq!(7, copy(Some(7)).unwrap());
+        assert_eq!(7, copy(Some(u64::MAX)).unwrap());
+        assert!(copy(Some(6)).is_err());
+    }
+
+    fn segments(path: &str) -> Vec<String> {
+        path.split('/').filter(|s| !s.is_empty()).map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn parse_url_paths() {
+        assert_eq!(Ok(UrlPath::Fid(Some(1), 1927)), parse_url_path(&segments("/dev1/0/000/001/0000001927.fid")));
+        assert_eq!(Ok(UrlPath::DomainKey(StoragePath::on_device(1, "dom", "k1"))), parse_url_path(&segments("/dev1/d/dom/k/k1")));
+
+        // Keys which end like a fid's path are still keys.
+        assert_eq!(Ok(UrlPath::DomainKey(StoragePath::on_device(1, "dom", "photos/0/000/001/0000001927.fid"))),
+                   parse_url_path(&segments("/dev1/d/dom/k/photos/0/000/001/0000001927.fid")));
+        assert!(parse_url_path(&segments("/photos/0/000/001/0000001927.fid")).is_err());
+        assert!(parse_url_path(&segments("/devx/0/000/001/0000001927.fid")).is_err());
+    }
 }
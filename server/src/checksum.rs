@@ -0,0 +1,165 @@
+//! MD5 checksums of file content, the only hash type MogileFS
+//! supports.
+
+use crypto::digest::Digest;
+use crypto::md5::Md5;
+use mogilefs_common::{MogError, MogResult};
+use rustc_serialize::hex::{FromHex, ToHex};
+use std::io::{self, ErrorKind, Read, Write};
+
+/// The lowercase hex MD5 digest of `content`.
+pub fn md5_hex(content: &[u8]) -> String {
+    let mut md5 = Md5::new();
+    md5.input(content);
+    md5.result_str()
+}
+
+/// The hex MD5 digest in a checksum argument like `MD5:<hex>`, as
+/// `create_close` is given.
+pub fn parse_checksum(checksum: &str) -> MogResult<String> {
+    let bad_checksum = || MogError::Other("bad_params".to_string(), Some(format!("Invalid checksum {:?}", checksum)));
+
+    let (hashtype, digest) = match checksum.find(':') {
+        Some(i) => (&checksum[..i], &checksum[(i + 1)..]),
+        None => return Err(bad_checksum()),
+    };
+
+    if hashtype.to_uppercase() != "MD5" {
+        return Err(MogError::Other("bad_params".to_string(), Some(format!("Unsupported hash type {:?}", hashtype))));
+    }
+
+    match digest.from_hex() {
+        Ok(ref bytes) if bytes.len() == 16 => Ok(bytes.to_hex()),
+        _ => Err(bad_checksum()),
+    }
+}
+
+/// The checksum argument for a hex MD5 digest, as `file_info`
+/// reports it.
+pub fn format_checksum(md5: &str) -> String {
+    format!("MD5:{}", md5)
+}
+
+/// A writer which works out the MD5 digest of everything written
+/// through it.
+pub struct Md5Writer<W> {
+    inner: W,
+    md5: Md5,
+}
+
+impl<W: Write> Md5Writer<W> {
+    pub fn new(inner: W) -> Md5Writer<W> {
+        Md5Writer {
+            inner: inner,
+            md5: Md5::new(),
+        }
+    }
+
+    /// The lowercase hex digest of what's been written.
+    pub fn md5_hex(&mut self) -> String {
+        self.md5.result_str()
+    }
+}
+
+impl<W: Write> Write for Md5Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = try!(self.inner.write(buf));
+        self.md5.input(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A reader which checks that what's read through it has the MD5
+/// digest it's expected to, such as the one in a `Content-MD5`
+/// header. Once the end is reached, a mismatch is an error.
+pub struct Md5CheckReader<R> {
+    inner: R,
+    md5: Md5,
+    expected: Vec<u8>,
+    mismatched: bool,
+}
+
+impl<R: Read> Md5CheckReader<R> {
+    pub fn new(inner: R, expected: Vec<u8>) -> Md5CheckReader<R> {
+        Md5CheckReader {
+            inner: inner,
+            md5: Md5::new(),
+            expected: expected,
+            mismatched: false,
+        }
+    }
+
+    /// Whether the content turned out not to match.
+    pub fn mismatched(&self) -> bool {
+        self.mismatched
+    }
+}
+
+impl<R: Read> Read for Md5CheckReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = try!(self.inner.read(buf));
+
+        if read > 0 || buf.is_empty() {
+            self.md5.input(&buf[..read]);
+        } else {
+            let mut digest = [0u8; 16];
+            self.md5.result(&mut digest);
+            if &digest[..] != &self.expected[..] {
+                self.mismatched = true;
+                return Err(io::Error::new(ErrorKind::InvalidData, "Content does not match its MD5 digest"));
+            }
+        }
+
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mogilefs_common::MogError;
+    use rustc_serialize::hex::FromHex;
+    use std::io::{self, Cursor, Read};
+    use super::*;
+
+    static CONTENT: &'static [u8] = b"The quick brown fox jumps over the lazy dog";
+    static CONTENT_MD5: &'static str = "9e107d9d372bb6826bd81d3542a419d6";
+
+    #[test]
+    fn md5_of_content() {
+        assert_eq!(CONTENT_MD5, md5_hex(CONTENT));
+        assert_eq!("d41d8cd98f00b204e9800998ecf8427e", md5_hex(b""));
+    }
+
+    #[test]
+    fn parse_checksums() {
+        assert_eq!(CONTENT_MD5, parse_checksum(&format_checksum(CONTENT_MD5)).unwrap());
+        assert_eq!(CONTENT_MD5, parse_checksum(&format!("md5:{}", CONTENT_MD5.to_uppercase())).unwrap());
+        assert!(matches!(parse_checksum("SHA1:abcd"), Err(MogError::Other(ref c, _)) if c == "bad_params"));
+        assert!(matches!(parse_checksum("MD5:abcd"), Err(MogError::Other(ref c, _)) if c == "bad_params"));
+        assert!(matches!(parse_checksum(CONTENT_MD5), Err(MogError::Other(ref c, _)) if c == "bad_params"));
+    }
+
+    #[test]
+    fn md5_writer() {
+        let mut writer = Md5Writer::new(vec![]);
+        io::copy(&mut Cursor::new(CONTENT), &mut writer).unwrap();
+        assert_eq!(CONTENT_MD5, writer.md5_hex());
+        assert_eq!(CONTENT, &writer.inner[..]);
+    }
+
+    #[test]
+    fn md5_check_reader() {
+        let mut content = vec![];
+        let mut reader = Md5CheckReader::new(Cursor::new(CONTENT), CONTENT_MD5.from_hex().unwrap());
+        reader.read_to_end(&mut content).unwrap();
+        assert!(!reader.mismatched());
+
+        let mut reader = Md5CheckReader::new(Cursor::new(&CONTENT[1..]), CONTENT_MD5.from_hex().unwrap());
+        assert!(reader.read_to_end(&mut content).is_err());
+        assert!(reader.mismatched());
+    }
+}
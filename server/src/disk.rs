@@ -0,0 +1,380 @@
+//! A storage backend which keeps file content on disk, one file per
+//! fid, while the mem tracker keeps track of everything else.
+
+use mogilefs_common::{MogError, MogResult};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Cursor, ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use super::backend::{copy_content, fid_from_path_segments, fid_path_segments, StorageBackend, StorageMetadata, StoragePath};
+use super::checksum::Md5Writer;
+use super::mem::SyncMemBackend;
+use time;
+use url::Url;
+
+static TEMP_DIR: &'static str = "tmp";
+
+#[derive(Clone, Debug)]
+pub struct DiskStorageBackend {
+    root: PathBuf,
+    metadata: SyncMemBackend,
+}
+
+impl DiskStorageBackend {
+    /// Keep content in the directory `root`, creating it if need be,
+    /// for the files `metadata` knows about. Content left over for
+    /// files it doesn't know about is removed; see
+    /// `remove_unknown_content`.
+    pub fn new<P: AsRef<Path>>(root: P, metadata: SyncMemBackend) -> MogResult<DiskStorageBackend> {
+        let backend = DiskStorageBackend {
+            root: root.as_ref().to_path_buf(),
+            metadata: metadata,
+        };
+
+        try!(fs::create_dir_all(backend.root.join(TEMP_DIR)));
+        try!(backend.metadata.track_released_fids());
+        try!(backend.remove_unknown_content());
+        Ok(backend)
+    }
+
+    /// Where the content for a fid lives, laid out like mogstored's.
+    pub fn fid_path(&self, fid: u64) -> PathBuf {
+        fid_path_segments(fid).iter().fold(self.root.clone(), |path, segment| path.join(segment))
+    }
+
+    fn temp_path(&self, fid: u64) -> PathBuf {
+        self.root.join(TEMP_DIR).join(format!("{}.{}.tmp", fid, time::precise_time_ns()))
+    }
+
+    /// Remove the content of files which have been deleted or
+    /// replaced since the last time.
+    fn remove_released_content(&self) -> MogResult<()> {
+        for fid in try!(self.metadata.take_released_fids()) {
+            match fs::remove_file(self.fid_path(fid)) {
+                Err(ref e) if e.kind() == ErrorKind::NotFound => {},
+                Err(e) => warn!("Error removing content for fid {}: {}", fid, e),
+                Ok(_) => {},
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove content left over from before a restart: whatever's in
+    /// the temporary directory, and content laid out for fids which
+    /// aren't known any more. Nothing else in the directory is
+    /// touched, in case it's there for some other reason.
+    fn remove_unknown_content(&self) -> MogResult<()> {
+        for dir_entry in try!(fs::read_dir(self.root.join(TEMP_DIR))) {
+            let path = try!(dir_entry).path();
+            if path.is_file() {
+                info!("Removing temporary content {:?}", path);
+                try!(fs::remove_file(&path));
+            }
+        }
+
+        self.remove_unknown_fids(&self.root, &mut vec![])
+    }
+
+    fn remove_unknown_fids(&self, dir: &Path, segments: &mut Vec<String>) -> MogResult<()> {
+        for dir_entry in try!(fs::read_dir(dir)) {
+            let path = try!(dir_entry).path();
+            let name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+
+            segments.push(name);
+            if path.is_dir() {
+                // Only the three levels of numbered directories fids
+                // are kept in are looked in.
+                if segments.len() < 4 && segments.last().unwrap().chars().all(|c| c.is_digit(10)) {
+                    try!(self.remove_unknown_fids(&path, segments));
+                }
+            } else if let Some(fid) = fid_from_path_segments(segments) {
+                if !try!(self.metadata.has_fid(fid)) {
+                    info!("Removing unknown content {:?}", path);
+                    try!(fs::remove_file(&path));
+                }
+            }
+            segments.pop();
+        }
+
+        Ok(())
+    }
+}
+
+impl StorageBackend for DiskStorageBackend {
+    type Content = File;
+
+    fn url_for_key(&self, domain: &str, key: &str) -> Url {
+        self.metadata.url_for_key(domain, key)
+    }
+
+    fn file_metadata(&self, path: &StoragePath) -> MogResult<StorageMetadata> {
+        self.metadata.file_metadata(path)
+    }
+
+    fn content_limit(&self, path: &StoragePath) -> MogResult<Option<u64>> {
+        self.metadata.content_limit(path)
+    }
+
+    fn is_open_upload(&self, path: &StoragePath) -> MogResult<bool> {
+        self.metadata.is_open_upload(path)
+    }
+
+    fn fid_storage_path(&self, devid: Option<u64>, fid: u64) -> MogResult<StoragePath> {
+        self.metadata.fid_storage_path(devid, fid)
+    }
+
+    fn store_reader_content<R: Read>(&self, path: &StoragePath, reader: &mut R) -> MogResult<()> {
+        try!(self.remove_released_content());
+        let fid = try!(self.metadata.writeable_fid(path));
+        let limit = try!(self.metadata.content_limit(path));
+
+        // The content's written somewhere else first, so that a
+        // reader never sees half of it.
+        let temp_path = self.temp_path(fid);
+        let (size, md5) = {
+            let mut file = try!(File::create(&temp_path));
+            let copied = copy_content(path, reader, &mut file, limit)
+                .and_then(|copied| file.sync_all().map(|_| copied).map_err(MogError::from));
+            match copied {
+                Ok(copied) => copied,
+                Err(e) => {
+                    let _ = fs::remove_file(&temp_path);
+                    return Err(e);
+                },
+            }
+        };
+
+        let fid_path = self.fid_path(fid);
+        try!(fs::create_dir_all(fid_path.parent().unwrap()));
+        try!(fs::rename(&temp_path, &fid_path));
+
+        match self.metadata.store_content_size(path, size, &md5) {
+            Err(e @ MogError::UnknownKey(..)) | Err(e @ MogError::UnknownFid(..)) => {
+                // The file went away while we were busy.
+                let _ = fs::remove_file(&fid_path);
+                Err(e)
+            },
+            result => result,
+        }
+    }
+
+    fn store_bytes_content(&self, path: &StoragePath, content: &[u8]) -> MogResult<()> {
+        self.store_reader_content(path, &mut Cursor::new(content))
+    }
+
+    fn store_reader_range<R: Read>(&self, path: &StoragePath, reader: &mut R, offset: u64, total: Option<u64>) -> MogResult<()> {
+        try!(self.remove_released_content());
+        let fid = try!(self.metadata.writeable_fid(path));
+        let limit = try!(self.metadata.range_limit(path, offset, total));
+
+        // The file isn't readable until it's closed, so the range
+        // can be written in place.
+        let fid_path = self.fid_path(fid);
+        try!(fs::create_dir_all(fid_path.parent().unwrap()));
+        let (length, _) = {
+            let mut file = try!(OpenOptions::new().write(true).create(true).open(&fid_path));
+            try!(file.seek(SeekFrom::Start(offset)));
+            let copied = try!(copy_content(path, reader, &mut file, limit));
+            try!(file.sync_all());
+            copied
+        };
+
+        // Once the last of the content is in, its digest can be
+        // worked out.
+        let md5 = if try!(self.metadata.range_completes(path, offset, length)) {
+            let mut writer = Md5Writer::new(io::sink());
+            try!(io::copy(&mut try!(File::open(&fid_path)), &mut writer));
+            Some(writer.md5_hex())
+        } else {
+            None
+        };
+
+        self.metadata.store_range_size(path, offset, length, total, md5)
+    }
+
+    fn delete_content(&self, path: &StoragePath) -> MogResult<()> {
+        try!(self.metadata.delete_content(path));
+        self.remove_released_content()
+    }
+
+    fn delete_file(&self, path: &StoragePath) -> MogResult<()> {
+        try!(self.metadata.delete_file(path));
+        self.remove_released_content()
+    }
+
+    fn get_content(&self, path: &StoragePath) -> MogResult<File> {
+        let fid = try!(self.metadata.readable_fid(path));
+        File::open(self.fid_path(fid)).map_err(|e| {
+            match e.kind() {
+                ErrorKind::NotFound => MogError::NoContent(path.key.clone()),
+                _ => MogError::from(e),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mogilefs_common::{Backend, MogError};
+    use mogilefs_common::requests::*;
+    use std::fs::{self, File};
+    use std::io::{Cursor, Read, Write};
+    use super::*;
+    use super::super::backend::{StorageBackend, StoragePath};
+    use super::super::checksum::md5_hex;
+    use super::super::test_support::*;
+
+    /// Open and store a file through the disk backend, returning the
+    /// request to close it with.
+    fn store_file(backend: &DiskStorageBackend, key: &str, content: &[u8]) -> CreateClose {
+        let co_response = backend.metadata.create_open(&CreateOpen {
+            domain: TEST_DOMAIN.to_string(), class: None,
+            key: key.to_string(), multi_dest: false, size: None,
+        }).unwrap();
+        let (devid, ref path) = co_response.paths[0];
+        let mut storage_path = StoragePath::on_device(devid, TEST_DOMAIN, key);
+        storage_path.fid = Some(co_response.fid);
+
+        backend.store_bytes_content(&storage_path, content).unwrap();
+
+        CreateClose {
+            domain: TEST_DOMAIN.to_string(), key: key.to_string(),
+            fid: co_response.fid, devid: devid, path: path.clone(), checksum: None,
+        }
+    }
+
+    #[test]
+    fn fid_path() {
+        let dir = temp_data_dir("disk_fid_path");
+        let backend = DiskStorageBackend::new(&dir, sync_backend_fixture()).unwrap();
+        assert_eq!(dir.join("0/000/001/0000001927.fid"), backend.fid_path(1927));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stores_content_on_disk() {
+        let dir = temp_data_dir("disk_store");
+        let backend = DiskStorageBackend::new(&dir, sync_backend_fixture()).unwrap();
+
+        let close_request = store_file(&backend, "test/key/3", TEST_CONTENT_1);
+        let mut on_disk = vec![];
+        File::open(backend.fid_path(close_request.fid)).unwrap().read_to_end(&mut on_disk).unwrap();
+        assert_eq!(TEST_CONTENT_1, &on_disk[..]);
+        assert!(fs::read_dir(dir.join("tmp")).unwrap().next().is_none());
+
+        backend.metadata.create_close(&close_request).unwrap();
+        let path = StoragePath::new(TEST_DOMAIN, "test/key/3");
+        assert_eq!(TEST_CONTENT_1.len() as u64, backend.file_metadata(&path).unwrap().size);
+
+        let mut content = vec![];
+        backend.get_content(&path).unwrap().read_to_end(&mut content).unwrap();
+        assert_eq!(TEST_CONTENT_1, &content[..]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_content_over_limit() {
+        let dir = temp_data_dir("disk_limit");
+        let backend = DiskStorageBackend::new(&dir, sync_backend_fixture()).unwrap();
+        backend.metadata.set_max_file_size(Some(4)).unwrap();
+
+        let path = StoragePath::new(TEST_DOMAIN, TEST_KEY_1);
+        let result = backend.store_bytes_content(&path, TEST_CONTENT_1);
+        assert!(matches!(result, Err(MogError::Other(ref c, _)) if c == "too_large"), "Store too large file result was {:?}", result);
+        assert!(fs::read_dir(dir.join("tmp")).unwrap().next().is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stores_ranges_on_disk() {
+        let dir = temp_data_dir("disk_ranges");
+        let backend = DiskStorageBackend::new(&dir, sync_backend_fixture()).unwrap();
+
+        let co_response = backend.metadata.create_open(&CreateOpen {
+            domain: TEST_DOMAIN.to_string(), class: None,
+            key: "test/key/3".to_string(), multi_dest: false, size: Some(TEST_CONTENT_1.len() as u64),
+        }).unwrap();
+        let (devid, ref path) = co_response.paths[0];
+        let mut storage_path = StoragePath::on_device(devid, TEST_DOMAIN, "test/key/3");
+        storage_path.fid = Some(co_response.fid);
+
+        let total = Some(TEST_CONTENT_1.len() as u64);
+        backend.store_reader_range(&storage_path, &mut Cursor::new(&TEST_CONTENT_1[8..]), 8, total).unwrap();
+        backend.store_reader_range(&storage_path, &mut Cursor::new(&TEST_CONTENT_1[..8]), 0, total).unwrap();
+
+        backend.metadata.create_close(&CreateClose {
+            domain: TEST_DOMAIN.to_string(), key: "test/key/3".to_string(),
+            fid: co_response.fid, devid: devid, path: path.clone(),
+            checksum: Some(format!("MD5:{}", md5_hex(TEST_CONTENT_1))),
+        }).unwrap();
+
+        let mut content = vec![];
+        backend.get_content(&StoragePath::new(TEST_DOMAIN, "test/key/3")).unwrap().read_to_end(&mut content).unwrap();
+        assert_eq!(TEST_CONTENT_1, &content[..]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn deletes_content() {
+        let dir = temp_data_dir("disk_delete");
+        let backend = DiskStorageBackend::new(&dir, sync_backend_fixture()).unwrap();
+
+        let close_request = store_file(&backend, "test/key/3", TEST_CONTENT_1);
+        backend.metadata.create_close(&close_request).unwrap();
+        let path = StoragePath::new(TEST_DOMAIN, "test/key/3");
+        backend.delete_content(&path).unwrap();
+        assert!(!backend.fid_path(close_request.fid).exists());
+        assert!(backend.metadata.file_info(&FileInfo { domain: TEST_DOMAIN.to_string(), key: "test/key/3".to_string() }).is_ok());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn removes_content_no_longer_needed() {
+        let dir = temp_data_dir("disk_remove");
+        let backend = DiskStorageBackend::new(&dir, sync_backend_fixture()).unwrap();
+
+        let close_request = store_file(&backend, "test/key/3", TEST_CONTENT_1);
+        backend.metadata.create_close(&close_request).unwrap();
+        backend.metadata.delete(&Delete { domain: TEST_DOMAIN.to_string(), key: "test/key/3".to_string() }).unwrap();
+        assert!(backend.fid_path(close_request.fid).exists());
+
+        // It goes when the next file is stored.
+        store_file(&backend, "test/key/4", TEST_CONTENT_1);
+        assert!(!backend.fid_path(close_request.fid).exists());
+
+        // Anything it doesn't know about at all goes at startup.
+        fs::create_dir_all(backend.fid_path(9999).parent().unwrap()).unwrap();
+        File::create(backend.fid_path(9999)).unwrap().write_all(b"Orphaned content").unwrap();
+        let backend = DiskStorageBackend::new(&dir, backend.metadata.clone()).unwrap();
+        assert!(!backend.fid_path(9999).exists());
+
+        let result = backend.get_content(&StoragePath::new(TEST_DOMAIN, "test/key/3"));
+        assert!(matches!(result, Err(MogError::UnknownKey(..))), "Deleted file content was {:?}", result);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn leaves_other_files_alone() {
+        let dir = temp_data_dir("disk_other_files");
+        fs::create_dir_all(dir.join("journal")).unwrap();
+        File::create(dir.join("journal/000001.log")).unwrap().write_all(b"Not content").unwrap();
+        File::create(dir.join("0000009999.fid")).unwrap().write_all(b"Not laid out like content").unwrap();
+        fs::create_dir_all(dir.join("tmp")).unwrap();
+        File::create(dir.join("tmp/9999.1.tmp")).unwrap().write_all(b"Half-written content").unwrap();
+
+        DiskStorageBackend::new(&dir, sync_backend_fixture()).unwrap();
+        assert!(dir.join("journal/000001.log").exists());
+        assert!(dir.join("0000009999.fid").exists());
+        assert!(!dir.join("tmp/9999.1.tmp").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,4 +1,5 @@
 extern crate chrono;
+extern crate crypto;
 extern crate hyper;
 extern crate iron;
 extern crate libc;
@@ -7,6 +8,7 @@ extern crate mogilefs_client;
 extern crate mogilefs_common;
 extern crate plugin;
 extern crate r2d2;
+extern crate rustc_serialize;
 extern crate statsd;
 extern crate threadpool;
 extern crate time;
@@ -26,11 +28,14 @@ extern crate regex;
 extern crate env_logger;
 
 pub mod backend;
+pub mod checksum;
+pub mod disk;
 pub mod mem;
 pub mod net;
 pub mod proxy;
 pub mod r2d2_statsd;
 pub mod range;
+pub mod signing;
 
 #[cfg(unix)]
 pub mod ctrlc;
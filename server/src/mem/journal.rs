@@ -0,0 +1,650 @@
+//! A write-ahead log for the in-memory backend, so that it can pick
+//! up where it left off when it's restarted.
+//!
+//! Every change to the backend's domains, classes and files is
+//! appended to the journal before it's made. Each change is a line
+//! that looks like a tracker request (`op arg=value&arg=value`), with
+//! file content base64-encoded. Every so often the journal is
+//! compacted in to a snapshot, which is in the same format: it's just
+//! the list of changes it takes to rebuild the backend from nothing.
+//!
+//! Hosts and devices aren't journalled; they're set up from the
+//! command line every time.
+
+use mogilefs_common::{ArgsHash, FromBytes, MogError, MogResult, ToArgs, ToUrlencodedString};
+use rustc_serialize::base64::{FromBase64, ToBase64, STANDARD};
+use std::collections::BTreeSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str;
+use time::{self, Timespec, Tm};
+use url::Url;
+
+/// How many changes are appended to the journal before it's
+/// compacted, by default.
+pub const DEFAULT_COMPACT_EVERY: usize = 10000;
+
+static SNAPSHOT_FILE: &'static str = "snapshot";
+static SNAPSHOT_TEMP_FILE: &'static str = "snapshot.tmp";
+
+/// A single change to the backend.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JournalEntry {
+    CreateDomain { domain: String },
+    DeleteDomain { domain: String },
+    SetClass { domain: String, class: String, mindevcount: u64, replpolicy: Option<String>, hashtype: Option<String> },
+    DeleteClass { domain: String, class: String },
+    OpenFile { fid: u64, domain: String, key: String, class: String, size: Option<u64>, paths: Vec<(u64, Url)>, created: Tm },
+    StoreContent { fid: Option<u64>, domain: String, key: String, devids: BTreeSet<u64>, size: u64, md5: Option<String>, content: Option<Vec<u8>>, mtime: Tm },
+    StoreRange { fid: u64, domain: String, key: String, devids: BTreeSet<u64>, offset: u64, length: u64, total: Option<u64>, md5: Option<String>, content: Option<Vec<u8>>, mtime: Tm },
+    DeleteContent { fid: Option<u64>, domain: String, key: String, devid: Option<u64> },
+    CloseFile { fid: u64 },
+    AbandonFile { fid: u64 },
+    PutFile { fid: u64, domain: String, key: String, class: String, devids: BTreeSet<u64>, size: Option<u64>, md5: Option<String>, content: Option<Vec<u8>>, mtime: Option<Tm> },
+    UpdateClass { domain: String, key: String, class: String },
+    Delete { domain: String, key: String },
+    Rename { domain: String, from_key: String, to_key: String },
+    NextFid { fid: u64 },
+}
+
+impl JournalEntry {
+    pub fn op(&self) -> &'static str {
+        use self::JournalEntry::*;
+
+        match *self {
+            CreateDomain { .. } => "create_domain",
+            DeleteDomain { .. } => "delete_domain",
+            SetClass { .. } => "set_class",
+            DeleteClass { .. } => "delete_class",
+            OpenFile { .. } => "open_file",
+            StoreContent { .. } => "store_content",
+            StoreRange { .. } => "store_range",
+            DeleteContent { .. } => "delete_content",
+            CloseFile { .. } => "close_file",
+            AbandonFile { .. } => "abandon_file",
+            PutFile { .. } => "put_file",
+            UpdateClass { .. } => "update_class",
+            Delete { .. } => "delete",
+            Rename { .. } => "rename",
+            NextFid { .. } => "next_fid",
+        }
+    }
+
+    /// The line this change is written to the journal as.
+    pub fn to_line(&self) -> String {
+        format!("{} {}\n", self.op(), self.to_urlencoded_string())
+    }
+}
+
+impl ToArgs for JournalEntry {
+    fn to_args(&self) -> Vec<(String, String)> {
+        use self::JournalEntry::*;
+
+        let mut args = vec![];
+
+        match *self {
+            CreateDomain { ref domain } | DeleteDomain { ref domain } => {
+                args.push(("domain".to_string(), domain.clone()));
+            },
+            SetClass { ref domain, ref class, mindevcount, ref replpolicy, ref hashtype } => {
+                args.push(("domain".to_string(), domain.clone()));
+                args.push(("class".to_string(), class.clone()));
+                args.push(("mindevcount".to_string(), mindevcount.to_string()));
+                if let Some(ref replpolicy) = *replpolicy {
+                    args.push(("replpolicy".to_string(), replpolicy.clone()));
+                }
+                if let Some(ref hashtype) = *hashtype {
+                    args.push(("hashtype".to_string(), hashtype.clone()));
+                }
+            },
+            DeleteClass { ref domain, ref class } => {
+                args.push(("domain".to_string(), domain.clone()));
+                args.push(("class".to_string(), class.clone()));
+            },
+            OpenFile { fid, ref domain, ref key, ref class, size, ref paths, ref created } => {
+                args.push(("fid".to_string(), fid.to_string()));
+                args.push(("domain".to_string(), domain.clone()));
+                args.push(("key".to_string(), key.clone()));
+                args.push(("class".to_string(), class.clone()));
+                if let Some(size) = size {
+                    args.push(("size".to_string(), size.to_string()));
+                }
+                args.push(("dev_count".to_string(), paths.len().to_string()));
+                for (i, &(devid, ref url)) in paths.iter().enumerate() {
+                    args.push((format!("devid_{}", i + 1), devid.to_string()));
+                    args.push((format!("path_{}", i + 1), url.to_string()));
+                }
+                args.push(("created".to_string(), tm_to_arg(created)));
+            },
+            StoreContent { fid, ref domain, ref key, ref devids, size, ref md5, ref content, ref mtime } => {
+                if let Some(fid) = fid {
+                    args.push(("fid".to_string(), fid.to_string()));
+                }
+                args.push(("domain".to_string(), domain.clone()));
+                args.push(("key".to_string(), key.clone()));
+                args.push(("devids".to_string(), devids_to_arg(devids)));
+                args.push(("size".to_string(), size.to_string()));
+                if let Some(ref md5) = *md5 {
+                    args.push(("md5".to_string(), md5.clone()));
+                }
+                if let Some(ref content) = *content {
+                    args.push(("content".to_string(), content.to_base64(STANDARD)));
+                }
+                args.push(("mtime".to_string(), tm_to_arg(mtime)));
+            },
+            StoreRange { fid, ref domain, ref key, ref devids, offset, length, total, ref md5, ref content, ref mtime } => {
+                args.push(("fid".to_string(), fid.to_string()));
+                args.push(("domain".to_string(), domain.clone()));
+                args.push(("key".to_string(), key.clone()));
+                args.push(("devids".to_string(), devids_to_arg(devids)));
+                args.push(("offset".to_string(), offset.to_string()));
+                args.push(("length".to_string(), length.to_string()));
+                if let Some(total) = total {
+                    args.push(("total".to_string(), total.to_string()));
+                }
+                if let Some(ref md5) = *md5 {
+                    args.push(("md5".to_string(), md5.clone()));
+                }
+                if let Some(ref content) = *content {
+                    args.push(("content".to_string(), content.to_base64(STANDARD)));
+                }
+                args.push(("mtime".to_string(), tm_to_arg(mtime)));
+            },
+            DeleteContent { fid, ref domain, ref key, devid } => {
+                if let Some(fid) = fid {
+                    args.push(("fid".to_string(), fid.to_string()));
+                }
+                args.push(("domain".to_string(), domain.clone()));
+                args.push(("key".to_string(), key.clone()));
+                if let Some(devid) = devid {
+                    args.push(("devid".to_string(), devid.to_string()));
+                }
+            },
+            CloseFile { fid } | AbandonFile { fid } | NextFid { fid } => {
+                args.push(("fid".to_string(), fid.to_string()));
+            },
+            PutFile { fid, ref domain, ref key, ref class, ref devids, size, ref md5, ref content, ref mtime } => {
+                args.push(("fid".to_string(), fid.to_string()));
+                args.push(("domain".to_string(), domain.clone()));
+                args.push(("key".to_string(), key.clone()));
+                args.push(("class".to_string(), class.clone()));
+                args.push(("devids".to_string(), devids_to_arg(devids)));
+                if let Some(size) = size {
+                    args.push(("size".to_string(), size.to_string()));
+                }
+                if let Some(ref md5) = *md5 {
+                    args.push(("md5".to_string(), md5.clone()));
+                }
+                if let Some(ref content) = *content {
+                    args.push(("content".to_string(), content.to_base64(STANDARD)));
+                }
+                if let Some(ref mtime) = *mtime {
+                    args.push(("mtime".to_string(), tm_to_arg(mtime)));
+                }
+            },
+            UpdateClass { ref domain, ref key, ref class } => {
+                args.push(("domain".to_string(), domain.clone()));
+                args.push(("key".to_string(), key.clone()));
+                args.push(("class".to_string(), class.clone()));
+            },
+            Delete { ref domain, ref key } => {
+                args.push(("domain".to_string(), domain.clone()));
+                args.push(("key".to_string(), key.clone()));
+            },
+            Rename { ref domain, ref from_key, ref to_key } => {
+                args.push(("domain".to_string(), domain.clone()));
+                args.push(("from_key".to_string(), from_key.clone()));
+                args.push(("to_key".to_string(), to_key.clone()));
+            },
+        }
+
+        args
+    }
+}
+
+impl FromBytes for JournalEntry {
+    fn from_bytes(bytes: &[u8]) -> MogResult<JournalEntry> {
+        use self::JournalEntry::*;
+
+        let line = try!(str::from_utf8(bytes)).trim_right();
+        let (op, args) = match line.find(' ') {
+            Some(i) => (&line[..i], &line[(i + 1)..]),
+            None => (line, ""),
+        };
+        let mut args = ArgsHash::from_bytes(args.as_bytes());
+        let bad = || bad_entry(line);
+
+        match op {
+            "create_domain" => Ok(CreateDomain {
+                domain: try!(args.extract_required_string("domain", bad())),
+            }),
+            "delete_domain" => Ok(DeleteDomain {
+                domain: try!(args.extract_required_string("domain", bad())),
+            }),
+            "set_class" => Ok(SetClass {
+                domain: try!(args.extract_required_string("domain", bad())),
+                class: try!(args.extract_required_string("class", bad())),
+                mindevcount: try!(args.extract_required_int("mindevcount", bad())),
+                replpolicy: args.extract_optional_string("replpolicy"),
+                hashtype: args.extract_optional_string("hashtype"),
+            }),
+            "delete_class" => Ok(DeleteClass {
+                domain: try!(args.extract_required_string("domain", bad())),
+                class: try!(args.extract_required_string("class", bad())),
+            }),
+            "open_file" => {
+                let dev_count = try!(args.extract_required_int("dev_count", bad()));
+                let mut paths = Vec::with_capacity(dev_count as usize);
+                for i in 1..(dev_count + 1) {
+                    let devid = try!(args.extract_required_int(&format!("devid_{}", i), bad()));
+                    let path = try!(args.extract_required_url(&format!("path_{}", i), bad()));
+                    paths.push((devid, path));
+                }
+
+                Ok(OpenFile {
+                    fid: try!(args.extract_required_int("fid", bad())),
+                    domain: try!(args.extract_required_string("domain", bad())),
+                    key: try!(args.extract_required_string("key", bad())),
+                    class: try!(args.extract_required_string("class", bad())),
+                    size: args.extract_optional_int("size"),
+                    paths: paths,
+                    created: try!(args.extract_optional_string("created").and_then(|c| tm_from_arg(&c)).ok_or(bad())),
+                })
+            },
+            "store_content" => Ok(StoreContent {
+                fid: args.extract_optional_int("fid"),
+                domain: try!(args.extract_required_string("domain", bad())),
+                key: try!(args.extract_required_string("key", bad())),
+                devids: try!(args.extract_optional_string("devids").and_then(|d| devids_from_arg(&d)).ok_or(bad())),
+                size: try!(args.extract_optional_int("size").ok_or(bad())),
+                md5: args.extract_optional_string("md5"),
+                content: try!(extract_content(&mut args, bad)),
+                mtime: try!(args.extract_optional_string("mtime").and_then(|m| tm_from_arg(&m)).ok_or(bad())),
+            }),
+            "store_range" => Ok(StoreRange {
+                fid: try!(args.extract_required_int("fid", bad())),
+                domain: try!(args.extract_required_string("domain", bad())),
+                key: try!(args.extract_required_string("key", bad())),
+                devids: try!(args.extract_optional_string("devids").and_then(|d| devids_from_arg(&d)).ok_or(bad())),
+                offset: try!(args.extract_required_int("offset", bad())),
+                length: try!(args.extract_required_int("length", bad())),
+                total: args.extract_optional_int("total"),
+                md5: args.extract_optional_string("md5"),
+                content: try!(extract_content(&mut args, bad)),
+                mtime: try!(args.extract_optional_string("mtime").and_then(|m| tm_from_arg(&m)).ok_or(bad())),
+            }),
+            "delete_content" => Ok(DeleteContent {
+                fid: args.extract_optional_int("fid"),
+                domain: try!(args.extract_required_string("domain", bad())),
+                key: try!(args.extract_required_string("key", bad())),
+                devid: args.extract_optional_int("devid"),
+            }),
+            "close_file" => Ok(CloseFile {
+                fid: try!(args.extract_required_int("fid", bad())),
+            }),
+            "abandon_file" => Ok(AbandonFile {
+                fid: try!(args.extract_required_int("fid", bad())),
+            }),
+            "put_file" => {
+                let content = try!(extract_content(&mut args, bad));
+                let mtime = match args.extract_optional_string("mtime") {
+                    Some(m) => Some(try!(tm_from_arg(&m).ok_or(bad()))),
+                    None => None,
+                };
+
+                Ok(PutFile {
+                    fid: try!(args.extract_required_int("fid", bad())),
+                    domain: try!(args.extract_required_string("domain", bad())),
+                    key: try!(args.extract_required_string("key", bad())),
+                    class: try!(args.extract_required_string("class", bad())),
+                    devids: try!(args.extract_optional_string("devids").and_then(|d| devids_from_arg(&d)).ok_or(bad())),
+                    size: args.extract_optional_int("size"),
+                    md5: args.extract_optional_string("md5"),
+                    content: content,
+                    mtime: mtime,
+                })
+            },
+            "update_class" => Ok(UpdateClass {
+                domain: try!(args.extract_required_string("domain", bad())),
+                key: try!(args.extract_required_string("key", bad())),
+                class: try!(args.extract_required_string("class", bad())),
+            }),
+            "delete" => Ok(Delete {
+                domain: try!(args.extract_required_string("domain", bad())),
+                key: try!(args.extract_required_string("key", bad())),
+            }),
+            "rename" => Ok(Rename {
+                domain: try!(args.extract_required_string("domain", bad())),
+                from_key: try!(args.extract_required_string("from_key", bad())),
+                to_key: try!(args.extract_required_string("to_key", bad())),
+            }),
+            "next_fid" => Ok(NextFid {
+                fid: try!(args.extract_required_int("fid", bad())),
+            }),
+            _ => Err(bad()),
+        }
+    }
+}
+
+/// The journal and snapshot for a backend, kept in a data directory.
+///
+/// The snapshot starts with a line naming its generation, and only
+/// the journal for that generation is replayed on top of it, so a
+/// compaction that's interrupted part way through can't replay a
+/// change twice.
+#[derive(Debug)]
+pub struct Journal {
+    dir: PathBuf,
+    generation: u64,
+    log: File,
+    appended: usize,
+    compact_every: usize,
+}
+
+impl Journal {
+    /// Open the journal in `dir`, creating the directory if need be,
+    /// and return it along with every change recorded there so far.
+    pub fn open<P: AsRef<Path>>(dir: P) -> MogResult<(Journal, Vec<JournalEntry>)> {
+        let dir = dir.as_ref().to_path_buf();
+        try!(fs::create_dir_all(&dir));
+
+        let (generation, mut entries) = try!(read_snapshot(&dir.join(SNAPSHOT_FILE)));
+        let log_path = dir.join(log_file_name(generation));
+        entries.extend(try!(read_log(&log_path)));
+
+        // Anything else is left over from before the last
+        // compaction.
+        for dir_entry in try!(fs::read_dir(&dir)) {
+            let path = try!(dir_entry).path();
+            let is_log = path.file_name().and_then(|n| n.to_str())
+                .map(|n| n.starts_with("journal.") && n.ends_with(".log"))
+                .unwrap_or(false);
+            if is_log && path != log_path {
+                try!(fs::remove_file(&path));
+            }
+        }
+
+        let log = try!(OpenOptions::new().create(true).append(true).open(&log_path));
+
+        Ok((Journal {
+            dir: dir,
+            generation: generation,
+            log: log,
+            appended: 0,
+            compact_every: DEFAULT_COMPACT_EVERY,
+        }, entries))
+    }
+
+    /// Set how many changes may be appended before the journal
+    /// should be compacted.
+    pub fn set_compact_every(&mut self, compact_every: usize) {
+        self.compact_every = compact_every;
+    }
+
+    /// Append a change to the journal, only returning once it's made
+    /// it to disk.
+    pub fn append(&mut self, entry: &JournalEntry) -> MogResult<()> {
+        try!(self.log.write_all(entry.to_line().as_bytes()));
+        try!(self.log.sync_data());
+        self.appended += 1;
+        Ok(())
+    }
+
+    pub fn needs_compaction(&self) -> bool {
+        self.appended >= self.compact_every
+    }
+
+    /// Replace the snapshot with one made of `entries`, and start a
+    /// new, empty journal.
+    pub fn compact(&mut self, entries: &[JournalEntry]) -> MogResult<()> {
+        let generation = self.generation + 1;
+        let temp_path = self.dir.join(SNAPSHOT_TEMP_FILE);
+
+        {
+            let mut writer = BufWriter::new(try!(File::create(&temp_path)));
+            try!(writeln!(writer, "snapshot generation={}", generation));
+            for entry in entries.iter() {
+                try!(writer.write_all(entry.to_line().as_bytes()));
+            }
+            try!(writer.flush());
+            try!(writer.get_ref().sync_all());
+        }
+
+        let log_path = self.dir.join(log_file_name(generation));
+        let log = try!(OpenOptions::new().create(true).write(true).truncate(true).open(&log_path));
+        try!(fs::rename(&temp_path, self.dir.join(SNAPSHOT_FILE)));
+
+        // Make sure the rename sticks, where the platform lets us.
+        if let Ok(dir) = File::open(&self.dir) {
+            let _ = dir.sync_all();
+        }
+
+        let old_log_path = self.dir.join(log_file_name(self.generation));
+        self.log = log;
+        self.generation = generation;
+        self.appended = 0;
+
+        match fs::remove_file(&old_log_path) {
+            Err(ref e) if e.kind() != ErrorKind::NotFound => {
+                warn!("Error removing old journal {:?}: {}", old_log_path, e);
+            },
+            _ => {},
+        }
+
+        Ok(())
+    }
+}
+
+fn log_file_name(generation: u64) -> String {
+    format!("journal.{}.log", generation)
+}
+
+fn read_snapshot(path: &Path) -> MogResult<(u64, Vec<JournalEntry>)> {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok((0, vec![])),
+        Err(e) => return Err(MogError::from(e)),
+    };
+
+    let mut lines = BufReader::new(file).lines();
+    let header = try!(try!(lines.next().ok_or(bad_entry("(empty snapshot)"))));
+    let generation = try!(header.split("generation=").nth(1)
+                          .and_then(|g| g.trim().parse().ok())
+                          .ok_or(bad_entry(&header)));
+
+    let mut entries = vec![];
+    for line in lines {
+        entries.push(try!(JournalEntry::from_bytes(try!(line).as_bytes())));
+    }
+
+    Ok((generation, entries))
+}
+
+fn read_log(path: &Path) -> MogResult<Vec<JournalEntry>> {
+    let mut file = match File::open(path) {
+        Ok(f) => f,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(MogError::from(e)),
+    };
+
+    let mut bytes = vec![];
+    try!(file.read_to_end(&mut bytes));
+
+    let mut lines: Vec<&[u8]> = bytes.split(|&b| b == b'\n').collect();
+
+    // Every entry ends with a newline, so this is either empty, or a
+    // change that was being written when we crashed, and which never
+    // happened.
+    let last = lines.pop().unwrap_or(&[]);
+    if !last.is_empty() {
+        warn!("Discarding incomplete last entry in journal {:?}", path);
+        let complete_len = (bytes.len() - last.len()) as u64;
+        try!(try!(OpenOptions::new().write(true).open(path)).set_len(complete_len));
+    }
+
+    lines.into_iter().map(|line| JournalEntry::from_bytes(line)).collect()
+}
+
+/// Pull out file content, which is only there when it's kept in
+/// memory.
+fn extract_content<F: Fn() -> MogError>(args: &mut ArgsHash, bad: F) -> MogResult<Option<Vec<u8>>> {
+    match args.extract_optional_string("content") {
+        Some(c) => c.from_base64().map(|c| Some(c)).map_err(|_| bad()),
+        None => Ok(None),
+    }
+}
+
+fn bad_entry(line: &str) -> MogError {
+    MogError::Other("bad_journal".to_string(), Some(format!("Could not read journal entry {:?}", line)))
+}
+
+fn tm_to_arg(tm: &Tm) -> String {
+    let ts = tm.to_timespec();
+    format!("{}.{:09}", ts.sec, ts.nsec)
+}
+
+fn tm_from_arg(arg: &str) -> Option<Tm> {
+    let mut parts = arg.splitn(2, '.');
+    let sec = parts.next().and_then(|s| s.parse().ok());
+    let nsec = parts.next().unwrap_or("0").parse().ok();
+    match (sec, nsec) {
+        (Some(sec), Some(nsec)) => Some(time::at_utc(Timespec::new(sec, nsec))),
+        _ => None,
+    }
+}
+
+fn devids_to_arg(devids: &BTreeSet<u64>) -> String {
+    devids.iter().map(|d| d.to_string()).collect::<Vec<String>>().join(",")
+}
+
+fn devids_from_arg(arg: &str) -> Option<BTreeSet<u64>> {
+    arg.split(',')
+        .filter(|d| !d.is_empty())
+        .map(|d| d.parse().ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use mogilefs_common::FromBytes;
+    use std::fs;
+    use super::*;
+    use super::super::super::test_support::*;
+    use time;
+    use url::Url;
+
+    fn sample_entries() -> Vec<JournalEntry> {
+        let now = time::at_utc(time::get_time());
+        vec![
+            JournalEntry::CreateDomain { domain: "test domain".to_string() },
+            JournalEntry::SetClass {
+                domain: "test domain".to_string(), class: "test_class".to_string(),
+                mindevcount: 2, replpolicy: None, hashtype: Some("MD5".to_string()),
+            },
+            JournalEntry::OpenFile {
+                fid: 12, domain: "test domain".to_string(), key: "test/key&1".to_string(),
+                class: "test_class".to_string(), size: Some(5), created: now,
+                paths: vec![ (1, Url::parse("http://storage/dev1/d/test%20domain/k/test/key%261?fid=12").unwrap()) ],
+            },
+            JournalEntry::StoreContent {
+                fid: Some(12), domain: "test domain".to_string(), key: "test/key&1".to_string(),
+                devids: vec![ 1, 2 ].into_iter().collect(), size: 5, md5: Some("407502b15c9f77c4b31682a981b36cda".to_string()),
+                content: Some(vec![ 0, 1, 2, 255, b'\n' ]), mtime: now,
+            },
+            JournalEntry::StoreContent {
+                fid: None, domain: "test domain".to_string(), key: "test/key&1".to_string(),
+                devids: vec![ 1 ].into_iter().collect(), size: 1024, md5: None, content: None, mtime: now,
+            },
+            JournalEntry::StoreRange {
+                fid: 12, domain: "test domain".to_string(), key: "test/key&1".to_string(),
+                devids: vec![ 1 ].into_iter().collect(), offset: 3, length: 2, total: Some(5), md5: None,
+                content: Some(vec![ 255, b'\n' ]), mtime: now,
+            },
+            JournalEntry::DeleteContent { fid: Some(12), domain: "test domain".to_string(), key: "test/key&1".to_string(), devid: Some(2) },
+            JournalEntry::CloseFile { fid: 12 },
+            JournalEntry::PutFile {
+                fid: 13, domain: "test domain".to_string(), key: "test/key/2".to_string(),
+                class: "default".to_string(), devids: Default::default(), size: None, md5: None, content: None, mtime: None,
+            },
+            JournalEntry::Rename { domain: "test domain".to_string(), from_key: "test/key/2".to_string(), to_key: "test/key/3".to_string() },
+        ]
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        for entry in sample_entries() {
+            let line = entry.to_line();
+            assert_eq!(1, line.lines().count());
+            assert_eq!(entry, JournalEntry::from_bytes(line.as_bytes()).unwrap());
+        }
+    }
+
+    #[test]
+    fn journal_replays_appended_entries() {
+        let dir = temp_data_dir("replay");
+        let entries = sample_entries();
+
+        {
+            let (mut journal, replayed) = Journal::open(&dir).unwrap();
+            assert!(replayed.is_empty());
+            for entry in entries.iter() {
+                journal.append(entry).unwrap();
+            }
+        }
+
+        let (_, replayed) = Journal::open(&dir).unwrap();
+        assert_eq!(entries, replayed);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn journal_replays_after_compaction() {
+        let dir = temp_data_dir("compact");
+        let entries = sample_entries();
+
+        {
+            let (mut journal, _) = Journal::open(&dir).unwrap();
+            journal.set_compact_every(2);
+            journal.append(&entries[0]).unwrap();
+            journal.append(&entries[1]).unwrap();
+            assert!(journal.needs_compaction());
+            journal.compact(&entries[0..2]).unwrap();
+            assert!(!journal.needs_compaction());
+            journal.append(&entries[2]).unwrap();
+        }
+
+        let (_, replayed) = Journal::open(&dir).unwrap();
+        assert_eq!(&entries[0..3], &replayed[..]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn journal_discards_truncated_last_entry() {
+        use std::fs::OpenOptions;
+        use std::io::Write;
+
+        let dir = temp_data_dir("truncated");
+        let entries = sample_entries();
+
+        {
+            let (mut journal, _) = Journal::open(&dir).unwrap();
+            journal.append(&entries[0]).unwrap();
+        }
+
+        {
+            let mut log = OpenOptions::new().append(true).open(dir.join("journal.0.log")).unwrap();
+            log.write_all(b"set_class domain=test+domain&cla").unwrap();
+        }
+
+        {
+            let (mut journal, replayed) = Journal::open(&dir).unwrap();
+            assert_eq!(&entries[0..1], &replayed[..]);
+            journal.append(&entries[1]).unwrap();
+        }
+
+        let (_, replayed) = Journal::open(&dir).unwrap();
+        assert_eq!(&entries[0..2], &replayed[..]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,87 +1,524 @@
 use mogilefs_common::{Backend, MogError, MogResult};
 use mogilefs_common::requests::*;
-use std::collections::HashMap;
-use std::io::{self, Cursor, Read, Write};
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{Cursor, Read};
+use std::path::Path;
 use std::sync::{Arc, RwLock};
-use super::super::backend::{StorageBackend, StorageMetadata};
-use super::{MemDomain, MemFileInfo};
-use time;
+use super::super::backend::{copy_content, fid_path_segments, range_end, too_large, StorageBackend, StorageMetadata, StoragePath};
+use super::super::checksum::{format_checksum, md5_hex, parse_checksum};
+use super::super::signing::UrlSigner;
+use super::journal::{Journal, JournalEntry};
+use super::{DeviceState, HostStatus, MemClass, MemDevice, MemDomain, MemFileInfo, MemHost, MemUpload, DEFAULT_CLASS};
+use time::{self, Duration};
 use url::Url;
 
+/// How many paths `create_open` hands out when asked for multiple
+/// destinations.
+const MULTI_DEST_COUNT: usize = 3;
+
+/// How many paths `get_paths` returns when no `pathcount` is given.
+const DEFAULT_PATHCOUNT: u64 = 2;
+
+/// The most files a single `list_fids` request will return.
+const LIST_FIDS_LIMIT: usize = 10000;
+
+/// How long, in seconds, a file may stay open before it's considered
+/// abandoned, by default.
+pub const DEFAULT_UPLOAD_EXPIRY: i64 = 3600;
+
+/// How the storage URLs the tracker hands out are laid out. The
+/// storage server understands both, whichever is chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UrlScheme {
+    /// `/dev<N>/d/<domain>/k/<key>`, with a `fid` query parameter for
+    /// files which haven't been closed yet.
+    DomainKey,
+    /// mogstored's `/dev<N>/0/000/001/0000001927.fid`.
+    Fid,
+}
+
+/// A file's content, for reading. It's shared with the backend
+/// rather than copied, so serving a file doesn't take as much memory
+/// again as the file.
+#[derive(Debug, Clone)]
+pub struct SharedContent(Arc<Vec<u8>>);
+
+impl AsRef<[u8]> for SharedContent {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
 #[derive(Debug)]
 pub struct MemBackend {
     domains: HashMap<String, MemDomain>,
     empty_domain: MemDomain,
+    hosts: BTreeMap<u64, MemHost>,
+    devices: BTreeMap<u64, MemDevice>,
+    fids: BTreeMap<u64, (String, String)>,
+    uploads: BTreeMap<u64, MemUpload>,
+    upload_expiry: Duration,
+    url_scheme: UrlScheme,
+    url_signer: Option<UrlSigner>,
+    max_file_size: Option<u64>,
+    domain_max_file_sizes: HashMap<String, u64>,
+    next_fid: u64,
+    released_fids: Option<Vec<u64>>,
+    journal: Option<Journal>,
     pub base_url: Url,
 }
 
 impl MemBackend {
     pub fn new(storage_base_url: Url) -> MemBackend {
+        MemBackend::with_devices(storage_base_url, 1)
+    }
+
+    /// Create a backend with `device_count` simulated devices, all on
+    /// a single host standing in for the storage server at the base
+    /// URL.
+    pub fn with_devices(storage_base_url: Url, device_count: u64) -> MemBackend {
+        let mut hosts = BTreeMap::new();
+        hosts.insert(1, MemHost::from_url(1, &storage_base_url));
+        let mut devices = BTreeMap::new();
+        for devid in 1..(device_count + 1) {
+            devices.insert(devid, MemDevice::new(devid, 1));
+        }
+
         MemBackend {
             domains: HashMap::new(),
             empty_domain: MemDomain::new(""),
+            hosts: hosts,
+            devices: devices,
+            fids: BTreeMap::new(),
+            uploads: BTreeMap::new(),
+            upload_expiry: Duration::seconds(DEFAULT_UPLOAD_EXPIRY),
+            url_scheme: UrlScheme::DomainKey,
+            url_signer: None,
+            max_file_size: None,
+            domain_max_file_sizes: HashMap::new(),
+            next_fid: 1,
+            released_fids: None,
+            journal: None,
             base_url: storage_base_url,
         }
     }
 
+    /// Add a domain, with all its files, to the backend, replacing
+    /// any domain already there with the same name.
+    pub fn insert_domain(&mut self, domain: MemDomain) {
+        if let Some(old_domain) = self.domains.remove(domain.name()) {
+            for (_, file_info) in old_domain.files() {
+                self.fids.remove(&file_info.fid());
+            }
+        }
+
+        for (key, file_info) in domain.files() {
+            self.fids.insert(file_info.fid(), (domain.name().to_string(), key.to_string()));
+            if file_info.fid() >= self.next_fid {
+                self.next_fid = file_info.fid() + 1;
+            }
+        }
+
+        self.domains.insert(domain.name().to_string(), domain);
+    }
+
+    /// Look up a file by its fid, returning the name of its domain
+    /// along with it.
+    pub fn file_by_fid(&self, fid: u64) -> Option<(&str, &MemFileInfo)> {
+        self.fids.get(&fid).and_then(|&(ref domain, ref key)| {
+            self.domains.get(domain)
+                .and_then(|d| d.file(key))
+                .map(|f| (domain.as_ref(), f))
+        })
+    }
+
+    pub fn file_info_by_fid(&self, fid: u64) -> MogResult<FileInfoResponse> {
+        self.file_by_fid(fid)
+            .map(|(domain, file_info)| file_info_response(domain, self.domains.get(domain).and_then(|d| d.class(&file_info.class)), file_info))
+            .ok_or(MogError::UnknownFid(fid.to_string()))
+    }
+
+    /// Change the storage base URL, keeping the synthetic host for it
+    /// in step.
+    pub fn set_base_url(&mut self, new_url: Url) {
+        if let Some(host) = self.hosts.get_mut(&1) {
+            let new_host = MemHost::from_url(1, &new_url);
+            host.hostname = new_host.hostname;
+            host.ip = new_host.ip;
+            host.http_port = new_host.http_port;
+        }
+        self.base_url = new_url;
+    }
+
+    /// Set how long a file may stay open between `create_open` and
+    /// `create_close` before it's abandoned.
+    pub fn set_upload_expiry(&mut self, expiry: Duration) {
+        self.upload_expiry = expiry;
+    }
+
+    /// Set how the storage URLs handed out from now on are laid out.
+    pub fn set_url_scheme(&mut self, scheme: UrlScheme) {
+        self.url_scheme = scheme;
+    }
+
+    /// Set the signer for the paths `create_open` hands out, so that
+    /// only the client that opened a file can store its content. With
+    /// no signer, the paths aren't signed.
+    pub fn set_url_signer(&mut self, signer: Option<UrlSigner>) {
+        self.url_signer = signer;
+    }
+
+    /// Set the largest file, in bytes, which may be stored in a
+    /// domain without a limit of its own. `None` means no limit.
+    pub fn set_max_file_size(&mut self, max: Option<u64>) {
+        self.max_file_size = max;
+    }
+
+    /// Set the largest file, in bytes, which may be stored in a
+    /// particular domain, whether or not it exists yet. `None` goes
+    /// back to the limit for every other domain.
+    pub fn set_domain_max_file_size(&mut self, domain: &str, max: Option<u64>) {
+        match max {
+            Some(max) => self.domain_max_file_sizes.insert(domain.to_string(), max),
+            None => self.domain_max_file_sizes.remove(domain),
+        };
+    }
+
+    /// The largest file, in bytes, which may be stored in a domain.
+    pub fn max_file_size(&self, domain: &str) -> Option<u64> {
+        self.domain_max_file_sizes.get(domain).cloned().or(self.max_file_size)
+    }
+
+    /// Forget about every open file that has expired, returning how
+    /// many there were.
+    pub fn reap_uploads(&mut self) -> MogResult<usize> {
+        let now = time::now_utc();
+        let expiry = self.upload_expiry;
+        let expired: Vec<u64> = self.uploads.iter()
+            .filter(|&(_, u)| u.created + expiry <= now)
+            .map(|(&fid, _)| fid)
+            .collect();
+
+        for &fid in expired.iter() {
+            try!(self.commit(JournalEntry::AbandonFile { fid: fid }));
+        }
+
+        Ok(expired.len())
+    }
+
+    /// Restore the domains and files recorded in the data directory
+    /// `dir`, and record every change made from now on there too, so
+    /// that they survive a restart.
+    pub fn open_data_dir<P: AsRef<Path>>(&mut self, dir: P) -> MogResult<()> {
+        let (journal, entries) = try!(Journal::open(dir));
+        let count = entries.len();
+
+        for entry in entries {
+            let op = entry.op();
+            if let Err(e) = self.apply(entry) {
+                // It would have failed the same way when it was first
+                // made, so there's nothing to undo.
+                warn!("Error replaying {} from the journal: {}", op, e);
+            }
+        }
+
+        info!("Replayed {} change(s) from the journal", count);
+        self.journal = Some(journal);
+        self.compact_journal()
+    }
+
+    /// Replace the data directory's journal with a snapshot of the
+    /// backend as it is now.
+    pub fn compact_journal(&mut self) -> MogResult<()> {
+        let entries = self.snapshot_entries();
+        match self.journal {
+            Some(ref mut journal) => journal.compact(&entries),
+            None => Ok(()),
+        }
+    }
+
+    /// Change the state of a device. Marking a device dead loses the
+    /// copies of files on it, as in MogileFS.
+    pub fn set_device_state(&mut self, devid: u64, state: DeviceState) -> MogResult<()> {
+        {
+            let device = try!(self.devices.get_mut(&devid).ok_or(MogError::UnknownDevice(devid.to_string())));
+
+            // Once a device is dead, it stays dead.
+            if device.state == DeviceState::Dead && state != DeviceState::Dead {
+                return Err(MogError::StateTooHigh);
+            }
+
+            device.state = state;
+        }
+
+        if state == DeviceState::Dead {
+            for domain in self.domains.values_mut() {
+                domain.drop_device(devid);
+            }
+        }
+
+        Ok(())
+    }
+
     // Tracker methods.
 
     pub fn create_domain(&mut self, req: &CreateDomain) -> MogResult<CreateDomain> {
         if self.domains.contains_key(&req.domain) {
             Err(MogError::DomainExists(req.domain.clone()))
         } else {
-            let domain = MemDomain::new(&req.domain);
-            self.domains.insert(req.domain.clone(), domain);
+            try!(self.commit(JournalEntry::CreateDomain { domain: req.domain.clone() }));
             Ok(CreateDomain { domain: req.domain.clone() })
         }
     }
 
-    pub fn create_open(&mut self, req: &CreateOpen) -> MogResult<CreateOpenResponse> {
-        let fid = self.domains.len() + 1;
-        let url = self.url_for_key(&req.domain, &req.key);
-        let domain = try!(self.domain_mut(&req.domain));
-        let file_info = MemFileInfo::new(fid as u64, &req.key);
-        try!(domain.add_file(&req.key, file_info));
-
-        let mut response = CreateOpenResponse {
-            fid: fid as u64,
-            paths: Vec::new(),
+    fn delete_domain(&mut self, req: &DeleteDomain) -> MogResult<DeleteDomain> {
+        match self.domains.get(&req.domain) {
+            None => return Err(MogError::DomainNotFound(req.domain.clone())),
+            Some(d) if !d.is_empty() => return Err(MogError::DomainNotEmpty(req.domain.clone())),
+            _ => {},
+        }
+
+        try!(self.commit(JournalEntry::DeleteDomain { domain: req.domain.clone() }));
+        Ok(DeleteDomain { domain: req.domain.clone() })
+    }
+
+    fn get_domains(&self, _req: &GetDomains) -> MogResult<GetDomainsResponse> {
+        let mut domains: Vec<&MemDomain> = self.domains.values().collect();
+        domains.sort_by(|a, b| a.name().cmp(b.name()));
+
+        Ok(GetDomainsResponse(domains.into_iter().map(|domain| {
+            DomainInfo {
+                name: domain.name().to_string(),
+                classes: domain.classes().map(|class| {
+                    ClassInfo {
+                        name: class.name().to_string(),
+                        mindevcount: class.mindevcount,
+                        replpolicy: class.replpolicy.clone(),
+                        hashtype: class.hashtype.clone(),
+                    }
+                }).collect(),
+            }
+        }).collect()))
+    }
+
+    fn create_class(&mut self, req: &CreateClass) -> MogResult<CreateClassResponse> {
+        if req.mindevcount < 1 {
+            return Err(MogError::InvalidMindevcount);
+        }
+
+        let class = {
+            let domain = try!(self.domain(&req.domain));
+            if req.update {
+                // Only the arguments that were given are changed.
+                let mut class = try!(domain.class(&req.class).ok_or(MogError::ClassNotFound(req.class.clone()))).clone();
+                class.mindevcount = req.mindevcount;
+                if let Some(ref replpolicy) = req.replpolicy {
+                    class.replpolicy = Some(replpolicy.clone());
+                }
+                if let Some(ref hashtype) = req.hashtype {
+                    class.set_hashtype(hashtype);
+                }
+                class
+            } else if domain.class(&req.class).is_some() {
+                return Err(MogError::ClassExists(req.class.clone()));
+            } else {
+                let mut class = MemClass::new(&req.class, req.mindevcount);
+                class.replpolicy = req.replpolicy.clone();
+                if let Some(ref hashtype) = req.hashtype {
+                    class.set_hashtype(hashtype);
+                }
+                class
+            }
         };
-        response.paths.push((1, url));
-        Ok(response)
+
+        try!(self.commit(JournalEntry::SetClass {
+            domain: req.domain.clone(),
+            class: class.name().to_string(),
+            mindevcount: class.mindevcount,
+            replpolicy: class.replpolicy.clone(),
+            hashtype: class.hashtype.clone(),
+        }));
+
+        Ok(CreateClassResponse {
+            domain: req.domain.clone(),
+            class: class.name().to_string(),
+            mindevcount: class.mindevcount,
+        })
+    }
+
+    fn delete_class(&mut self, req: &DeleteClass) -> MogResult<DeleteClass> {
+        {
+            let domain = try!(self.domains.get(&req.domain).ok_or(MogError::DomainNotFound(req.domain.clone())));
+            try!(domain.check_remove_class(&req.class));
+        }
+
+        try!(self.commit(JournalEntry::DeleteClass { domain: req.domain.clone(), class: req.class.clone() }));
+        Ok(DeleteClass { domain: req.domain.clone(), class: req.class.clone() })
+    }
+
+    pub fn create_open(&mut self, req: &CreateOpen) -> MogResult<CreateOpenResponse> {
+        let dest_count = if req.multi_dest { MULTI_DEST_COUNT } else { 1 };
+        let devids: Vec<u64> = self.writeable_devices().into_iter().take(dest_count).collect();
+
+        if devids.is_empty() {
+            return Err(MogError::Other("no_devices".to_string(), Some("No devices found to store file".to_string())));
+        }
+
+        let class = req.class.as_ref().map(|c| c.as_ref()).unwrap_or(DEFAULT_CLASS);
+        if try!(self.domain(&req.domain)).class(class).is_none() {
+            return Err(MogError::UnregClass(class.to_string()));
+        }
+
+        match (req.size, self.max_file_size(&req.domain)) {
+            (Some(size), Some(max)) if size > max => {
+                return Err(MogError::Other("too_large".to_string(), Some(format!("Files in domain {:?} may not be larger than {} bytes", req.domain, max))));
+            },
+            _ => {},
+        }
+
+        // Fids are never reused, even after the file they belonged to
+        // is deleted or replaced.
+        let fid = self.next_fid;
+        let created = time::now_utc();
+        let expires = (created + self.upload_expiry).to_timespec().sec;
+
+        let paths: Vec<(u64, Url)> = devids.into_iter()
+            .map(|devid| {
+                let mut url = self.device_url_for_fid(devid, fid, &req.domain, &req.key);
+                if let Some(ref signer) = self.url_signer {
+                    signer.sign_store_url(&mut url, fid, &req.domain, &req.key, expires);
+                }
+                (devid, url)
+            })
+            .collect();
+
+        // The file doesn't go in to the domain until it's closed.
+        try!(self.commit(JournalEntry::OpenFile {
+            fid: fid,
+            domain: req.domain.clone(),
+            key: req.key.clone(),
+            class: class.to_string(),
+            size: req.size,
+            paths: paths.clone(),
+            created: created,
+        }));
+
+        Ok(CreateOpenResponse {
+            fid: fid,
+            paths: paths,
+        })
+    }
+
+    pub fn create_close(&mut self, req: &CreateClose) -> MogResult<()> {
+        {
+            let upload = try!(self.uploads.get(&req.fid).ok_or(
+                MogError::Other("no_temp_file".to_string(), Some(format!("No open file with fid {}", req.fid)))));
+
+            // It may not have been reaped yet, but it's still too
+            // late.
+            if upload.created + self.upload_expiry <= time::now_utc() {
+                return Err(MogError::Other("upload_expired".to_string(), Some(format!("Fid {} was open for too long", req.fid))));
+            }
+
+            if upload.domain != req.domain || upload.file.key() != req.key {
+                return Err(MogError::Other("key_mismatch".to_string(), Some(format!("Fid {} was not opened for that domain and key", req.fid))));
+            }
+
+            match upload.paths.iter().find(|&&(devid, _)| devid == req.devid) {
+                None => {
+                    return Err(MogError::Other("invalid_destdev".to_string(), Some(format!("Device {} was not a destination for fid {}", req.devid, req.fid))));
+                },
+                Some(&(_, ref path)) if path != &req.path => {
+                    return Err(MogError::Other("invalid_path".to_string(), Some(format!("Path {} was not a destination for fid {}", req.path, req.fid))));
+                },
+                _ => {},
+            }
+
+            if !upload.is_contiguous() {
+                return Err(MogError::Other("incomplete_upload".to_string(), Some(format!("Fid {} is missing some of its content", req.fid))));
+            }
+
+            match (upload.file.size, upload.declared_size) {
+                (None, _) => {
+                    return Err(MogError::NoContent(req.key.clone()));
+                },
+                (Some(size), Some(declared)) if size != declared => {
+                    return Err(MogError::Other("size_mismatch".to_string(), Some(format!("Fid {} was {} bytes, not the {} bytes promised", req.fid, size, declared))));
+                },
+                _ => {},
+            }
+
+            if let Some(ref checksum) = req.checksum {
+                let expected = try!(parse_checksum(checksum));
+                if upload.file.md5.as_ref() != Some(&expected) {
+                    return Err(MogError::Other("checksum_mismatch".to_string(), Some(format!("Fid {} does not have checksum {}", req.fid, checksum))));
+                }
+            }
+        }
+
+        self.commit(JournalEntry::CloseFile { fid: req.fid })
     }
 
     fn get_paths(&self, req: &GetPaths) -> MogResult<GetPathsResponse> {
-        let paths = try!(self.domain(&req.domain)
-                         .and_then(|d| d.file(&req.key).ok_or(MogError::UnknownKey(req.key.clone())))
-                         .map(|_| vec![ self.url_for_key(&req.domain, &req.key) ]));
-        Ok(GetPathsResponse(paths))
+        let file_info = try!(try!(self.file(&req.domain, &req.key)).ok_or(MogError::UnknownKey(req.key.clone())));
+        let pathcount = req.pathcount.unwrap_or(DEFAULT_PATHCOUNT);
+        Ok(GetPathsResponse(file_info.devids.iter()
+                            .filter(|&&devid| self.device_is_readable(devid))
+                            .take(pathcount as usize)
+                            .map(|&devid| self.device_url_for_file(devid, file_info.fid(), &req.domain, &req.key))
+                            .collect()))
     }
     
     fn file_info(&self, req: &FileInfo) -> MogResult<FileInfoResponse> {
-        self.domain(&req.domain)
-            .and_then(|d| d.file(&req.key).ok_or(MogError::UnknownKey(req.key.clone())))
-            .map(|file_info| {
-                FileInfoResponse {
-                    fid: file_info.fid(),
-                    devcount: 1,
-                    length: file_info.size.unwrap_or(0),
-                    domain: req.domain.clone(),
-                    class: "default".to_string(),
-                    key: file_info.key().to_string(),
-                }
-            })
+        let domain = try!(self.domain(&req.domain));
+        domain.file(&req.key)
+            .map(|file_info| file_info_response(&req.domain, domain.class(&file_info.class), file_info))
+            .ok_or(MogError::UnknownKey(req.key.clone()))
     }
     
+    fn update_class(&mut self, req: &UpdateClass) -> MogResult<()> {
+        {
+            let domain = try!(self.domain(&req.domain));
+
+            if domain.class(&req.new_class).is_none() {
+                return Err(MogError::UnregClass(req.new_class.clone()));
+            } else if domain.file(&req.key).is_none() {
+                return Err(MogError::UnknownKey(req.key.clone()));
+            }
+        }
+
+        self.commit(JournalEntry::UpdateClass {
+            domain: req.domain.clone(),
+            key: req.key.clone(),
+            class: req.new_class.clone(),
+        })
+    }
+
     fn delete(&mut self, req: &Delete) -> MogResult<()> {
-        try!(self.domain_mut(&req.domain))
-            .remove_file(&req.key)
-            .map(|_| ())
-            .ok_or(MogError::UnknownKey(req.key.clone()))
+        if try!(self.file(&req.domain, &req.key)).is_none() {
+            return Err(MogError::UnknownKey(req.key.clone()));
+        }
+
+        self.commit(JournalEntry::Delete { domain: req.domain.clone(), key: req.key.clone() })
     }
 
     fn rename(&mut self, req: &Rename) -> MogResult<()> {
-        self.domain_mut(&req.domain).and_then(|d| d.rename(&req.from_key, &req.to_key))
+        {
+            let domain = try!(self.domain(&req.domain));
+
+            if domain.file(&req.to_key).is_some() {
+                return Err(MogError::KeyExists(req.to_key.clone()));
+            } else if domain.file(&req.from_key).is_none() {
+                return Err(MogError::UnknownKey(req.from_key.clone()));
+            }
+        }
+
+        self.commit(JournalEntry::Rename {
+            domain: req.domain.clone(),
+            from_key: req.from_key.clone(),
+            to_key: req.to_key.clone(),
+        })
     }
 
     fn list_keys(&self, req: &ListKeys) -> MogResult<ListKeysResponse> {
@@ -96,84 +533,940 @@ impl MemBackend {
                             .collect()))
     }
 
+    fn get_hosts(&self, req: &GetHosts) -> MogResult<GetHostsResponse> {
+        Ok(GetHostsResponse(self.hosts.values()
+                            .filter(|h| req.hostid.map(|id| id == h.hostid()).unwrap_or(true))
+                            .map(|host| {
+                                HostInfo {
+                                    hostid: host.hostid(),
+                                    hostname: host.hostname.clone(),
+                                    hostip: host.ip.clone(),
+                                    http_port: host.http_port,
+                                    http_get_port: host.http_get_port,
+                                    altip: host.altip.clone(),
+                                    altmask: host.altmask.clone(),
+                                    status: host.status.as_str().to_string(),
+                                }
+                            })
+                            .collect()))
+    }
+
+    fn get_devices(&self, req: &GetDevices) -> MogResult<GetDevicesResponse> {
+        Ok(GetDevicesResponse(self.devices.values()
+                              .filter(|d| req.devid.map(|id| id == d.devid()).unwrap_or(true))
+                              .map(|device| {
+                                  DeviceInfo {
+                                      devid: device.devid(),
+                                      hostid: device.hostid(),
+                                      status: device.state.as_str().to_string(),
+                                      observed_state: Some(device.state.observed_state().to_string()),
+                                      weight: Some(device.weight),
+                                      mb_total: None,
+                                      mb_used: None,
+                                  }
+                              })
+                              .collect()))
+    }
+
+    fn create_host(&mut self, req: &CreateHost) -> MogResult<CreateHostResponse> {
+        if self.host_by_name(&req.host).is_some() {
+            return Err(MogError::HostExists(req.host.clone()));
+        }
+
+        let status = match req.status {
+            Some(ref s) => try!(s.parse()),
+            None => HostStatus::Down,
+        };
+
+        let hostid = self.hosts.keys().next_back().map(|id| id + 1).unwrap_or(1);
+        let mut host = MemHost::new(hostid, &req.host, &req.ip, req.port);
+        host.http_get_port = req.getport;
+        host.altip = req.altip.clone();
+        host.altmask = req.altmask.clone();
+        host.status = status;
+        self.hosts.insert(hostid, host);
+
+        Ok(CreateHostResponse { hostid: hostid, hostname: req.host.clone() })
+    }
+
+    fn update_host(&mut self, req: &UpdateHost) -> MogResult<CreateHostResponse> {
+        let status = match req.status {
+            Some(ref s) => Some(try!(s.parse())),
+            None => None,
+        };
+
+        let host = try!(self.host_by_name_mut(&req.host).ok_or(MogError::UnknownHost(req.host.clone())));
+
+        // Only the arguments that were given are changed.
+        if let Some(ref ip) = req.ip {
+            host.ip = ip.clone();
+        }
+        if let Some(port) = req.port {
+            host.http_port = port;
+        }
+        if let Some(getport) = req.getport {
+            host.http_get_port = Some(getport);
+        }
+        if let Some(ref altip) = req.altip {
+            host.altip = Some(altip.clone());
+        }
+        if let Some(ref altmask) = req.altmask {
+            host.altmask = Some(altmask.clone());
+        }
+        if let Some(status) = status {
+            host.status = status;
+        }
+
+        Ok(CreateHostResponse { hostid: host.hostid(), hostname: host.hostname.clone() })
+    }
+
+    fn delete_host(&mut self, req: &DeleteHost) -> MogResult<()> {
+        let hostid = try!(self.host_by_name(&req.host).map(|h| h.hostid()).ok_or(MogError::UnknownHost(req.host.clone())));
+
+        if self.devices.values().any(|d| d.hostid() == hostid) {
+            return Err(MogError::HostNotEmpty(req.host.clone()));
+        }
+
+        self.hosts.remove(&hostid);
+        Ok(())
+    }
+
+    fn create_device(&mut self, req: &CreateDevice) -> MogResult<()> {
+        let hostid = try!(self.host_by_name(&req.hostname).map(|h| h.hostid()).ok_or(MogError::UnknownHost(req.hostname.clone())));
+
+        if self.devices.contains_key(&req.devid) {
+            return Err(MogError::DeviceExists(req.devid.to_string()));
+        }
+
+        let mut device = MemDevice::new(req.devid, hostid);
+        if let Some(ref state) = req.state {
+            device.state = try!(state.parse());
+        }
+        self.devices.insert(req.devid, device);
+        Ok(())
+    }
+
+    fn set_state(&mut self, req: &SetState) -> MogResult<()> {
+        let state = try!(req.state.parse());
+        try!(self.host_device_mut(&req.host, req.device));
+        self.set_device_state(req.device, state)
+    }
+
+    fn set_weight(&mut self, req: &SetWeight) -> MogResult<()> {
+        let device = try!(self.host_device_mut(&req.host, req.device));
+        device.weight = req.weight;
+        Ok(())
+    }
+
+    fn list_fids(&self, req: &ListFids) -> MogResult<ListFidsResponse> {
+        if req.from > req.to {
+            return Ok(ListFidsResponse(vec![]));
+        }
+
+        // `to` is inclusive, and may be as big as a fid can be.
+        let to = req.to;
+        Ok(ListFidsResponse(self.fids.range(req.from..)
+                            .take_while(|&(&fid, _)| fid <= to)
+                            .filter_map(|(&fid, _)| self.file_by_fid(fid))
+                            .take(LIST_FIDS_LIMIT)
+                            .map(|(domain, file_info)| {
+                                FidInfo {
+                                    fid: file_info.fid(),
+                                    domain: domain.to_string(),
+                                    key: file_info.key().to_string(),
+                                    class: file_info.class.clone(),
+                                    length: file_info.size.unwrap_or(0),
+                                    devcount: file_info.devids.len() as u64,
+                                }
+                            })
+                            .collect()))
+    }
+
+    fn file_debug(&self, req: &FileDebug) -> MogResult<FileDebugResponse> {
+        let (domain, file_info) = match (req.fid, &req.domain, &req.key) {
+            (Some(fid), _, _) => {
+                try!(self.file_by_fid(fid).ok_or(MogError::UnknownFid(fid.to_string())))
+            },
+            (None, &Some(ref domain), &Some(ref key)) => {
+                let file_info = try!(try!(self.file(domain, key)).ok_or(MogError::UnknownKey(key.clone())));
+                (domain.as_ref(), file_info)
+            },
+            _ => return Err(MogError::NoFid),
+        };
+
+        Ok(FileDebugResponse {
+            fid: file_info.fid(),
+            domain: domain.to_string(),
+            key: file_info.key().to_string(),
+            class: file_info.class.clone(),
+            length: file_info.size.unwrap_or(0),
+            devcount: file_info.devids.len() as u64,
+            devids: file_info.devids.iter().cloned().collect(),
+        })
+    }
+
     // Storage server methods.
 
     pub fn url_for_key(&self, domain: &str, key: &str) -> Url {
         url_for_key(&self.base_url, domain, key)
     }
 
-    pub fn file_metadata(&self, domain: &str, key: &str) -> MogResult<StorageMetadata> {
-        let file_info = try!(try!(self.file(domain, key)).ok_or(MogError::UnknownKey(key.to_string())));
+    pub fn device_url_for_key(&self, devid: u64, domain: &str, key: &str) -> Url {
+        url_for_device_key(&self.base_url, devid, domain, key)
+    }
+
+    /// The URL a particular version of a file, such as one that's
+    /// still being uploaded, is stored at on a device.
+    pub fn device_url_for_fid(&self, devid: u64, fid: u64, domain: &str, key: &str) -> Url {
+        match self.url_scheme {
+            UrlScheme::DomainKey => {
+                let mut url = self.device_url_for_key(devid, domain, key);
+                url.query_pairs_mut().append_pair("fid", &fid.to_string());
+                url
+            },
+            UrlScheme::Fid => url_for_device_fid(&self.base_url, devid, fid),
+        }
+    }
+
+    /// The URL a file is read from on a device.
+    pub fn device_url_for_file(&self, devid: u64, fid: u64, domain: &str, key: &str) -> Url {
+        match self.url_scheme {
+            UrlScheme::DomainKey => self.device_url_for_key(devid, domain, key),
+            UrlScheme::Fid => url_for_device_fid(&self.base_url, devid, fid),
+        }
+    }
+
+    pub fn fid_storage_path(&self, devid: Option<u64>, fid: u64) -> MogResult<StoragePath> {
+        let (domain, key) = match self.uploads.get(&fid) {
+            Some(upload) => (upload.domain.clone(), upload.file.key().to_string()),
+            None => try!(self.fids.get(&fid).cloned().ok_or(MogError::UnknownFid(fid.to_string()))),
+        };
+
+        Ok(StoragePath { devid: devid, fid: Some(fid), domain: domain, key: key })
+    }
+
+    pub fn file_metadata(&self, path: &StoragePath) -> MogResult<StorageMetadata> {
+        let file_info = try!(self.readable_file(path));
 
         match (file_info.size, file_info.mtime) {
             (Some(size), Some(mtime)) => {
-                Ok(StorageMetadata { size: size, mtime: mtime, })
+                let mtime_spec = mtime.to_timespec();
+                Ok(StorageMetadata {
+                    size: size,
+                    mtime: mtime,
+                    etag: format!("{}-{}.{:09}", file_info.fid(), mtime_spec.sec, mtime_spec.nsec),
+                    md5: file_info.md5.clone(),
+                })
             },
             _ => {
-                Err(MogError::NoContent(key.to_string()))
+                Err(MogError::NoContent(path.key.clone()))
             }
         }
     }
 
-    pub fn store_reader_content<R: Read>(&mut self, domain: &str, key: &str, reader: &mut R) -> MogResult<()> {
+    /// The most content, in bytes, which may be stored for the file a
+    /// storage request refers to: no more than it was opened with, or
+    /// than its domain allows.
+    pub fn content_limit(&self, path: &StoragePath) -> MogResult<Option<u64>> {
+        try!(self.storage_file(path));
+        let declared_size = path.fid
+            .and_then(|fid| self.uploads.get(&fid))
+            .and_then(|upload| upload.declared_size);
+
+        Ok(match (declared_size, self.max_file_size(&path.domain)) {
+            (Some(declared), Some(max)) => Some(cmp::min(declared, max)),
+            (declared, max) => declared.or(max),
+        })
+    }
+
+    pub fn store_reader_content<R: Read>(&mut self, path: &StoragePath, reader: &mut R) -> MogResult<()> {
+        let limit = try!(self.content_limit(path));
         let mut content = vec![];
-        try!(io::copy(reader, &mut content));
-        self.store_bytes_content(domain, key, &content)
+        try!(copy_content(path, reader, &mut content, limit));
+        self.store_bytes_content(path, &content)
     }
 
-    pub fn store_bytes_content(&mut self, domain: &str, key: &str, content: &[u8]) -> MogResult<()> {
-        let file_info = try!(try!(self.file_mut(domain, key)).ok_or(MogError::UnknownKey(key.to_string())));
-        file_info.size = Some(content.len() as u64);
-        file_info.content = Some(content.to_owned());
-        file_info.mtime = Some(time::now_utc());
-        Ok(())
+    pub fn store_bytes_content(&mut self, path: &StoragePath, content: &[u8]) -> MogResult<()> {
+        match try!(self.content_limit(path)) {
+            Some(limit) if content.len() as u64 > limit => return Err(too_large(path, limit)),
+            _ => {},
+        }
+
+        let devids = try!(self.content_devids(path));
+        self.commit(JournalEntry::StoreContent {
+            fid: path.fid,
+            domain: path.domain.clone(),
+            key: path.key.clone(),
+            devids: devids.into_iter().collect(),
+            size: content.len() as u64,
+            md5: Some(md5_hex(content)),
+            content: Some(content.to_owned()),
+            mtime: time::now_utc(),
+        })
     }
 
-    pub fn get_content<W: Write>(&self, domain: &str, key: &str, writer: &mut W) -> MogResult<()> {
-        let file_info = try!(try!(self.file(domain, key)).ok_or(MogError::UnknownKey(key.to_string())));
-        match file_info.content {
-            Some(ref reader) => {
-                try!(io::copy(&mut Cursor::new(reader), writer));
-                Ok(())
-            },
-            None => {
-                Err(MogError::NoContent(key.to_string()))
+    /// Remove the copy of the content for the file a storage request
+    /// refers to from the device it was addressed to, or every copy
+    /// if it wasn't addressed to one. The file itself is left alone.
+    pub fn delete_content(&mut self, path: &StoragePath) -> MogResult<()> {
+        {
+            let file_info = try!(self.storage_file(path));
+            let has_copy = match path.devid {
+                Some(devid) => file_info.devids.contains(&devid),
+                None => !file_info.devids.is_empty(),
+            };
+
+            if !has_copy {
+                return Err(MogError::NoContent(path.key.clone()));
             }
         }
+
+        self.commit(JournalEntry::DeleteContent {
+            fid: path.fid,
+            domain: path.domain.clone(),
+            key: path.key.clone(),
+            devid: path.devid,
+        })
     }
 
-    // Utility methods.
+    /// Remove the file a storage request refers to altogether, as if
+    /// it had been deleted through the tracker.
+    pub fn delete_file(&mut self, path: &StoragePath) -> MogResult<()> {
+        let fid = try!(self.storage_file(path)).fid();
 
-    fn file(&self, domain: &str, key: &str) -> MogResult<Option<&MemFileInfo>> {
-        self.domain(domain).map(|d| d.file(key))
+        if self.uploads.contains_key(&fid) {
+            self.commit(JournalEntry::AbandonFile { fid: fid })
+        } else {
+            self.commit(JournalEntry::Delete { domain: path.domain.clone(), key: path.key.clone() })
+        }
     }
 
-    fn file_mut(&mut self, domain: &str, key: &str) -> MogResult<Option<&mut MemFileInfo>> {
-        self.domain_mut(domain).map(|d| d.file_mut(key))
+    /// Record that `size` bytes of content, with the hex MD5 digest
+    /// `md5`, have been stored somewhere else, such as on disk, for
+    /// the file a storage request refers to.
+    pub fn store_content_size(&mut self, path: &StoragePath, size: u64, md5: &str) -> MogResult<()> {
+        let devids = try!(self.content_devids(path));
+        self.commit(JournalEntry::StoreContent {
+            fid: path.fid,
+            domain: path.domain.clone(),
+            key: path.key.clone(),
+            devids: devids.into_iter().collect(),
+            size: size,
+            md5: Some(md5.to_string()),
+            content: None,
+            mtime: time::now_utc(),
+        })
     }
 
-    fn domain(&self, domain_name: &str) -> MogResult<&MemDomain> {
-        // self.domains.get(domain_name).ok_or(MogError::UnregDomain(domain_name.to_string()))
-        Ok(self.domains.get(domain_name).unwrap_or(&self.empty_domain))
+    /// The most content, in bytes, which may be stored starting
+    /// `offset` bytes in to the file a storage request refers to,
+    /// which must still be open. `total` is how long the client says
+    /// the whole content will be, if it does, but the tracker has to
+    /// know how big the file may be as well: the size it was declared
+    /// to be when it was opened, or the most its domain allows.
+    pub fn range_limit(&self, path: &StoragePath, offset: u64, total: Option<u64>) -> MogResult<Option<u64>> {
+        let declared_size = try!(self.open_upload(path)).declared_size;
+        match (total, declared_size) {
+            (Some(total), Some(declared)) if total != declared => {
+                return Err(MogError::Other("size_mismatch".to_string(), Some(format!("{:?} is {} bytes, not {}", path.key, declared, total))));
+            },
+            _ => {},
+        }
+
+        // Otherwise there'd be nothing but the client's say-so to stop
+        // a range starting however far in it likes.
+        let limit = match (try!(self.content_limit(path)), total) {
+            (Some(limit), Some(total)) => cmp::min(limit, total),
+            (Some(limit), None) => limit,
+            (None, _) => {
+                return Err(MogError::Other("size_unknown".to_string(), Some(format!("The size of {:?} must be known to store a range of it", path.key))));
+            },
+        };
+
+        if offset > limit {
+            Err(too_large(path, limit))
+        } else {
+            Ok(Some(limit - offset))
+        }
     }
 
-    fn domain_mut(&mut self, domain_name: &str) -> MogResult<&mut MemDomain> {
-        // self.domains.get_mut(domain_name).ok_or(MogError::UnregDomain(domain_name.to_string()))
-        Ok(self.domains.entry(domain_name.to_string()).or_insert(MemDomain::new(domain_name)))
+    /// Whether storing `length` bytes, `offset` bytes in to the file a
+    /// storage request refers to, would fill in the last of the gaps
+    /// in its content.
+    pub fn range_completes(&self, path: &StoragePath, offset: u64, length: u64) -> MogResult<bool> {
+        let end = try!(range_end(path, offset, length));
+        self.open_upload(path).map(|upload| upload.completed_by(offset, end))
     }
-}
 
-#[derive(Clone, Debug)]
-pub struct SyncMemBackend(Arc<RwLock<MemBackend>>, Url);
+    pub fn store_reader_range<R: Read>(&mut self, path: &StoragePath, reader: &mut R, offset: u64, total: Option<u64>) -> MogResult<()> {
+        let limit = try!(self.range_limit(path, offset, total));
+        let mut content = vec![];
+        try!(copy_content(path, reader, &mut content, limit));
+        self.store_bytes_range(path, offset, &content, total)
+    }
 
-impl SyncMemBackend {
-    pub fn new(backend: MemBackend) -> SyncMemBackend {
-        let base_url = backend.base_url.clone();
-        SyncMemBackend(Arc::new(RwLock::new(backend)), base_url)
+    /// Store part of the content of the file a storage request refers
+    /// to, which must still be open, starting `offset` bytes in.
+    pub fn store_bytes_range(&mut self, path: &StoragePath, offset: u64, content: &[u8], total: Option<u64>) -> MogResult<()> {
+        match try!(self.range_limit(path, offset, total)) {
+            Some(limit) if content.len() as u64 > limit => return Err(too_large(path, offset + limit)),
+            _ => {},
+        }
+
+        let devids = try!(self.content_devids(path));
+        self.commit(JournalEntry::StoreRange {
+            fid: path.fid.unwrap(),
+            domain: path.domain.clone(),
+            key: path.key.clone(),
+            devids: devids.into_iter().collect(),
+            offset: offset,
+            length: content.len() as u64,
+            total: total,
+            md5: None,
+            content: Some(content.to_owned()),
+            mtime: time::now_utc(),
+        })
     }
 
-    pub fn with_file<F>(&self, domain: &str, key: &str, block: F) -> MogResult<()>
-        where F: FnOnce(&MemFileInfo) -> MogResult<()>
+    /// Record that `length` bytes of content have been stored
+    /// somewhere else, such as on disk, `offset` bytes in to the file
+    /// a storage request refers to. `md5` is the digest of the whole
+    /// content, if this was the last of it.
+    pub fn store_range_size(&mut self, path: &StoragePath, offset: u64, length: u64, total: Option<u64>, md5: Option<String>) -> MogResult<()> {
+        try!(self.range_limit(path, offset, total));
+        let devids = try!(self.content_devids(path));
+        self.commit(JournalEntry::StoreRange {
+            fid: path.fid.unwrap(),
+            domain: path.domain.clone(),
+            key: path.key.clone(),
+            devids: devids.into_iter().collect(),
+            offset: offset,
+            length: length,
+            total: total,
+            md5: md5,
+            content: None,
+            mtime: time::now_utc(),
+        })
+    }
+
+    /// Whether the file a storage request refers to is still open,
+    /// and hasn't been open for too long, so that content may be
+    /// stored for it.
+    pub fn is_open_upload(&self, path: &StoragePath) -> MogResult<bool> {
+        try!(self.storage_file(path));
+        let now = time::now_utc();
+        Ok(path.fid
+           .and_then(|fid| self.uploads.get(&fid))
+           .map(|upload| upload.created + self.upload_expiry > now)
+           .unwrap_or(false))
+    }
+
+    /// The fid of the file a storage request would store content
+    /// for, if it's allowed to.
+    pub fn writeable_fid(&self, path: &StoragePath) -> MogResult<u64> {
+        try!(self.content_devids(path));
+        self.storage_file(path).map(|f| f.fid())
+    }
+
+    /// The fid of the file a storage request would read content from,
+    /// if there's a readable copy of it.
+    pub fn readable_fid(&self, path: &StoragePath) -> MogResult<u64> {
+        self.readable_file(path).map(|f| f.fid())
+    }
+
+    /// Whether a fid belongs to a file, whether or not it's been
+    /// closed yet.
+    pub fn has_fid(&self, fid: u64) -> bool {
+        self.fids.contains_key(&fid) || self.uploads.contains_key(&fid)
+    }
+
+    /// Start keeping track of the fids whose content isn't needed any
+    /// more, for a storage backend which keeps content elsewhere.
+    pub fn track_released_fids(&mut self) {
+        if self.released_fids.is_none() {
+            self.released_fids = Some(vec![]);
+        }
+    }
+
+    /// The fids whose content isn't needed any more, since the last
+    /// time this was called.
+    pub fn take_released_fids(&mut self) -> Vec<u64> {
+        match self.released_fids {
+            Some(ref mut released) => released.drain(..).collect(),
+            None => vec![],
+        }
+    }
+
+    /// The devices content for the file a storage request refers to
+    /// should be stored on, checking that it may be stored at all.
+    fn content_devids(&self, path: &StoragePath) -> MogResult<Vec<u64>> {
+        let file_info = try!(self.storage_file(path));
+        let mindevcount = try!(self.domain(&path.domain)).class(&file_info.class)
+            .map(|c| c.mindevcount)
+            .unwrap_or(1);
+        let mut writeable = self.writeable_devices();
+
+        // The device the content was sent to gets the first copy,
+        // then it's replicated straight away to enough other devices
+        // to satisfy the class.
+        match path.devid {
+            Some(devid) => {
+                if !self.devices.contains_key(&devid) {
+                    return Err(MogError::UnknownDevice(devid.to_string()));
+                } else if !writeable.contains(&devid) {
+                    return Err(MogError::StorageError(Some(format!("Device {} is not writeable", devid))));
+                }
+                writeable.retain(|&d| d != devid);
+                writeable.insert(0, devid);
+            },
+            None if writeable.is_empty() => {
+                return Err(MogError::StorageError(Some("No writeable devices".to_string())));
+            },
+            None => {},
+        }
+
+        writeable.truncate(mindevcount as usize);
+        Ok(writeable)
+    }
+
+    pub fn get_content(&self, path: &StoragePath) -> MogResult<Cursor<SharedContent>> {
+        let file_info = try!(self.readable_file(path));
+        match file_info.content {
+            Some(ref content) => {
+                Ok(Cursor::new(SharedContent(content.clone())))
+            },
+            None => {
+                Err(MogError::NoContent(path.key.clone()))
+            }
+        }
+    }
+
+    // Journal methods.
+
+    /// Record a change in the journal, if there is one, and then make
+    /// it.
+    fn commit(&mut self, entry: JournalEntry) -> MogResult<()> {
+        if let Some(ref mut journal) = self.journal {
+            try!(journal.append(&entry));
+        }
+
+        try!(self.apply(entry));
+
+        if self.journal.as_ref().map(|j| j.needs_compaction()).unwrap_or(false) {
+            if let Err(e) = self.compact_journal() {
+                // The change is safely in the journal either way, and
+                // this will be tried again after the next one.
+                error!("Error compacting the journal: {}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Make a change, either one that's just been journalled or one
+    /// being replayed from the journal.
+    fn apply(&mut self, entry: JournalEntry) -> MogResult<()> {
+        match entry {
+            JournalEntry::CreateDomain { domain } => {
+                self.domains.insert(domain.clone(), MemDomain::new(&domain));
+            },
+            JournalEntry::DeleteDomain { domain } => {
+                self.domains.remove(&domain);
+            },
+            JournalEntry::SetClass { domain, class, mindevcount, replpolicy, hashtype } => {
+                let mut new_class = MemClass::new(&class, mindevcount);
+                new_class.replpolicy = replpolicy;
+                new_class.hashtype = hashtype;
+
+                let domain = try!(self.domain_mut(&domain));
+                if domain.class(&class).is_some() {
+                    *domain.class_mut(&class).unwrap() = new_class;
+                } else {
+                    try!(domain.add_class(new_class));
+                }
+            },
+            JournalEntry::DeleteClass { domain, class } => {
+                let domain = try!(self.domains.get_mut(&domain).ok_or(MogError::DomainNotFound(domain.clone())));
+                try!(domain.remove_class(&class));
+            },
+            JournalEntry::OpenFile { fid, domain, key, class, size, paths, created } => {
+                let mut file_info = MemFileInfo::new(fid, &key);
+                file_info.class = class;
+                self.uploads.insert(fid, MemUpload {
+                    domain: domain,
+                    file: file_info,
+                    declared_size: size,
+                    received: vec![],
+                    paths: paths,
+                    created: created,
+                });
+                self.reserve_fid(fid);
+            },
+            JournalEntry::StoreContent { fid, domain, key, devids, size, md5, content, mtime } => {
+                let path = StoragePath { devid: None, fid: fid, domain: domain, key: key };
+                {
+                    let file_info = try!(self.storage_file_mut(&path));
+                    file_info.devids = devids;
+                    file_info.size = Some(size);
+                    file_info.md5 = md5;
+                    file_info.content = content.map(Arc::new);
+                    file_info.mtime = Some(mtime);
+                }
+
+                // Whatever was sent a piece at a time has been
+                // replaced.
+                if let Some(upload) = fid.and_then(|fid| self.uploads.get_mut(&fid)) {
+                    upload.received.clear();
+                }
+            },
+            JournalEntry::StoreRange { fid, domain, key, devids, offset, length, total, md5, content, mtime } => {
+                let path = StoragePath { devid: None, fid: Some(fid), domain: domain, key: key };
+                try!(self.open_upload(&path));
+                let end = try!(range_end(&path, offset, length));
+                let upload = self.uploads.get_mut(&fid).unwrap();
+
+                if let Some(range_content) = content {
+                    if upload.file.content.is_none() {
+                        upload.file.content = Some(Arc::new(vec![]));
+                    }
+                    // This only copies the content if someone's reading
+                    // it right now, which an uploader checking on its
+                    // progress might be. They keep what they started
+                    // reading; it's no bigger than the file's allowed
+                    // to be, and ranges are rarely stored while it's
+                    // being read.
+                    let file_content = Arc::make_mut(upload.file.content.as_mut().unwrap());
+                    let end = end as usize;
+                    if file_content.len() < end {
+                        file_content.resize(end, 0);
+                    }
+                    file_content[(offset as usize)..end].copy_from_slice(&range_content);
+                }
+
+                upload.receive_range(offset, end);
+                if total.is_some() {
+                    upload.declared_size = total;
+                }
+                upload.file.devids = devids;
+                upload.file.size = Some(upload.received_length());
+                upload.file.mtime = Some(mtime);
+
+                // The digest can only be worked out once the content
+                // is all there; it's given when the content isn't
+                // kept here.
+                upload.file.md5 = if upload.is_contiguous() {
+                    md5.or_else(|| upload.file.content.as_ref().map(|c| md5_hex(c)))
+                } else {
+                    None
+                };
+            },
+            JournalEntry::DeleteContent { fid, domain, key, devid } => {
+                let path = StoragePath { devid: None, fid: fid, domain: domain, key: key };
+                let released = {
+                    let file_info = try!(self.storage_file_mut(&path));
+                    match devid {
+                        Some(devid) => { file_info.devids.remove(&devid); },
+                        None => file_info.devids.clear(),
+                    }
+
+                    if file_info.devids.is_empty() {
+                        file_info.content = None;
+                        Some(file_info.fid())
+                    } else {
+                        None
+                    }
+                };
+
+                if let Some(fid) = released {
+                    self.release_fid(fid);
+                }
+            },
+            JournalEntry::CloseFile { fid } => {
+                let upload = try!(self.uploads.remove(&fid).ok_or(
+                    MogError::Other("no_temp_file".to_string(), Some(format!("No open file with fid {}", fid)))));
+                try!(self.insert_file(&upload.domain, upload.file));
+            },
+            JournalEntry::AbandonFile { fid } => {
+                if self.uploads.remove(&fid).is_some() {
+                    self.release_fid(fid);
+                }
+            },
+            JournalEntry::PutFile { fid, domain, key, class, devids, size, md5, content, mtime } => {
+                let mut file_info = MemFileInfo::new(fid, &key);
+                file_info.class = class;
+                file_info.devids = devids;
+                file_info.size = size;
+                file_info.md5 = md5;
+                file_info.content = content.map(Arc::new);
+                file_info.mtime = mtime;
+                try!(self.insert_file(&domain, file_info));
+            },
+            JournalEntry::UpdateClass { domain, key, class } => {
+                let file_info = try!(try!(self.file_mut(&domain, &key)).ok_or(MogError::UnknownKey(key.clone())));
+                file_info.class = class;
+            },
+            JournalEntry::Delete { domain, key } => {
+                let file_info = try!(try!(self.domain_mut(&domain))
+                                     .remove_file(&key)
+                                     .ok_or(MogError::UnknownKey(key.clone())));
+                self.fids.remove(&file_info.fid());
+                self.release_fid(file_info.fid());
+            },
+            JournalEntry::Rename { domain, from_key, to_key } => {
+                try!(try!(self.domain_mut(&domain)).rename(&from_key, &to_key));
+
+                // The file keeps its fid, under its new name.
+                let fid = try!(self.file(&domain, &to_key)).map(|f| f.fid());
+                if let Some(fid) = fid {
+                    self.fids.insert(fid, (domain, to_key));
+                }
+            },
+            JournalEntry::NextFid { fid } => {
+                if fid > self.next_fid {
+                    self.next_fid = fid;
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    /// The changes it would take to rebuild the backend, as it is
+    /// now, from nothing.
+    fn snapshot_entries(&self) -> Vec<JournalEntry> {
+        let mut entries = vec![ JournalEntry::NextFid { fid: self.next_fid } ];
+
+        let mut domains: Vec<&MemDomain> = self.domains.values().collect();
+        domains.sort_by(|a, b| a.name().cmp(b.name()));
+
+        for domain in domains {
+            entries.push(JournalEntry::CreateDomain { domain: domain.name().to_string() });
+
+            for class in domain.classes() {
+                entries.push(JournalEntry::SetClass {
+                    domain: domain.name().to_string(),
+                    class: class.name().to_string(),
+                    mindevcount: class.mindevcount,
+                    replpolicy: class.replpolicy.clone(),
+                    hashtype: class.hashtype.clone(),
+                });
+            }
+
+            for (key, file_info) in domain.files() {
+                entries.push(JournalEntry::PutFile {
+                    fid: file_info.fid(),
+                    domain: domain.name().to_string(),
+                    key: key.to_string(),
+                    class: file_info.class.clone(),
+                    devids: file_info.devids.clone(),
+                    size: file_info.size,
+                    md5: file_info.md5.clone(),
+                    content: file_info.content.as_ref().map(|c| (**c).clone()),
+                    mtime: file_info.mtime,
+                });
+            }
+        }
+
+        for (&fid, upload) in self.uploads.iter() {
+            entries.push(JournalEntry::OpenFile {
+                fid: fid,
+                domain: upload.domain.clone(),
+                key: upload.file.key().to_string(),
+                class: upload.file.class.clone(),
+                size: upload.declared_size,
+                paths: upload.paths.clone(),
+                created: upload.created,
+            });
+
+            if !upload.received.is_empty() {
+                let last = upload.received.len() - 1;
+                for (i, &(from, to)) in upload.received.iter().enumerate() {
+                    entries.push(JournalEntry::StoreRange {
+                        fid: fid,
+                        domain: upload.domain.clone(),
+                        key: upload.file.key().to_string(),
+                        devids: upload.file.devids.clone(),
+                        offset: from,
+                        length: to - from,
+                        total: None,
+                        md5: if i == last { upload.file.md5.clone() } else { None },
+                        content: upload.file.content.as_ref().map(|c| c[(from as usize)..(to as usize)].to_vec()),
+                        mtime: upload.file.mtime.unwrap_or(upload.created),
+                    });
+                }
+            } else if let (Some(size), Some(mtime)) = (upload.file.size, upload.file.mtime) {
+                entries.push(JournalEntry::StoreContent {
+                    fid: Some(fid),
+                    domain: upload.domain.clone(),
+                    key: upload.file.key().to_string(),
+                    devids: upload.file.devids.clone(),
+                    size: size,
+                    md5: upload.file.md5.clone(),
+                    content: upload.file.content.as_ref().map(|c| (**c).clone()),
+                    mtime: mtime,
+                });
+            }
+        }
+
+        entries
+    }
+
+    // Utility methods.
+
+    /// Put a file in its domain, replacing whatever was there under
+    /// that key before.
+    fn insert_file(&mut self, domain: &str, file_info: MemFileInfo) -> MogResult<()> {
+        let fid = file_info.fid();
+        let key = file_info.key().to_string();
+
+        if let Some(old_file_info) = try!(self.domain_mut(domain)).remove_file(&key) {
+            self.fids.remove(&old_file_info.fid());
+            self.release_fid(old_file_info.fid());
+        }
+
+        try!(try!(self.domain_mut(domain)).add_file(&key, file_info));
+        self.fids.insert(fid, (domain.to_string(), key));
+        self.reserve_fid(fid);
+        Ok(())
+    }
+
+    fn release_fid(&mut self, fid: u64) {
+        if let Some(ref mut released) = self.released_fids {
+            released.push(fid);
+        }
+    }
+
+    /// Make sure a fid is never handed out again.
+    fn reserve_fid(&mut self, fid: u64) {
+        if fid >= self.next_fid {
+            self.next_fid = fid + 1;
+        }
+    }
+
+    fn file(&self, domain: &str, key: &str) -> MogResult<Option<&MemFileInfo>> {
+        self.domain(domain).map(|d| d.file(key))
+    }
+
+    fn file_mut(&mut self, domain: &str, key: &str) -> MogResult<Option<&mut MemFileInfo>> {
+        self.domain_mut(domain).map(|d| d.file_mut(key))
+    }
+
+    /// Look up the file a storage request refers to. With a fid,
+    /// that's either a file still being uploaded or the closed file
+    /// with that fid; without one, it's the closed file.
+    fn storage_file(&self, path: &StoragePath) -> MogResult<&MemFileInfo> {
+        let file_info = match path.fid.and_then(|fid| self.uploads.get(&fid)) {
+            Some(upload) if upload.domain == path.domain => Some(&upload.file),
+            Some(_) => None,
+            None => try!(self.file(&path.domain, &path.key)),
+        };
+
+        match (file_info, path.fid) {
+            (Some(f), Some(fid)) if f.key() == path.key && f.fid() == fid => Ok(f),
+            (Some(f), None) => Ok(f),
+            (_, Some(fid)) => Err(MogError::UnknownFid(fid.to_string())),
+            (None, None) => Err(MogError::UnknownKey(path.key.clone())),
+        }
+    }
+
+    /// The file a storage request refers to, which must still be
+    /// open.
+    fn open_upload(&self, path: &StoragePath) -> MogResult<&MemUpload> {
+        try!(self.storage_file(path));
+        path.fid.and_then(|fid| self.uploads.get(&fid)).ok_or(
+            MogError::Other("no_temp_file".to_string(), Some(format!("{:?} is not an open file", path.key))))
+    }
+
+    fn storage_file_mut(&mut self, path: &StoragePath) -> MogResult<&mut MemFileInfo> {
+        // Make sure it's there first, so that the lookups below can't
+        // miss.
+        try!(self.storage_file(path));
+
+        match path.fid {
+            Some(fid) if self.uploads.contains_key(&fid) => Ok(&mut self.uploads.get_mut(&fid).unwrap().file),
+            _ => Ok(try!(self.file_mut(&path.domain, &path.key)).unwrap()),
+        }
+    }
+
+    /// Look up a file for a storage request, making sure there's a
+    /// readable copy of it on the device the request was addressed to
+    /// (or any device, if it wasn't addressed to one).
+    fn readable_file(&self, path: &StoragePath) -> MogResult<&MemFileInfo> {
+        let file_info = try!(self.storage_file(path));
+
+        let readable = match path.devid {
+            Some(devid) => file_info.devids.contains(&devid) && self.device_is_readable(devid),
+            None => file_info.devids.iter().any(|&devid| self.device_is_readable(devid)),
+        };
+
+        if readable {
+            Ok(file_info)
+        } else {
+            Err(MogError::NoContent(path.key.clone()))
+        }
+    }
+
+    /// The devices new files may be stored on, heaviest first.
+    fn writeable_devices(&self) -> Vec<u64> {
+        let mut devices: Vec<&MemDevice> = self.devices.values()
+            .filter(|d| d.state.is_writeable() && d.weight > 0 && self.host_is_alive(d.hostid()))
+            .collect();
+        devices.sort_by(|a, b| b.weight.cmp(&a.weight).then(a.devid().cmp(&b.devid())));
+        devices.into_iter().map(|d| d.devid()).collect()
+    }
+
+    fn device_is_readable(&self, devid: u64) -> bool {
+        self.devices.get(&devid)
+            .map(|d| d.state.is_readable() && self.host_is_alive(d.hostid()))
+            .unwrap_or(false)
+    }
+
+    fn host_is_alive(&self, hostid: u64) -> bool {
+        self.hosts.get(&hostid).map(|h| h.status == HostStatus::Alive).unwrap_or(false)
+    }
+
+    fn host_by_name(&self, hostname: &str) -> Option<&MemHost> {
+        self.hosts.values().find(|h| h.hostname == hostname)
+    }
+
+    fn host_by_name_mut(&mut self, hostname: &str) -> Option<&mut MemHost> {
+        self.hosts.values_mut().find(|h| h.hostname == hostname)
+    }
+
+    /// Look up a device, making sure it's on the named host.
+    fn host_device_mut(&mut self, hostname: &str, devid: u64) -> MogResult<&mut MemDevice> {
+        let hostid = try!(self.host_by_name(hostname).map(|h| h.hostid()).ok_or(MogError::UnknownHost(hostname.to_string())));
+        let device = try!(self.devices.get_mut(&devid).ok_or(MogError::UnknownDevice(devid.to_string())));
+
+        if device.hostid() != hostid {
+            return Err(MogError::HostMismatch);
+        }
+
+        Ok(device)
+    }
+
+    fn domain(&self, domain_name: &str) -> MogResult<&MemDomain> {
+        // self.domains.get(domain_name).ok_or(MogError::UnregDomain(domain_name.to_string()))
+        Ok(self.domains.get(domain_name).unwrap_or(&self.empty_domain))
+    }
+
+    fn domain_mut(&mut self, domain_name: &str) -> MogResult<&mut MemDomain> {
+        // self.domains.get_mut(domain_name).ok_or(MogError::UnregDomain(domain_name.to_string()))
+        Ok(self.domains.entry(domain_name.to_string()).or_insert(MemDomain::new(domain_name)))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SyncMemBackend(Arc<RwLock<MemBackend>>, Url);
+
+impl SyncMemBackend {
+    pub fn new(backend: MemBackend) -> SyncMemBackend {
+        let base_url = backend.base_url.clone();
+        SyncMemBackend(Arc::new(RwLock::new(backend)), base_url)
+    }
+
+    pub fn with_file<F>(&self, domain: &str, key: &str, block: F) -> MogResult<()>
+        where F: FnOnce(&MemFileInfo) -> MogResult<()>
     {
         let guard = try!(self.0.read());
         match guard.file(domain, key) {
@@ -192,223 +1485,1375 @@ impl SyncMemBackend {
             Ok(None) => Err(MogError::UnknownKey(key.to_string())),
             Err(e) => Err(e),
         }
-    }
+    }
+
+    pub fn base_url(&self) -> Url {
+        self.1.clone()
+    }
+
+    pub fn set_base_url(&mut self, new_url: Url) -> MogResult<()> {
+        let mut guard = try!(self.0.write());
+        guard.set_base_url(new_url.clone());
+        self.1 = new_url;
+        Ok(())
+    }
+
+    pub fn set_device_state(&self, devid: u64, state: DeviceState) -> MogResult<()> {
+        try!(self.0.write()).set_device_state(devid, state)
+    }
+
+    pub fn set_upload_expiry(&self, expiry: Duration) -> MogResult<()> {
+        try!(self.0.write()).set_upload_expiry(expiry);
+        Ok(())
+    }
+
+    pub fn set_url_signer(&self, signer: Option<UrlSigner>) -> MogResult<()> {
+        try!(self.0.write()).set_url_signer(signer);
+        Ok(())
+    }
+
+    pub fn set_max_file_size(&self, max: Option<u64>) -> MogResult<()> {
+        try!(self.0.write()).set_max_file_size(max);
+        Ok(())
+    }
+
+    pub fn set_domain_max_file_size(&self, domain: &str, max: Option<u64>) -> MogResult<()> {
+        try!(self.0.write()).set_domain_max_file_size(domain, max);
+        Ok(())
+    }
+
+    pub fn reap_uploads(&self) -> MogResult<usize> {
+        try!(self.0.write()).reap_uploads()
+    }
+
+    pub fn store_content_size(&self, path: &StoragePath, size: u64, md5: &str) -> MogResult<()> {
+        try!(self.0.write()).store_content_size(path, size, md5)
+    }
+
+    pub fn range_limit(&self, path: &StoragePath, offset: u64, total: Option<u64>) -> MogResult<Option<u64>> {
+        try!(self.0.read()).range_limit(path, offset, total)
+    }
+
+    pub fn range_completes(&self, path: &StoragePath, offset: u64, length: u64) -> MogResult<bool> {
+        try!(self.0.read()).range_completes(path, offset, length)
+    }
+
+    pub fn store_range_size(&self, path: &StoragePath, offset: u64, length: u64, total: Option<u64>, md5: Option<String>) -> MogResult<()> {
+        try!(self.0.write()).store_range_size(path, offset, length, total, md5)
+    }
+
+    pub fn writeable_fid(&self, path: &StoragePath) -> MogResult<u64> {
+        try!(self.0.read()).writeable_fid(path)
+    }
+
+    pub fn readable_fid(&self, path: &StoragePath) -> MogResult<u64> {
+        try!(self.0.read()).readable_fid(path)
+    }
+
+    pub fn has_fid(&self, fid: u64) -> MogResult<bool> {
+        Ok(try!(self.0.read()).has_fid(fid))
+    }
+
+    pub fn track_released_fids(&self) -> MogResult<()> {
+        try!(self.0.write()).track_released_fids();
+        Ok(())
+    }
+
+    pub fn take_released_fids(&self) -> MogResult<Vec<u64>> {
+        Ok(try!(self.0.write()).take_released_fids())
+    }
+}
+
+impl Backend for SyncMemBackend {
+    fn create_domain(&self, request: &CreateDomain) -> MogResult<CreateDomain> {
+        try!(self.0.write()).create_domain(&request)
+    }
+
+    fn create_open(&self, request: &CreateOpen) -> MogResult<CreateOpenResponse> {
+        try!(self.0.write()).create_open(&request)
+    }
+
+    fn create_close(&self, request: &CreateClose) -> MogResult<()> {
+        try!(self.0.write()).create_close(&request)
+    }
+
+    fn create_class(&self, request: &CreateClass) -> MogResult<CreateClassResponse> {
+        try!(self.0.write()).create_class(&request)
+    }
+
+    fn get_paths(&self, request: &GetPaths) -> MogResult<GetPathsResponse> {
+        try!(self.0.read()).get_paths(&request)
+    }
+    
+    fn file_info(&self, request: &FileInfo) -> MogResult<FileInfoResponse> {
+        try!(self.0.read()).file_info(&request)
+    }
+    
+    fn update_class(&self, request: &UpdateClass) -> MogResult<()> {
+        try!(self.0.write()).update_class(&request)
+    }
+
+    fn delete(&self, request: &Delete) -> MogResult<()> {
+        try!(self.0.write()).delete(&request)
+    }
+
+    fn rename(&self, request: &Rename) -> MogResult<()> {
+        try!(self.0.write()).rename(&request)
+    }
+
+    fn list_keys(&self, request: &ListKeys) -> MogResult<ListKeysResponse> {
+        try!(self.0.read()).list_keys(&request)
+    }
+
+    fn delete_domain(&self, request: &DeleteDomain) -> MogResult<DeleteDomain> {
+        try!(self.0.write()).delete_domain(&request)
+    }
+
+    fn delete_class(&self, request: &DeleteClass) -> MogResult<DeleteClass> {
+        try!(self.0.write()).delete_class(&request)
+    }
+
+    fn get_domains(&self, request: &GetDomains) -> MogResult<GetDomainsResponse> {
+        try!(self.0.read()).get_domains(&request)
+    }
+
+    fn get_hosts(&self, request: &GetHosts) -> MogResult<GetHostsResponse> {
+        try!(self.0.read()).get_hosts(&request)
+    }
+
+    fn get_devices(&self, request: &GetDevices) -> MogResult<GetDevicesResponse> {
+        try!(self.0.read()).get_devices(&request)
+    }
+
+    fn create_host(&self, request: &CreateHost) -> MogResult<CreateHostResponse> {
+        try!(self.0.write()).create_host(&request)
+    }
+
+    fn update_host(&self, request: &UpdateHost) -> MogResult<CreateHostResponse> {
+        try!(self.0.write()).update_host(&request)
+    }
+
+    fn delete_host(&self, request: &DeleteHost) -> MogResult<()> {
+        try!(self.0.write()).delete_host(&request)
+    }
+
+    fn create_device(&self, request: &CreateDevice) -> MogResult<()> {
+        try!(self.0.write()).create_device(&request)
+    }
+
+    fn set_state(&self, request: &SetState) -> MogResult<()> {
+        try!(self.0.write()).set_state(&request)
+    }
+
+    fn set_weight(&self, request: &SetWeight) -> MogResult<()> {
+        try!(self.0.write()).set_weight(&request)
+    }
+
+    fn list_fids(&self, request: &ListFids) -> MogResult<ListFidsResponse> {
+        try!(self.0.read()).list_fids(&request)
+    }
+
+    fn file_debug(&self, request: &FileDebug) -> MogResult<FileDebugResponse> {
+        try!(self.0.read()).file_debug(&request)
+    }
+}
+
+impl StorageBackend for SyncMemBackend {
+    type Content = Cursor<SharedContent>;
+
+    fn url_for_key(&self, domain: &str, key: &str) -> Url {
+        url_for_key(&self.1, domain, key)
+    }
+
+    fn file_metadata(&self, path: &StoragePath) -> MogResult<StorageMetadata> {
+        try!(self.0.read()).file_metadata(path)
+    }
+
+    fn content_limit(&self, path: &StoragePath) -> MogResult<Option<u64>> {
+        try!(self.0.read()).content_limit(path)
+    }
+
+    fn is_open_upload(&self, path: &StoragePath) -> MogResult<bool> {
+        try!(self.0.read()).is_open_upload(path)
+    }
+
+    fn fid_storage_path(&self, devid: Option<u64>, fid: u64) -> MogResult<StoragePath> {
+        try!(self.0.read()).fid_storage_path(devid, fid)
+    }
+
+    fn store_reader_content<R: Read>(&self, path: &StoragePath, reader: &mut R) -> MogResult<()> {
+        // Read the content without holding the lock, since it could
+        // take a while to arrive.
+        let limit = try!(self.content_limit(path));
+        let mut content = vec![];
+        try!(copy_content(path, reader, &mut content, limit));
+        try!(self.0.write()).store_bytes_content(path, &content)
+    }
+
+    fn store_bytes_content(&self, path: &StoragePath, content: &[u8]) -> MogResult<()> {
+        try!(self.0.write()).store_bytes_content(path, content)
+    }
+
+    fn store_reader_range<R: Read>(&self, path: &StoragePath, reader: &mut R, offset: u64, total: Option<u64>) -> MogResult<()> {
+        let limit = try!(try!(self.0.read()).range_limit(path, offset, total));
+        let mut content = vec![];
+        try!(copy_content(path, reader, &mut content, limit));
+        try!(self.0.write()).store_bytes_range(path, offset, &content, total)
+    }
+
+    fn get_content(&self, path: &StoragePath) -> MogResult<Cursor<SharedContent>> {
+        try!(self.0.read()).get_content(path)
+    }
+
+    fn delete_content(&self, path: &StoragePath) -> MogResult<()> {
+        try!(self.0.write()).delete_content(path)
+    }
+
+    fn delete_file(&self, path: &StoragePath) -> MogResult<()> {
+        try!(self.0.write()).delete_file(path)
+    }
+}
+
+/// The `file_info` response for a file. Its checksum is only
+/// reported if its class has a hash type.
+fn file_info_response(domain: &str, class: Option<&MemClass>, file_info: &MemFileInfo) -> FileInfoResponse {
+    let checksum = match class.and_then(|c| c.hashtype.as_ref()) {
+        Some(_) => file_info.md5.as_ref().map(|md5| format_checksum(md5)),
+        None => None,
+    };
+
+    FileInfoResponse {
+        fid: file_info.fid(),
+        devcount: file_info.devids.len() as u64,
+        length: file_info.size.unwrap_or(0),
+        domain: domain.to_string(),
+        class: file_info.class.clone(),
+        key: file_info.key().to_string(),
+        checksum: checksum,
+    }
+}
+
+pub fn url_for_key(base_url: &Url, domain: &str, key: &str) -> Url {
+    storage_url(base_url, None, domain, key)
+}
+
+pub fn url_for_device_key(base_url: &Url, devid: u64, domain: &str, key: &str) -> Url {
+    storage_url(base_url, Some(devid), domain, key)
+}
+
+pub fn url_for_device_fid(base_url: &Url, devid: u64, fid: u64) -> Url {
+    let dev_segment = format!("dev{}", devid);
+    let fid_segments = fid_path_segments(fid);
+    let mut new_path: Vec<&str> = base_url.path_segments().unwrap().collect();
+    new_path.push(&dev_segment);
+    new_path.extend(fid_segments.iter().map(|s| s.as_str()));
+    new_path = new_path.into_iter().skip_while(|p| p.is_empty()).collect();
+
+    let mut fid_url = base_url.clone();
+    fid_url.set_path(&new_path.join("/"));
+    fid_url
+}
+
+fn storage_url(base_url: &Url, devid: Option<u64>, domain: &str, key: &str) -> Url {
+    let dev_segment = devid.map(|d| format!("dev{}", d));
+    let mut new_path: Vec<&str> = base_url.path_segments().unwrap().collect();
+    if let Some(ref dev) = dev_segment {
+        new_path.push(dev);
+    }
+    new_path.extend([ "d", domain, "k" ].iter());
+    new_path.extend(key.split("/"));
+    new_path = new_path.into_iter().skip_while(|p| p.is_empty()).collect();
+
+    let mut key_url = base_url.clone();
+    key_url.set_path(&new_path.join("/"));
+    key_url
+}
+
+#[cfg(test)]
+mod tests {
+    use mogilefs_common::MogError;
+    use mogilefs_common::requests::*;
+    use std::io::{Cursor, Read};
+    use super::super::{DeviceState, HostStatus};
+    use super::{MemBackend, UrlScheme, DEFAULT_UPLOAD_EXPIRY};
+    use std::fs;
+    use super::super::super::backend::{fid_path_segments, StoragePath};
+    use super::super::super::checksum::md5_hex;
+    use super::super::super::signing::UrlSigner;
+    use time::{self, Duration};
+    use super::super::super::test_support::*;
+
+    /// Open, store and close a file, the way a client would.
+    fn upload_file(backend: &mut MemBackend, request: &CreateOpen, content: &[u8]) -> u64 {
+        let co_response = backend.create_open(request).unwrap();
+        let (devid, ref path) = co_response.paths[0];
+        let mut storage_path = StoragePath::on_device(devid, &request.domain, &request.key);
+        storage_path.fid = Some(co_response.fid);
+
+        backend.store_bytes_content(&storage_path, content).unwrap();
+        backend.create_close(&CreateClose {
+            domain: request.domain.clone(), key: request.key.clone(),
+            fid: co_response.fid, devid: devid, path: path.clone(), checksum: None,
+        }).unwrap();
+
+        co_response.fid
+    }
+
+    #[test]
+    fn backend_get_file() {
+        let mut backend = backend_fixture();
+
+        {
+            let file = backend.file(TEST_DOMAIN, TEST_KEY_1);
+            assert!(
+                matches!(file, Ok(Some(ref f)) if f.key() == TEST_KEY_1),
+                "Immutable present file was {:?}", file);
+        }
+
+        {
+            let file = backend.file(TEST_DOMAIN, "test/key/3");
+            assert!(
+                matches!(file, Ok(None)),
+                "Immutable missing file was {:?}", file);
+        }
+
+        {
+            let file = backend.file_mut(TEST_DOMAIN, TEST_KEY_1);
+            assert!(
+                matches!(file, Ok(Some(ref f)) if f.key() == TEST_KEY_1),
+                "Mutable present file was {:?}", file);
+        }
+
+        {
+            let file = backend.file_mut(TEST_DOMAIN, "test/key/3");
+            assert!(
+                matches!(file, Ok(None)),
+                "Mutable missing file was {:?}", file);
+        }
+
+        // {
+        //     let file = backend.file("test_domain_2", TEST_KEY_1);
+        //     assert!(
+        //         matches!(file, Err(MogError::UnregDomain(ref d)) if d == "test_domain_2"),
+        //         "Immutable file from nonexistent domain was {:?}", file);
+        // }
+
+        // {
+        //     let file = backend.file_mut("test_domain_2", TEST_KEY_1);
+        //     assert!(
+        //         matches!(file, Err(MogError::UnregDomain(ref d)) if d == "test_domain_2"),
+        //         "Mutable file from nonexistent domain was {:?}", file);
+        // }
+    }
+
+    #[test]
+    fn backend_create_domain() {
+        let mut backend = backend_fixture();
+
+        let create_request = CreateDomain { domain: "test_domain_2".to_string() };
+        let create_result = backend.create_domain(&create_request);
+        assert!(create_result.is_ok(), "Create new domain result was {:?}", create_result);
+
+        assert!(backend.domains.contains_key("test_domain_2"));
+
+        let create_dup_request = CreateDomain { domain: TEST_DOMAIN.to_string() };
+        let create_dup_result = backend.create_domain(&create_dup_request);
+        assert!(
+            matches!(create_dup_result, Err(MogError::DomainExists(ref d)) if d == TEST_DOMAIN),
+            "Create duplicate domain result was {:?}", create_dup_result);
+    }
+
+    #[test]
+    fn backend_delete_domain() {
+        let mut backend = backend_fixture();
+
+        {
+            let delete_result = backend.delete_domain(&DeleteDomain { domain: "test_domain_2".to_string() });
+            assert!(
+                matches!(delete_result, Err(MogError::DomainNotFound(ref d)) if d == "test_domain_2"),
+                "Delete unknown domain result was {:?}", delete_result);
+        }
+
+        {
+            let delete_result = backend.delete_domain(&DeleteDomain { domain: TEST_DOMAIN.to_string() });
+            assert!(
+                matches!(delete_result, Err(MogError::DomainNotEmpty(ref d)) if d == TEST_DOMAIN),
+                "Delete non-empty domain result was {:?}", delete_result);
+            assert!(backend.domains.contains_key(TEST_DOMAIN));
+        }
+
+        {
+            backend.create_domain(&CreateDomain { domain: "test_domain_2".to_string() }).unwrap();
+            let delete_result = backend.delete_domain(&DeleteDomain { domain: "test_domain_2".to_string() });
+            assert!(delete_result.is_ok(), "Delete empty domain result was {:?}", delete_result);
+            assert!(!backend.domains.contains_key("test_domain_2"));
+        }
+    }
+
+    #[test]
+    fn backend_create_and_delete_class() {
+        let mut backend = backend_fixture();
+        let create_request = CreateClass {
+            domain: TEST_DOMAIN.to_string(), class: "test_class".to_string(), mindevcount: 3,
+            replpolicy: None, hashtype: None, update: false,
+        };
+
+        {
+            let create_result = backend.create_class(&create_request);
+            assert!(create_result.is_ok(), "Create class result was {:?}", create_result);
+            assert_eq!(3, backend.domains[TEST_DOMAIN].class("test_class").unwrap().mindevcount);
+        }
+
+        {
+            let delete_result = backend.delete_class(&DeleteClass { domain: TEST_DOMAIN.to_string(), class: "test_class".to_string() });
+            assert!(delete_result.is_ok(), "Delete class result was {:?}", delete_result);
+            assert!(backend.domains[TEST_DOMAIN].class("test_class").is_none());
+        }
+
+        {
+            let delete_result = backend.delete_class(&DeleteClass { domain: TEST_DOMAIN.to_string(), class: "test_class".to_string() });
+            assert!(
+                matches!(delete_result, Err(MogError::ClassNotFound(ref c)) if c == "test_class"),
+                "Delete unknown class result was {:?}", delete_result);
+        }
+
+        {
+            let delete_result = backend.delete_class(&DeleteClass { domain: "test_domain_2".to_string(), class: "test_class".to_string() });
+            assert!(
+                matches!(delete_result, Err(MogError::DomainNotFound(ref d)) if d == "test_domain_2"),
+                "Delete class from unknown domain result was {:?}", delete_result);
+        }
+    }
+
+    #[test]
+    fn backend_create_duplicate_class() {
+        let mut backend = backend_fixture();
+        let mut request = CreateClass {
+            domain: TEST_DOMAIN.to_string(), class: "test_class".to_string(), mindevcount: 3,
+            replpolicy: None, hashtype: None, update: false,
+        };
+        backend.create_class(&request).unwrap();
+
+        request.mindevcount = 1;
+        let create_result = backend.create_class(&request);
+        assert!(
+            matches!(create_result, Err(MogError::ClassExists(ref c)) if c == "test_class"),
+            "Create duplicate class result was {:?}", create_result);
+        assert_eq!(3, backend.domains[TEST_DOMAIN].class("test_class").unwrap().mindevcount);
+
+        request.mindevcount = 0;
+        request.class = "other_class".to_string();
+        let create_result = backend.create_class(&request);
+        assert!(
+            matches!(create_result, Err(MogError::InvalidMindevcount)),
+            "Create class with zero mindevcount result was {:?}", create_result);
+    }
+
+    #[test]
+    fn backend_update_class() {
+        let mut backend = backend_fixture();
+        let mut request = CreateClass {
+            domain: TEST_DOMAIN.to_string(), class: "test_class".to_string(), mindevcount: 3,
+            replpolicy: Some("MultipleHosts()".to_string()), hashtype: Some("MD5".to_string()), update: true,
+        };
+
+        {
+            let update_result = backend.create_class(&request);
+            assert!(
+                matches!(update_result, Err(MogError::ClassNotFound(ref c)) if c == "test_class"),
+                "Update unknown class result was {:?}", update_result);
+        }
+
+        request.update = false;
+        backend.create_class(&request).unwrap();
+
+        {
+            request.update = true;
+            request.mindevcount = 1;
+            request.replpolicy = None;
+            request.hashtype = Some("NONE".to_string());
+            let update_result = backend.create_class(&request);
+            assert!(matches!(update_result, Ok(ref r) if r.mindevcount == 1), "Update class result was {:?}", update_result);
+
+            let class = backend.domains[TEST_DOMAIN].class("test_class").unwrap();
+            assert_eq!(1, class.mindevcount);
+            assert_eq!(Some("MultipleHosts()".to_string()), class.replpolicy);
+            assert_eq!(None, class.hashtype);
+        }
+    }
+
+    #[test]
+    fn backend_create_open_with_class() {
+        let mut backend = backend_fixture();
+        let mut request = CreateOpen {
+            domain: TEST_DOMAIN.to_string(), class: Some("test_class".to_string()),
+            key: "test/key/3".to_string(), multi_dest: true, size: None,
+        };
+
+        {
+            let co_result = backend.create_open(&request);
+            assert!(
+                matches!(co_result, Err(MogError::UnregClass(ref c)) if c == "test_class"),
+                "Create open with unregistered class result was {:?}", co_result);
+            assert!(backend.domains[TEST_DOMAIN].file("test/key/3").is_none());
+        }
+
+        backend.create_class(&CreateClass {
+            domain: TEST_DOMAIN.to_string(), class: "test_class".to_string(), mindevcount: 1,
+            replpolicy: None, hashtype: None, update: false,
+        }).unwrap();
+
+        {
+            upload_file(&mut backend, &request, TEST_CONTENT_1);
+            let info = backend.file_info(&FileInfo { domain: TEST_DOMAIN.to_string(), key: "test/key/3".to_string() }).unwrap();
+            assert_eq!("test_class", info.class);
+        }
+
+        {
+            request.class = None;
+            request.key = "test/key/4".to_string();
+            upload_file(&mut backend, &request, TEST_CONTENT_1);
+            let info = backend.file_info(&FileInfo { domain: TEST_DOMAIN.to_string(), key: "test/key/4".to_string() }).unwrap();
+            assert_eq!("default", info.class);
+        }
+    }
+
+    #[test]
+    fn backend_delete_class_with_files() {
+        let mut backend = backend_fixture();
+        backend.create_class(&CreateClass {
+            domain: TEST_DOMAIN.to_string(), class: "test_class".to_string(), mindevcount: 1,
+            replpolicy: None, hashtype: None, update: false,
+        }).unwrap();
+        upload_file(&mut backend, &CreateOpen {
+            domain: TEST_DOMAIN.to_string(), class: Some("test_class".to_string()),
+            key: "test/key/3".to_string(), multi_dest: true, size: None,
+        }, TEST_CONTENT_1);
+
+        let delete_result = backend.delete_class(&DeleteClass { domain: TEST_DOMAIN.to_string(), class: "test_class".to_string() });
+        assert!(
+            matches!(delete_result, Err(MogError::ClassHasFiles(ref c)) if c == "test_class"),
+            "Delete class with files result was {:?}", delete_result);
+    }
+
+    #[test]
+    fn backend_get_domains() {
+        let mut backend = backend_fixture();
+        backend.create_class(&CreateClass {
+            domain: TEST_DOMAIN.to_string(), class: "test_class".to_string(), mindevcount: 3,
+            replpolicy: None, hashtype: None, update: false,
+        }).unwrap();
+
+        let domains = backend.get_domains(&GetDomains).unwrap().0;
+        assert_eq!(1, domains.len());
+        assert_eq!(TEST_DOMAIN, domains[0].name);
+
+        let class_names: Vec<&str> = domains[0].classes.iter().map(|c| c.name.as_ref()).collect();
+        assert_eq!(vec![ "default", "test_class" ], class_names);
+        assert_eq!(3, domains[0].classes[1].mindevcount);
+    }
+
+    #[test]
+    fn backend_create_open() {
+        use url::Url;
+
+        let sync_backend = sync_backend_fixture();
+        // let storage = MemStorage::new(
+        //     sync_backend.clone(),
+        //     Url::parse(format!("http://{}/{}", TEST_HOST, TEST_BASE_PATH).as_ref()).unwrap());
+
+        {
+            let req = CreateOpen { domain: TEST_DOMAIN.to_string(), class: None, key: "test/key/3".to_string(), multi_dest: true, size: None };
+            let mut backend = sync_backend.0.write().unwrap();
+            let co_result = backend.create_open(&req);
+            assert!(co_result.is_ok());
+            let co_response = co_result.unwrap();
+            assert_eq!(1, co_response.paths.len());
+            assert_eq!(
+                Url::parse(format!("http://{}/{}/dev1/d/{}/k/{}?fid=5", TEST_HOST, TEST_BASE_PATH, TEST_DOMAIN, "test/key/3").as_ref()).unwrap(),
+                co_response.paths.iter().next().unwrap().1);
+        }
+
+        {
+            // The file isn't visible until it's closed.
+            let backend = sync_backend.0.read().unwrap();
+            let file = backend.file(TEST_DOMAIN, "test/key/3");
+            assert!(matches!(file, Ok(None)), "Create opened file was {:?}", file);
+            let upload = backend.uploads.get(&5).unwrap();
+            assert_eq!("test/key/3", upload.file.key());
+            assert!(upload.file.content.is_none());
+            assert!(upload.file.size.is_none());
+        }
+
+        {
+            let req = CreateOpen { domain: TEST_DOMAIN.to_string(), class: None, key: TEST_KEY_1.to_string(), multi_dest: true, size: None };
+            let mut backend = sync_backend.0.write().unwrap();
+            let co_result = backend.create_open(&req);
+            assert!(co_result.is_ok(), "Create open with duplicate key result was {:?}", co_result);
+            let co_response = co_result.unwrap();
+            assert_eq!(1, co_response.paths.len());
+            assert_eq!(
+                Url::parse(format!("http://{}/{}/dev1/d/{}/k/{}?fid=6", TEST_HOST, TEST_BASE_PATH, TEST_DOMAIN, TEST_KEY_1).as_ref()).unwrap(),
+                co_response.paths.iter().next().unwrap().1);
+        }
+
+        // {
+        //     let mut backend = sync_backend.0.lock().unwrap();
+        //     let co_result = backend.create_open("test_domain_2", "test/key/3", &storage);
+        //     assert!(
+        //         matches!(co_result, Err(MogError::UnregDomain(ref k)) if k == "test_domain_2"),
+        //         "Create open with unknown domain result was {:?}", co_result);
+        // }
+    }
+
+    #[test]
+    fn backend_create_open_signed() {
+        let mut backend = backend_fixture();
+        let signer = UrlSigner::new("secret");
+        backend.set_url_signer(Some(signer.clone()));
+
+        let req = CreateOpen { domain: TEST_DOMAIN.to_string(), class: None, key: "test/key/3".to_string(), multi_dest: false, size: None };
+        let co_response = backend.create_open(&req).unwrap();
+        let url = &co_response.paths[0].1;
+        assert!(url.query().unwrap().starts_with(&format!("fid={}&expires=", co_response.fid)));
+        assert_eq!(Ok(()), signer.verify_store(url.query(), co_response.fid, TEST_DOMAIN, "test/key/3"));
+        assert!(signer.verify_store(url.query(), co_response.fid, TEST_DOMAIN, TEST_KEY_1).is_err());
+
+        // The signed path is the one the file has to be closed with.
+        let mut storage_path = StoragePath::on_device(1, TEST_DOMAIN, "test/key/3");
+        storage_path.fid = Some(co_response.fid);
+        backend.store_bytes_content(&storage_path, TEST_CONTENT_1).unwrap();
+        backend.create_close(&CreateClose {
+            domain: TEST_DOMAIN.to_string(), key: "test/key/3".to_string(),
+            fid: co_response.fid, devid: 1, path: url.clone(), checksum: None,
+        }).unwrap();
+    }
+
+    #[test]
+    fn backend_is_open_upload() {
+        let mut backend = backend_fixture();
+        let req = CreateOpen { domain: TEST_DOMAIN.to_string(), class: None, key: TEST_KEY_1.to_string(), multi_dest: false, size: None };
+        let fid = backend.create_open(&req).unwrap().fid;
+
+        let mut path = StoragePath::new(TEST_DOMAIN, TEST_KEY_1);
+        assert_eq!(false, backend.is_open_upload(&path).unwrap());
+        path.fid = Some(fid);
+        assert_eq!(true, backend.is_open_upload(&path).unwrap());
+        path.fid = Some(3);
+        assert_eq!(false, backend.is_open_upload(&path).unwrap());
+
+        path.fid = Some(fid);
+        backend.set_upload_expiry(Duration::seconds(0));
+        assert_eq!(false, backend.is_open_upload(&path).unwrap());
+
+        let unknown = StoragePath::new(TEST_DOMAIN, "test/key/3");
+        assert!(matches!(backend.is_open_upload(&unknown), Err(MogError::UnknownKey(..))));
+    }
+
+    #[test]
+    fn backend_update_file_class() {
+        let mut backend = backend_fixture();
+        let request = UpdateClass {
+            domain: TEST_DOMAIN.to_string(), key: TEST_KEY_1.to_string(), new_class: "test_class".to_string(),
+        };
+
+        {
+            let update_result = backend.update_class(&request);
+            assert!(
+                matches!(update_result, Err(MogError::UnregClass(ref c)) if c == "test_class"),
+                "Update to unregistered class result was {:?}", update_result);
+        }
+
+        backend.create_class(&CreateClass {
+            domain: TEST_DOMAIN.to_string(), class: "test_class".to_string(), mindevcount: 1,
+            replpolicy: None, hashtype: None, update: false,
+        }).unwrap();
+
+        {
+            let update_result = backend.update_class(&request);
+            assert!(update_result.is_ok(), "Update class result was {:?}", update_result);
+            assert_eq!("test_class", backend.domains[TEST_DOMAIN].file(TEST_KEY_1).unwrap().class);
+        }
+
+        {
+            let update_result = backend.update_class(&UpdateClass {
+                domain: TEST_DOMAIN.to_string(), key: "test/key/3".to_string(), new_class: "test_class".to_string(),
+            });
+            assert!(
+                matches!(update_result, Err(MogError::UnknownKey(ref k)) if k == "test/key/3"),
+                "Update class of unknown key result was {:?}", update_result);
+        }
+    }
+
+    #[test]
+    fn backend_get_hosts_and_devices() {
+        let backend = backend_fixture();
+
+        let hosts = backend.get_hosts(&GetHosts { hostid: None }).unwrap();
+        assert_eq!(1, hosts.0.len());
+        assert_eq!(1, hosts.0[0].hostid);
+        assert_eq!(TEST_HOST, hosts.0[0].hostname);
+        assert_eq!(80, hosts.0[0].http_port);
+        assert_eq!("alive", hosts.0[0].status);
+        assert!(backend.get_hosts(&GetHosts { hostid: Some(2) }).unwrap().0.is_empty());
+
+        let devices = backend.get_devices(&GetDevices { devid: None }).unwrap();
+        assert_eq!(1, devices.0.len());
+        assert_eq!(1, devices.0[0].devid);
+        assert_eq!(1, devices.0[0].hostid);
+        assert_eq!("alive", devices.0[0].status);
+        assert_eq!(Some("writeable".to_string()), devices.0[0].observed_state);
+        assert_eq!(Some(100), devices.0[0].weight);
+    }
+
+    #[test]
+    fn backend_create_update_and_delete_host() {
+        let mut backend = backend_fixture();
+        let create_request = CreateHost {
+            host: "storage2".to_string(), ip: "10.0.0.2".to_string(), port: 7500,
+            getport: None, altip: None, altmask: None, status: None,
+        };
+
+        let create_response = backend.create_host(&create_request).unwrap();
+        assert_eq!(CreateHostResponse { hostid: 2, hostname: "storage2".to_string() }, create_response);
+        assert_eq!(Some(HostStatus::Down), backend.hosts.get(&2).map(|h| h.status));
+
+        let create_dup_result = backend.create_host(&create_request);
+        assert!(
+            matches!(create_dup_result, Err(MogError::HostExists(ref h)) if h == "storage2"),
+            "Create duplicate host result was {:?}", create_dup_result);
+
+        let update_request = UpdateHost {
+            host: "storage2".to_string(), ip: None, port: Some(7600),
+            getport: None, altip: None, altmask: None, status: Some("alive".to_string()),
+        };
+        backend.update_host(&update_request).unwrap();
+        {
+            let host = backend.hosts.get(&2).unwrap();
+            assert_eq!("10.0.0.2", host.ip);
+            assert_eq!(7600, host.http_port);
+            assert_eq!(HostStatus::Alive, host.status);
+        }
+
+        backend.create_device(&CreateDevice { hostname: "storage2".to_string(), devid: 2, state: None }).unwrap();
+        let delete_result = backend.delete_host(&DeleteHost { host: "storage2".to_string() });
+        assert!(
+            matches!(delete_result, Err(MogError::HostNotEmpty(ref h)) if h == "storage2"),
+            "Delete host with devices result was {:?}", delete_result);
+
+        backend.devices.remove(&2);
+        backend.delete_host(&DeleteHost { host: "storage2".to_string() }).unwrap();
+        assert!(!backend.hosts.contains_key(&2));
+
+        let delete_result = backend.delete_host(&DeleteHost { host: "storage2".to_string() });
+        assert!(
+            matches!(delete_result, Err(MogError::UnknownHost(ref h)) if h == "storage2"),
+            "Delete unknown host result was {:?}", delete_result);
+    }
+
+    #[test]
+    fn backend_create_device() {
+        let mut backend = backend_fixture();
+
+        let unknown_host_result = backend.create_device(&CreateDevice { hostname: "storage2".to_string(), devid: 2, state: None });
+        assert!(
+            matches!(unknown_host_result, Err(MogError::UnknownHost(ref h)) if h == "storage2"),
+            "Create device on unknown host result was {:?}", unknown_host_result);
+
+        let dup_result = backend.create_device(&CreateDevice { hostname: TEST_HOST.to_string(), devid: 1, state: None });
+        assert!(
+            matches!(dup_result, Err(MogError::DeviceExists(ref d)) if d == "1"),
+            "Create duplicate device result was {:?}", dup_result);
+
+        let bad_state_result = backend.create_device(&CreateDevice {
+            hostname: TEST_HOST.to_string(), devid: 2, state: Some("bogus".to_string()),
+        });
+        assert!(
+            matches!(bad_state_result, Err(MogError::UnknownState(ref s)) if s == "bogus"),
+            "Create device with bad state result was {:?}", bad_state_result);
+
+        backend.create_device(&CreateDevice {
+            hostname: TEST_HOST.to_string(), devid: 2, state: Some("readonly".to_string()),
+        }).unwrap();
+        assert_eq!(Some(DeviceState::Readonly), backend.devices.get(&2).map(|d| d.state));
+    }
+
+    #[test]
+    fn backend_set_state_and_weight() {
+        let mut backend = backend_fixture();
+        backend.create_host(&CreateHost {
+            host: "storage2".to_string(), ip: "10.0.0.2".to_string(), port: 7500,
+            getport: None, altip: None, altmask: None, status: None,
+        }).unwrap();
+
+        let mismatch_result = backend.set_state(&SetState { host: "storage2".to_string(), device: 1, state: "down".to_string() });
+        assert!(matches!(mismatch_result, Err(MogError::HostMismatch)), "Set state on wrong host result was {:?}", mismatch_result);
+
+        let unknown_result = backend.set_weight(&SetWeight { host: TEST_HOST.to_string(), device: 5, weight: 10 });
+        assert!(
+            matches!(unknown_result, Err(MogError::UnknownDevice(ref d)) if d == "5"),
+            "Set weight on unknown device result was {:?}", unknown_result);
+
+        backend.set_weight(&SetWeight { host: TEST_HOST.to_string(), device: 1, weight: 10 }).unwrap();
+        assert_eq!(Some(10), backend.devices.get(&1).map(|d| d.weight));
+
+        backend.set_state(&SetState { host: TEST_HOST.to_string(), device: 1, state: "dead".to_string() }).unwrap();
+        assert_eq!(Some(DeviceState::Dead), backend.devices.get(&1).map(|d| d.state));
+
+        let revive_result = backend.set_state(&SetState { host: TEST_HOST.to_string(), device: 1, state: "alive".to_string() });
+        assert!(matches!(revive_result, Err(MogError::StateTooHigh)), "Revive dead device result was {:?}", revive_result);
+    }
+
+    #[test]
+    fn backend_create_open_multiple_devices() {
+        let mut backend = multi_device_backend_fixture();
+        let mut request = CreateOpen {
+            domain: TEST_DOMAIN.to_string(), class: None,
+            key: "test/key/3".to_string(), multi_dest: true, size: None,
+        };
+
+        {
+            let co_response = backend.create_open(&request).unwrap();
+            let devids: Vec<u64> = co_response.paths.iter().map(|&(d, _)| d).collect();
+            assert_eq!(vec![ 1, 2, 3 ], devids);
+            assert_eq!(
+                format!("http://{}/{}/dev2/d/{}/k/{}?fid=5", TEST_HOST, TEST_BASE_PATH, TEST_DOMAIN, "test/key/3"),
+                co_response.paths[1].1.as_str());
+        }
+
+        {
+            request.multi_dest = false;
+            backend.set_device_state(1, DeviceState::Readonly).unwrap();
+            let co_response = backend.create_open(&request).unwrap();
+            let devids: Vec<u64> = co_response.paths.iter().map(|&(d, _)| d).collect();
+            assert_eq!(vec![ 2 ], devids);
+        }
+
+        {
+            for devid in 2..(TEST_DEVICE_COUNT + 1) {
+                backend.set_device_state(devid, DeviceState::Down).unwrap();
+            }
+            let co_result = backend.create_open(&request);
+            assert!(
+                matches!(co_result, Err(MogError::Other(ref c, _)) if c == "no_devices"),
+                "Create open with no writeable devices result was {:?}", co_result);
+        }
+    }
+
+    #[test]
+    fn backend_store_replicates_to_devices() {
+        let mut backend = multi_device_backend_fixture();
+        let new_content: &'static [u8] = b"This is new test content";
+
+        backend.store_bytes_content(&StoragePath::on_device(3, TEST_DOMAIN, TEST_KEY_2), new_content).unwrap();
+        {
+            let file = backend.domains[TEST_DOMAIN].file(TEST_KEY_2).unwrap();
+            assert_eq!(vec![ 1, 3 ], file.devids.iter().cloned().collect::<Vec<u64>>());
+        }
+
+        let info = backend.file_info(&FileInfo { domain: TEST_DOMAIN.to_string(), key: TEST_KEY_2.to_string() }).unwrap();
+        assert_eq!(2, info.devcount);
+
+        let mut content = vec![];
+        backend.get_content(&StoragePath::on_device(3, TEST_DOMAIN, TEST_KEY_2)).unwrap().read_to_end(&mut content).unwrap();
+        assert_eq!(new_content, &content[..]);
+
+        let missing_result = backend.get_content(&StoragePath::on_device(2, TEST_DOMAIN, TEST_KEY_2));
+        assert!(
+            matches!(missing_result, Err(MogError::NoContent(ref k)) if k == TEST_KEY_2),
+            "Get content from device without a copy result was {:?}", missing_result);
+
+        let unknown_result = backend.store_bytes_content(&StoragePath::on_device(9, TEST_DOMAIN, TEST_KEY_2), new_content);
+        assert!(
+            matches!(unknown_result, Err(MogError::UnknownDevice(ref d)) if d == "9"),
+            "Store content to unknown device result was {:?}", unknown_result);
+    }
+
+    #[test]
+    fn backend_get_paths_multiple_devices() {
+        let mut backend = multi_device_backend_fixture();
+        backend.store_bytes_content(&StoragePath::on_device(2, TEST_DOMAIN, TEST_KEY_2), TEST_CONTENT_1).unwrap();
+        let mut request = GetPaths { domain: TEST_DOMAIN.to_string(), key: TEST_KEY_2.to_string(), noverify: true, pathcount: None };
+
+        {
+            let paths = backend.get_paths(&request).unwrap().0;
+            assert_eq!(
+                vec![
+                    format!("http://{}/{}/dev1/d/{}/k/{}", TEST_HOST, TEST_BASE_PATH, TEST_DOMAIN, TEST_KEY_2),
+                    format!("http://{}/{}/dev2/d/{}/k/{}", TEST_HOST, TEST_BASE_PATH, TEST_DOMAIN, TEST_KEY_2),
+                ],
+                paths.iter().map(|u| u.as_str().to_string()).collect::<Vec<String>>());
+        }
+
+        {
+            request.pathcount = Some(1);
+            assert_eq!(1, backend.get_paths(&request).unwrap().0.len());
+            request.pathcount = None;
+        }
+
+        {
+            backend.set_device_state(2, DeviceState::Dead).unwrap();
+            let paths = backend.get_paths(&request).unwrap().0;
+            assert_eq!(1, paths.len());
+            assert!(paths[0].as_str().contains("/dev1/"));
+            assert!(!backend.domains[TEST_DOMAIN].file(TEST_KEY_2).unwrap().devids.contains(&2));
+        }
+
+        {
+            backend.set_device_state(1, DeviceState::Dead).unwrap();
+            assert!(backend.get_paths(&request).unwrap().0.is_empty());
+            let file = backend.domains[TEST_DOMAIN].file(TEST_KEY_2).unwrap();
+            assert!(file.content.is_none());
+        }
+    }
+
+    #[test]
+    fn backend_fid_url_scheme() {
+        let mut backend = backend_fixture();
+        backend.set_url_scheme(UrlScheme::Fid);
+
+        let fid = backend.file(TEST_DOMAIN, TEST_KEY_1).unwrap().unwrap().fid();
+        let request = GetPaths { domain: TEST_DOMAIN.to_string(), key: TEST_KEY_1.to_string(), noverify: true, pathcount: None };
+        let fid_segments = fid_path_segments(fid).join("/");
+        assert_eq!(
+            vec![ format!("http://{}/{}/dev1/{}", TEST_HOST, TEST_BASE_PATH, fid_segments) ],
+            backend.get_paths(&request).unwrap().0.iter().map(|u| u.as_str().to_string()).collect::<Vec<String>>());
+
+        let co_response = backend.create_open(&CreateOpen {
+            domain: TEST_DOMAIN.to_string(), class: None,
+            key: "test/key/3".to_string(), multi_dest: false, size: None,
+        }).unwrap();
+        assert_eq!(
+            format!("http://{}/{}/dev1/{}", TEST_HOST, TEST_BASE_PATH, fid_path_segments(co_response.fid).join("/")),
+            co_response.paths[0].1.as_str());
+
+        let path = backend.fid_storage_path(Some(1), co_response.fid).unwrap();
+        assert_eq!((Some(1), Some(co_response.fid), TEST_DOMAIN, "test/key/3"), (path.devid, path.fid, &path.domain[..], &path.key[..]));
+        assert_eq!(TEST_KEY_1, backend.fid_storage_path(None, fid).unwrap().key);
+        assert!(matches!(backend.fid_storage_path(None, 9999), Err(MogError::UnknownFid(..))));
+    }
+
+    #[test]
+    fn backend_allocates_unique_fids() {
+        let mut backend = backend_fixture();
+        let mut request = CreateOpen {
+            domain: TEST_DOMAIN.to_string(), class: None,
+            key: "test/key/3".to_string(), multi_dest: false, size: None,
+        };
+
+        let fid_3 = upload_file(&mut backend, &request, TEST_CONTENT_1);
+        request.key = "test/key/4".to_string();
+        let fid_4 = upload_file(&mut backend, &request, TEST_CONTENT_1);
+        assert_eq!(5, fid_3);
+        assert_eq!(6, fid_4);
+
+        // Replacing a key gets a new fid, and the old one goes away.
+        let new_fid_4 = upload_file(&mut backend, &request, TEST_CONTENT_1);
+        assert_eq!(7, new_fid_4);
+        assert!(backend.file_by_fid(fid_4).is_none());
+        assert_eq!(Some("test/key/4"), backend.file_by_fid(new_fid_4).map(|(_, f)| f.key()));
+
+        // Deleted fids aren't reused.
+        backend.delete(&Delete { domain: TEST_DOMAIN.to_string(), key: "test/key/3".to_string() }).unwrap();
+        assert!(backend.file_by_fid(fid_3).is_none());
+        request.key = "test/key/5".to_string();
+        assert_eq!(8, backend.create_open(&request).unwrap().fid);
+    }
+
+    #[test]
+    fn backend_file_info_by_fid() {
+        let mut backend = backend_fixture();
+
+        let info = backend.file_info_by_fid(3).unwrap();
+        assert_eq!(TEST_DOMAIN, info.domain);
+        assert_eq!(TEST_KEY_1, info.key);
+        assert_eq!(TEST_CONTENT_1.len() as u64, info.length);
+
+        backend.rename(&Rename {
+            domain: TEST_DOMAIN.to_string(), from_key: TEST_KEY_1.to_string(), to_key: "test/key/3".to_string(),
+        }).unwrap();
+        let info = backend.file_info_by_fid(3).unwrap();
+        assert_eq!(3, info.fid);
+        assert_eq!("test/key/3", info.key);
+
+        let unknown_result = backend.file_info_by_fid(99);
+        assert!(
+            matches!(unknown_result, Err(MogError::UnknownFid(ref f)) if f == "99"),
+            "File info for unknown fid result was {:?}", unknown_result);
+    }
+
+    #[test]
+    fn backend_list_fids() {
+        let backend = full_backend_fixture();
+
+        let fids = backend.list_fids(&ListFids { from: 101, to: 104 }).unwrap().0;
+        assert_eq!(vec![ 101, 102, 103, 104 ], fids.iter().map(|f| f.fid).collect::<Vec<u64>>());
+        assert_eq!(TEST_FULL_DOMAIN, fids[0].domain);
+        assert_eq!(format!("{}/key/1", TEST_KEY_PREFIX_1), fids[0].key);
+        assert_eq!(format!("{}/key/1", TEST_KEY_PREFIX_2), fids[1].key);
+        assert_eq!("default", fids[0].class);
+
+        assert!(backend.list_fids(&ListFids { from: 1, to: 100 }).unwrap().0.is_empty());
+        assert!(backend.list_fids(&ListFids { from: 104, to: 101 }).unwrap().0.is_empty());
+
+        let fids = backend.list_fids(&ListFids { from: 104, to: u64::MAX }).unwrap().0;
+        assert_eq!(Some(104), fids.first().map(|f| f.fid));
+    }
+
+    #[test]
+    fn backend_file_debug() {
+        let backend = backend_fixture();
+
+        let by_fid = backend.file_debug(&FileDebug { fid: Some(3), domain: None, key: None }).unwrap();
+        assert_eq!(FileDebugResponse {
+            fid: 3, domain: TEST_DOMAIN.to_string(), key: TEST_KEY_1.to_string(), class: "default".to_string(),
+            length: TEST_CONTENT_1.len() as u64, devcount: 1, devids: vec![ 1 ],
+        }, by_fid);
+
+        let by_key = backend.file_debug(&FileDebug {
+            fid: None, domain: Some(TEST_DOMAIN.to_string()), key: Some(TEST_KEY_1.to_string()),
+        }).unwrap();
+        assert_eq!(by_fid, by_key);
+
+        let unknown_result = backend.file_debug(&FileDebug { fid: Some(99), domain: None, key: None });
+        assert!(
+            matches!(unknown_result, Err(MogError::UnknownFid(ref f)) if f == "99"),
+            "File debug for unknown fid result was {:?}", unknown_result);
+    }
+
+    #[test]
+    fn backend_create_close_publishes_file() {
+        let mut backend = backend_fixture();
+        let co_response = backend.create_open(&CreateOpen {
+            domain: TEST_DOMAIN.to_string(), class: None,
+            key: TEST_KEY_1.to_string(), multi_dest: false, size: None,
+        }).unwrap();
+        let (devid, ref path) = co_response.paths[0];
+        let mut storage_path = StoragePath::on_device(devid, TEST_DOMAIN, TEST_KEY_1);
+        storage_path.fid = Some(co_response.fid);
+        let new_content: &'static [u8] = b"This is new test content";
+        backend.store_bytes_content(&storage_path, new_content).unwrap();
+
+        {
+            // The old version is still the one everyone sees.
+            let info = backend.file_info(&FileInfo { domain: TEST_DOMAIN.to_string(), key: TEST_KEY_1.to_string() }).unwrap();
+            assert_eq!(3, info.fid);
+            let mut content = vec![];
+            backend.get_content(&StoragePath::new(TEST_DOMAIN, TEST_KEY_1)).unwrap().read_to_end(&mut content).unwrap();
+            assert_eq!(TEST_CONTENT_1, &content[..]);
+        }
 
-    pub fn base_url(&self) -> Url {
-        self.1.clone()
-    }
+        backend.create_close(&CreateClose {
+            domain: TEST_DOMAIN.to_string(), key: TEST_KEY_1.to_string(),
+            fid: co_response.fid, devid: devid, path: path.clone(), checksum: None,
+        }).unwrap();
 
-    pub fn set_base_url(&mut self, new_url: Url) -> MogResult<()> {
-        let mut guard = try!(self.0.write());
-        guard.base_url = new_url.clone();
-        self.1 = new_url;
-        Ok(())
+        {
+            let info = backend.file_info(&FileInfo { domain: TEST_DOMAIN.to_string(), key: TEST_KEY_1.to_string() }).unwrap();
+            assert_eq!(co_response.fid, info.fid);
+            let mut content = vec![];
+            backend.get_content(&StoragePath::new(TEST_DOMAIN, TEST_KEY_1)).unwrap().read_to_end(&mut content).unwrap();
+            assert_eq!(new_content, &content[..]);
+            assert!(backend.file_by_fid(3).is_none());
+            assert!(backend.uploads.is_empty());
+        }
     }
-}
 
-impl Backend for SyncMemBackend {
-    fn create_domain(&self, request: &CreateDomain) -> MogResult<CreateDomain> {
-        try!(self.0.write()).create_domain(&request)
-    }
+    #[test]
+    fn backend_uncommitted_file_is_hidden() {
+        let mut backend = backend_fixture();
+        backend.create_open(&CreateOpen {
+            domain: TEST_DOMAIN.to_string(), class: None,
+            key: "test/key/3".to_string(), multi_dest: false, size: None,
+        }).unwrap();
 
-    fn create_open(&self, request: &CreateOpen) -> MogResult<CreateOpenResponse> {
-        try!(self.0.write()).create_open(&request)
+        let keys = backend.list_keys(&ListKeys { domain: TEST_DOMAIN.to_string(), prefix: None, after: None, limit: None }).unwrap();
+        assert!(!keys.0.contains(&"test/key/3".to_string()));
+        assert!(backend.file_info(&FileInfo { domain: TEST_DOMAIN.to_string(), key: "test/key/3".to_string() }).is_err());
+        assert!(backend.get_paths(&GetPaths {
+            domain: TEST_DOMAIN.to_string(), key: "test/key/3".to_string(), noverify: true, pathcount: None,
+        }).is_err());
     }
 
-    fn create_close(&self, _request: &CreateClose) -> MogResult<()> {
-        // There's nothing to do here, since the create_open request
-        // created the entry, and the storage server request already
-        // stored the file. Just say it worked. Thought: if there was
-        // no create_open or storage server request, should this
-        // return an error?
-        Ok(())
-    }
+    #[test]
+    fn backend_create_close_validates_request() {
+        let mut backend = multi_device_backend_fixture();
+        let co_response = backend.create_open(&CreateOpen {
+            domain: TEST_DOMAIN.to_string(), class: None,
+            key: "test/key/3".to_string(), multi_dest: false, size: None,
+        }).unwrap();
+        let (devid, ref path) = co_response.paths[0];
+        let close_request = CreateClose {
+            domain: TEST_DOMAIN.to_string(), key: "test/key/3".to_string(),
+            fid: co_response.fid, devid: devid, path: path.clone(), checksum: None,
+        };
 
-    fn create_class(&self, request: &CreateClass) -> MogResult<CreateClassResponse> {
-        // The in-memory backend doesn't have the concept of storage
-        // classes, so just return an Ok response echoing the request.
-        Ok(CreateClassResponse {
-            domain: request.domain.clone(),
-            class: request.class.clone(),
-            mindevcount: request.mindevcount,
-        })
-    }
+        {
+            let mut bad_request = close_request.clone();
+            bad_request.fid = 99;
+            let result = backend.create_close(&bad_request);
+            assert!(matches!(result, Err(MogError::Other(ref c, _)) if c == "no_temp_file"), "Close unknown fid result was {:?}", result);
+        }
 
-    fn get_paths(&self, request: &GetPaths) -> MogResult<GetPathsResponse> {
-        try!(self.0.read()).get_paths(&request)
-    }
-    
-    fn file_info(&self, request: &FileInfo) -> MogResult<FileInfoResponse> {
-        try!(self.0.read()).file_info(&request)
-    }
-    
-    fn delete(&self, request: &Delete) -> MogResult<()> {
-        try!(self.0.write()).delete(&request)
-    }
+        {
+            let mut bad_request = close_request.clone();
+            bad_request.key = "test/key/4".to_string();
+            let result = backend.create_close(&bad_request);
+            assert!(matches!(result, Err(MogError::Other(ref c, _)) if c == "key_mismatch"), "Close wrong key result was {:?}", result);
+        }
 
-    fn rename(&self, request: &Rename) -> MogResult<()> {
-        try!(self.0.write()).rename(&request)
-    }
+        {
+            let mut bad_request = close_request.clone();
+            bad_request.devid = 2;
+            let result = backend.create_close(&bad_request);
+            assert!(matches!(result, Err(MogError::Other(ref c, _)) if c == "invalid_destdev"), "Close wrong devid result was {:?}", result);
+        }
 
-    fn list_keys(&self, request: &ListKeys) -> MogResult<ListKeysResponse> {
-        try!(self.0.read()).list_keys(&request)
-    }
-}
+        {
+            let mut bad_request = close_request.clone();
+            bad_request.path = backend.device_url_for_key(devid, TEST_DOMAIN, "test/key/3");
+            let result = backend.create_close(&bad_request);
+            assert!(matches!(result, Err(MogError::Other(ref c, _)) if c == "invalid_path"), "Close wrong path result was {:?}", result);
+        }
 
-impl StorageBackend for SyncMemBackend {
-    fn url_for_key(&self, domain: &str, key: &str) -> Url {
-        url_for_key(&self.1, domain, key)
-    }
+        {
+            let result = backend.create_close(&close_request);
+            assert!(matches!(result, Err(MogError::NoContent(ref k)) if k == "test/key/3"), "Close without content result was {:?}", result);
+        }
 
-    fn file_metadata(&self, domain: &str, key: &str) -> MogResult<StorageMetadata> {
-        try!(self.0.read()).file_metadata(domain, key)
+        {
+            let mut storage_path = StoragePath::on_device(devid, TEST_DOMAIN, "test/key/3");
+            storage_path.fid = Some(co_response.fid);
+            backend.store_bytes_content(&storage_path, TEST_CONTENT_1).unwrap();
+            backend.create_close(&close_request).unwrap();
+            assert!(backend.file(TEST_DOMAIN, "test/key/3").unwrap().is_some());
+        }
     }
 
-    fn store_reader_content<R: Read>(&self, domain: &str, key: &str, reader: &mut R) -> MogResult<()> {
-        try!(self.0.write()).store_reader_content(domain, key, reader)
+    #[test]
+    fn backend_reap_uploads() {
+        let mut backend = backend_fixture();
+        let request = CreateOpen {
+            domain: TEST_DOMAIN.to_string(), class: None,
+            key: "test/key/3".to_string(), multi_dest: false, size: None,
+        };
+        let old_fid = backend.create_open(&request).unwrap().fid;
+        let new_fid = backend.create_open(&request).unwrap().fid;
+        backend.uploads.get_mut(&old_fid).unwrap().created = time::now_utc() - Duration::seconds(DEFAULT_UPLOAD_EXPIRY + 1);
+
+        assert_eq!(1, backend.reap_uploads().unwrap());
+        assert!(!backend.uploads.contains_key(&old_fid));
+        assert!(backend.uploads.contains_key(&new_fid));
+        assert_eq!(0, backend.reap_uploads().unwrap());
     }
 
-    fn store_bytes_content(&self, domain: &str, key: &str, content: &[u8]) -> MogResult<()> {
-        try!(self.0.write()).store_bytes_content(domain, key, content)
+    #[test]
+    fn backend_create_close_expired_upload() {
+        let mut backend = backend_fixture();
+        let co_response = backend.create_open(&CreateOpen {
+            domain: TEST_DOMAIN.to_string(), class: None,
+            key: "test/key/3".to_string(), multi_dest: false, size: None,
+        }).unwrap();
+        let (devid, ref path) = co_response.paths[0];
+        let mut storage_path = StoragePath::on_device(devid, TEST_DOMAIN, "test/key/3");
+        storage_path.fid = Some(co_response.fid);
+        backend.store_bytes_content(&storage_path, TEST_CONTENT_1).unwrap();
+
+        backend.set_upload_expiry(Duration::seconds(0));
+        let result = backend.create_close(&CreateClose {
+            domain: TEST_DOMAIN.to_string(), key: "test/key/3".to_string(),
+            fid: co_response.fid, devid: devid, path: path.clone(), checksum: None,
+        });
+        assert!(matches!(result, Err(MogError::Other(ref c, _)) if c == "upload_expired"), "Close expired upload result was {:?}", result);
+        assert!(backend.file(TEST_DOMAIN, "test/key/3").unwrap().is_none());
     }
 
-    fn get_content<W: Write>(&self, domain: &str, key: &str, writer: &mut W) -> MogResult<()> {
-        try!(self.0.read()).get_content(domain, key, writer)
+    #[test]
+    fn backend_create_close_size_mismatch() {
+        let mut backend = backend_fixture();
+        let co_response = backend.create_open(&CreateOpen {
+            domain: TEST_DOMAIN.to_string(), class: None,
+            key: "test/key/3".to_string(), multi_dest: false, size: Some(TEST_CONTENT_1.len() as u64 + 1),
+        }).unwrap();
+        let (devid, ref path) = co_response.paths[0];
+        let mut storage_path = StoragePath::on_device(devid, TEST_DOMAIN, "test/key/3");
+        storage_path.fid = Some(co_response.fid);
+        backend.store_bytes_content(&storage_path, TEST_CONTENT_1).unwrap();
+
+        let result = backend.create_close(&CreateClose {
+            domain: TEST_DOMAIN.to_string(), key: "test/key/3".to_string(),
+            fid: co_response.fid, devid: devid, path: path.clone(), checksum: None,
+        });
+        assert!(matches!(result, Err(MogError::Other(ref c, _)) if c == "size_mismatch"), "Close short upload result was {:?}", result);
+        assert!(backend.file(TEST_DOMAIN, "test/key/3").unwrap().is_none());
     }
-}
 
-pub fn url_for_key(base_url: &Url, domain: &str, key: &str) -> Url {
-    let mut new_path: Vec<&str> = base_url.path_segments().unwrap().collect();
-    new_path.extend([ "d", domain, "k" ].iter());
-    new_path.extend(key.split("/"));
-    new_path = new_path.into_iter().skip_while(|p| p.is_empty()).collect();
+    #[test]
+    fn backend_create_close_checksum() {
+        let mut backend = backend_fixture();
+        let co_response = backend.create_open(&CreateOpen {
+            domain: TEST_DOMAIN.to_string(), class: None,
+            key: "test/key/3".to_string(), multi_dest: false, size: None,
+        }).unwrap();
+        let (devid, ref path) = co_response.paths[0];
+        let mut storage_path = StoragePath::on_device(devid, TEST_DOMAIN, "test/key/3");
+        storage_path.fid = Some(co_response.fid);
+        backend.store_bytes_content(&storage_path, TEST_CONTENT_1).unwrap();
+
+        let mut request = CreateClose {
+            domain: TEST_DOMAIN.to_string(), key: "test/key/3".to_string(),
+            fid: co_response.fid, devid: devid, path: path.clone(),
+            checksum: Some(format!("MD5:{}", md5_hex(b"Some other content"))),
+        };
+        let result = backend.create_close(&request);
+        assert!(matches!(result, Err(MogError::Other(ref c, _)) if c == "checksum_mismatch"), "Close with wrong checksum result was {:?}", result);
+        assert!(backend.file(TEST_DOMAIN, "test/key/3").unwrap().is_none());
 
-    let mut key_url = base_url.clone();
-    key_url.set_path(&new_path.join("/"));
-    key_url
-}
+        request.checksum = Some("MD5:nonsense".to_string());
+        let result = backend.create_close(&request);
+        assert!(matches!(result, Err(MogError::Other(ref c, _)) if c == "bad_params"), "Close with bad checksum result was {:?}", result);
 
-#[cfg(test)]
-mod tests {
-    use mogilefs_common::{Backend, MogError};
-    use mogilefs_common::requests::*;
-    use std::io::Cursor;
-    use super::super::super::test_support::*;
+        request.checksum = Some(format!("MD5:{}", md5_hex(TEST_CONTENT_1).to_uppercase()));
+        backend.create_close(&request).unwrap();
+        assert!(backend.file(TEST_DOMAIN, "test/key/3").unwrap().is_some());
+    }
 
     #[test]
-    fn backend_get_file() {
+    fn backend_file_info_checksum() {
         let mut backend = backend_fixture();
+        let request = FileInfo { domain: TEST_DOMAIN.to_string(), key: TEST_KEY_1.to_string() };
+        assert_eq!(None, backend.file_info(&request).unwrap().checksum);
 
-        {
-            let file = backend.file(TEST_DOMAIN, TEST_KEY_1);
-            assert!(
-                matches!(file, Ok(Some(ref f)) if f.key() == TEST_KEY_1),
-                "Immutable present file was {:?}", file);
-        }
+        // It's only reported once the file's class has a hash type.
+        backend.create_class(&CreateClass {
+            domain: TEST_DOMAIN.to_string(), class: "default".to_string(), mindevcount: 2,
+            replpolicy: None, hashtype: Some("MD5".to_string()), update: true,
+        }).unwrap();
+        assert_eq!(Some(format!("MD5:{}", md5_hex(TEST_CONTENT_1))), backend.file_info(&request).unwrap().checksum);
 
-        {
-            let file = backend.file(TEST_DOMAIN, "test/key/3");
-            assert!(
-                matches!(file, Ok(None)),
-                "Immutable missing file was {:?}", file);
-        }
+        let storage_path = StoragePath::new(TEST_DOMAIN, TEST_KEY_1);
+        backend.store_reader_content(&storage_path, &mut Cursor::new(b"New content")).unwrap();
+        assert_eq!(Some(format!("MD5:{}", md5_hex(b"New content"))), backend.file_info(&request).unwrap().checksum);
+    }
 
-        {
-            let file = backend.file_mut(TEST_DOMAIN, TEST_KEY_1);
-            assert!(
-                matches!(file, Ok(Some(ref f)) if f.key() == TEST_KEY_1),
-                "Mutable present file was {:?}", file);
-        }
+    #[test]
+    fn backend_store_ranges() {
+        let mut backend = backend_fixture();
+        backend.set_max_file_size(Some(1024));
+        let co_response = backend.create_open(&CreateOpen {
+            domain: TEST_DOMAIN.to_string(), class: None,
+            key: "test/key/3".to_string(), multi_dest: false, size: None,
+        }).unwrap();
+        let (devid, ref path) = co_response.paths[0];
+        let mut storage_path = StoragePath::on_device(devid, TEST_DOMAIN, "test/key/3");
+        storage_path.fid = Some(co_response.fid);
+        let close_request = CreateClose {
+            domain: TEST_DOMAIN.to_string(), key: "test/key/3".to_string(),
+            fid: co_response.fid, devid: devid, path: path.clone(),
+            checksum: Some(format!("MD5:{}", md5_hex(TEST_CONTENT_1))),
+        };
+        let total = Some(TEST_CONTENT_1.len() as u64);
 
-        {
-            let file = backend.file_mut(TEST_DOMAIN, "test/key/3");
-            assert!(
-                matches!(file, Ok(None)),
-                "Mutable missing file was {:?}", file);
-        }
+        backend.store_reader_range(&storage_path, &mut Cursor::new(&TEST_CONTENT_1[10..]), 10, total).unwrap();
+        assert_eq!(0, backend.file_metadata(&storage_path).unwrap().size);
+        let result = backend.create_close(&close_request);
+        assert!(matches!(result, Err(MogError::Other(ref c, _)) if c == "incomplete_upload"), "Close with a gap result was {:?}", result);
 
-        // {
-        //     let file = backend.file("test_domain_2", TEST_KEY_1);
-        //     assert!(
-        //         matches!(file, Err(MogError::UnregDomain(ref d)) if d == "test_domain_2"),
-        //         "Immutable file from nonexistent domain was {:?}", file);
-        // }
+        backend.store_reader_range(&storage_path, &mut Cursor::new(&TEST_CONTENT_1[..4]), 0, total).unwrap();
+        assert_eq!(4, backend.file_metadata(&storage_path).unwrap().size);
 
-        // {
-        //     let file = backend.file_mut("test_domain_2", TEST_KEY_1);
-        //     assert!(
-        //         matches!(file, Err(MogError::UnregDomain(ref d)) if d == "test_domain_2"),
-        //         "Mutable file from nonexistent domain was {:?}", file);
-        // }
+        // The same range can be sent again, if it's not known whether
+        // it arrived.
+        backend.store_reader_range(&storage_path, &mut Cursor::new(&TEST_CONTENT_1[..4]), 0, total).unwrap();
+        backend.store_reader_range(&storage_path, &mut Cursor::new(&TEST_CONTENT_1[4..10]), 4, total).unwrap();
+        assert_eq!(TEST_CONTENT_1.len() as u64, backend.file_metadata(&storage_path).unwrap().size);
+
+        backend.create_close(&close_request).unwrap();
+        let mut content = vec![];
+        backend.get_content(&StoragePath::new(TEST_DOMAIN, "test/key/3")).unwrap().read_to_end(&mut content).unwrap();
+        assert_eq!(TEST_CONTENT_1, &content[..]);
     }
 
     #[test]
-    fn backend_create_domain() {
+    fn backend_store_bad_ranges() {
         let mut backend = backend_fixture();
+        let co_response = backend.create_open(&CreateOpen {
+            domain: TEST_DOMAIN.to_string(), class: None,
+            key: "test/key/3".to_string(), multi_dest: false, size: Some(10),
+        }).unwrap();
+        let mut storage_path = StoragePath::on_device(co_response.paths[0].0, TEST_DOMAIN, "test/key/3");
+        storage_path.fid = Some(co_response.fid);
 
-        let create_request = CreateDomain { domain: "test_domain_2".to_string() };
-        let create_result = backend.create_domain(&create_request);
-        assert!(create_result.is_ok(), "Create new domain result was {:?}", create_result);
+        let result = backend.store_bytes_range(&storage_path, 0, b"abcd", Some(20));
+        assert!(matches!(result, Err(MogError::Other(ref c, _)) if c == "size_mismatch"), "Store range with wrong total result was {:?}", result);
 
-        assert!(backend.domains.contains_key("test_domain_2"));
+        let result = backend.store_bytes_range(&storage_path, 8, b"abcd", None);
+        assert!(matches!(result, Err(MogError::Other(ref c, _)) if c == "too_large"), "Store range past the end result was {:?}", result);
 
-        let create_dup_request = CreateDomain { domain: TEST_DOMAIN.to_string() };
-        let create_dup_result = backend.create_domain(&create_dup_request);
-        assert!(
-            matches!(create_dup_result, Err(MogError::DomainExists(ref d)) if d == TEST_DOMAIN),
-            "Create duplicate domain result was {:?}", create_dup_result);
+        // Files which have been closed can't be added to.
+        let result = backend.store_bytes_range(&StoragePath::on_device(1, TEST_DOMAIN, TEST_KEY_1), 0, b"abcd", None);
+        assert!(matches!(result, Err(MogError::Other(ref c, _)) if c == "no_temp_file"), "Store range to closed file result was {:?}", result);
+
+        // Without knowing how big the file is, there's no telling how
+        // far in a range may start.
+        let co_response = backend.create_open(&CreateOpen {
+            domain: TEST_DOMAIN.to_string(), class: None,
+            key: "test/key/4".to_string(), multi_dest: false, size: None,
+        }).unwrap();
+        let mut storage_path = StoragePath::on_device(co_response.paths[0].0, TEST_DOMAIN, "test/key/4");
+        storage_path.fid = Some(co_response.fid);
+        let result = backend.store_bytes_range(&storage_path, 1000000000000, b"abcd", None);
+        assert!(matches!(result, Err(MogError::Other(ref c, _)) if c == "size_unknown"), "Store range of unknown size file result was {:?}", result);
+        let result = backend.store_bytes_range(&storage_path, 1099511627775, b"a", Some(1099511627776));
+        assert!(matches!(result, Err(MogError::Other(ref c, _)) if c == "size_unknown"), "Store range of client-sized file result was {:?}", result);
+        assert!(backend.uploads[&co_response.fid].file.content.is_none());
+
+        // No range can go past the largest file there can be.
+        backend.set_max_file_size(Some(u64::MAX));
+        let result = backend.store_bytes_range(&storage_path, u64::MAX, b"abcd", Some(u64::MAX));
+        assert!(matches!(result, Err(MogError::Other(ref c, _)) if c == "too_large"), "Store range past the largest file result was {:?}", result);
     }
 
     #[test]
-    fn backend_create_open() {
-        use url::Url;
+    fn backend_store_content_larger_than_declared() {
+        let mut backend = backend_fixture();
+        let co_response = backend.create_open(&CreateOpen {
+            domain: TEST_DOMAIN.to_string(), class: None,
+            key: "test/key/3".to_string(), multi_dest: false, size: Some(4),
+        }).unwrap();
+        let mut storage_path = StoragePath::on_device(co_response.paths[0].0, TEST_DOMAIN, "test/key/3");
+        storage_path.fid = Some(co_response.fid);
+        assert_eq!(Some(4), backend.content_limit(&storage_path).unwrap());
 
-        let sync_backend = sync_backend_fixture();
-        // let storage = MemStorage::new(
-        //     sync_backend.clone(),
-        //     Url::parse(format!("http://{}/{}", TEST_HOST, TEST_BASE_PATH).as_ref()).unwrap());
+        let result = backend.store_reader_content(&storage_path, &mut Cursor::new(TEST_CONTENT_1));
+        assert!(matches!(result, Err(MogError::Other(ref c, _)) if c == "too_large"), "Store too much content result was {:?}", result);
+        assert!(backend.uploads[&co_response.fid].file.size.is_none());
 
-        {
-            let req = CreateOpen { domain: TEST_DOMAIN.to_string(), class: None, key: "test/key/3".to_string(), multi_dest: true, size: None };
-            let mut backend = sync_backend.0.write().unwrap();
-            let co_result = backend.create_open(&req);
-            assert!(co_result.is_ok());
-            let co_response = co_result.unwrap();
-            assert_eq!(1, co_response.paths.len());
-            assert_eq!(
-                Url::parse(format!("http://{}/{}/d/{}/k/{}", TEST_HOST, TEST_BASE_PATH, TEST_DOMAIN, "test/key/3").as_ref()).unwrap(),
-                co_response.paths.iter().next().unwrap().1);
-        }
+        backend.store_reader_content(&storage_path, &mut Cursor::new(&TEST_CONTENT_1[..4])).unwrap();
+        assert_eq!(Some(4), backend.uploads[&co_response.fid].file.size);
+    }
 
-        {
-            let backend = sync_backend.0.read().unwrap();
-            let file = backend.file(TEST_DOMAIN, "test/key/3");
-            assert!(matches!(file, Ok(Some(..))), "Create opened file was {:?}", file);
-            let file = file.unwrap().unwrap();
-            assert_eq!("test/key/3", file.key());
-            assert!(file.content.is_none());
-            assert!(file.size.is_none());
-        }
+    #[test]
+    fn backend_max_file_size() {
+        let mut backend = backend_fixture();
+        backend.set_max_file_size(Some(1024));
+        backend.set_domain_max_file_size(TEST_DOMAIN, Some(4));
+        assert_eq!(Some(1024), backend.max_file_size("other_domain"));
+        assert_eq!(Some(4), backend.max_file_size(TEST_DOMAIN));
+
+        let result = backend.create_open(&CreateOpen {
+            domain: TEST_DOMAIN.to_string(), class: None,
+            key: "test/key/3".to_string(), multi_dest: false, size: Some(5),
+        });
+        assert!(matches!(result, Err(MogError::Other(ref c, _)) if c == "too_large"), "Open too large file result was {:?}", result);
 
-        {
-            let req = CreateOpen { domain: TEST_DOMAIN.to_string(), class: None, key: TEST_KEY_1.to_string(), multi_dest: true, size: None };
-            let mut backend = sync_backend.0.write().unwrap();
-            let co_result = backend.create_open(&req);
-            assert!(co_result.is_ok(), "Create open with duplicate key result was {:?}", co_result);
-            let co_response = co_result.unwrap();
-            assert_eq!(1, co_response.paths.len());
-            assert_eq!(
-                Url::parse(format!("http://{}/{}/d/{}/k/{}", TEST_HOST, TEST_BASE_PATH, TEST_DOMAIN, TEST_KEY_1).as_ref()).unwrap(),
-                co_response.paths.iter().next().unwrap().1);
-        }
+        let path = StoragePath::new(TEST_DOMAIN, TEST_KEY_1);
+        let result = backend.store_bytes_content(&path, TEST_CONTENT_1);
+        assert!(matches!(result, Err(MogError::Other(ref c, _)) if c == "too_large"), "Store too large file result was {:?}", result);
 
-        // {
-        //     let mut backend = sync_backend.0.lock().unwrap();
-        //     let co_result = backend.create_open("test_domain_2", "test/key/3", &storage);
-        //     assert!(
-        //         matches!(co_result, Err(MogError::UnregDomain(ref k)) if k == "test_domain_2"),
-        //         "Create open with unknown domain result was {:?}", co_result);
-        // }
+        backend.set_domain_max_file_size(TEST_DOMAIN, None);
+        assert_eq!(Some(1024), backend.content_limit(&path).unwrap());
+        backend.store_bytes_content(&path, TEST_CONTENT_1).unwrap();
     }
 
     #[test]
@@ -508,6 +2953,55 @@ mod tests {
         }
     }
 
+    #[test]
+    fn delete_content_from_device() {
+        let mut backend = multi_device_backend_fixture();
+        let path = StoragePath::new(TEST_DOMAIN, TEST_KEY_1);
+        backend.store_bytes_content(&StoragePath::on_device(1, TEST_DOMAIN, TEST_KEY_1), TEST_CONTENT_1).unwrap();
+        backend.store_bytes_content(&StoragePath::on_device(2, TEST_DOMAIN, TEST_KEY_1), TEST_CONTENT_1).unwrap();
+        let devids: Vec<u64> = backend.file(TEST_DOMAIN, TEST_KEY_1).unwrap().unwrap().devids.iter().cloned().collect();
+        assert!(devids.len() > 1, "Content was only stored on {:?}", devids);
+
+        backend.delete_content(&StoragePath::on_device(devids[0], TEST_DOMAIN, TEST_KEY_1)).unwrap();
+        assert!(!backend.file(TEST_DOMAIN, TEST_KEY_1).unwrap().unwrap().devids.contains(&devids[0]));
+        assert!(backend.get_content(&path).is_ok());
+
+        let result = backend.delete_content(&StoragePath::on_device(devids[0], TEST_DOMAIN, TEST_KEY_1));
+        assert!(matches!(result, Err(MogError::NoContent(..))), "Delete missing copy result was {:?}", result);
+
+        backend.delete_content(&path).unwrap();
+        assert!(matches!(backend.get_content(&path), Err(MogError::NoContent(..))));
+        assert!(backend.file(TEST_DOMAIN, TEST_KEY_1).unwrap().is_some());
+    }
+
+    #[test]
+    fn delete_file() {
+        let mut backend = backend_fixture();
+        backend.delete_file(&StoragePath::new(TEST_DOMAIN, TEST_KEY_1)).unwrap();
+        assert!(backend.file(TEST_DOMAIN, TEST_KEY_1).unwrap().is_none());
+
+        let fid = backend.create_open(&CreateOpen {
+            domain: TEST_DOMAIN.to_string(), class: None,
+            key: "test/key/3".to_string(), multi_dest: false, size: None,
+        }).unwrap().fid;
+        let mut upload_path = StoragePath::new(TEST_DOMAIN, "test/key/3");
+        upload_path.fid = Some(fid);
+        backend.delete_file(&upload_path).unwrap();
+        assert!(!backend.uploads.contains_key(&fid));
+    }
+
+    #[test]
+    fn file_metadata_etag_changes_with_content() {
+        let mut backend = backend_fixture();
+        let path = StoragePath::new(TEST_DOMAIN, TEST_KEY_1);
+        let old_metadata = backend.file_metadata(&path).unwrap();
+        assert_eq!(old_metadata.etag, backend.file_metadata(&path).unwrap().etag);
+
+        backend.store_bytes_content(&path, b"This is new test content").unwrap();
+        let new_metadata = backend.file_metadata(&path).unwrap();
+        assert!(old_metadata.etag != new_metadata.etag, "ETag {:?} didn't change", new_metadata.etag);
+    }
+
     #[test]
     fn url_for_key() {
         let backend = backend_fixture();
@@ -521,9 +3015,9 @@ mod tests {
         let backend = backend_fixture();
         let mut content = vec![];
 
-        backend.get_content(TEST_DOMAIN, TEST_KEY_1, &mut content).unwrap_or_else(|e| {
+        backend.get_content(&StoragePath::new(TEST_DOMAIN, TEST_KEY_1)).unwrap_or_else(|e| {
             panic!("Error retrieving content from {:?}: {}", TEST_KEY_1, e);
-        });
+        }).read_to_end(&mut content).unwrap();
 
         let content_ref: &[u8] = &content;
         assert_eq!(TEST_CONTENT_1, content_ref);
@@ -532,19 +3026,15 @@ mod tests {
     #[test]
     fn get_content_unknown_key() {
         let backend = backend_fixture();
-        let mut content = vec![];
-        assert!(matches!(backend.get_content(TEST_DOMAIN, "test/key/3", &mut content).unwrap_err(),
+        assert!(matches!(backend.get_content(&StoragePath::new(TEST_DOMAIN, "test/key/3")).unwrap_err(),
                          MogError::UnknownKey(ref k) if k == "test/key/3"));
-        assert!(content.is_empty());
     }
 
     #[test]
     fn get_content_no_content() {
         let backend = backend_fixture();
-        let mut content = vec![];
-        assert!(matches!(backend.get_content(TEST_DOMAIN, TEST_KEY_2, &mut content).unwrap_err(),
+        assert!(matches!(backend.get_content(&StoragePath::new(TEST_DOMAIN, TEST_KEY_2)).unwrap_err(),
                          MogError::NoContent(ref k) if k == TEST_KEY_2));
-        assert!(content.is_empty());
     }
 
     #[test]
@@ -552,11 +3042,11 @@ mod tests {
         let mut backend = backend_fixture();
         let new_content = Vec::from("This is new test content");
 
-        backend.store_reader_content(TEST_DOMAIN, TEST_KEY_1, &mut Cursor::new(new_content.clone())).unwrap_or_else(|e| {
+        backend.store_reader_content(&StoragePath::new(TEST_DOMAIN, TEST_KEY_1), &mut Cursor::new(new_content.clone())).unwrap_or_else(|e| {
             panic!("Error storing content to {:?}: {}", TEST_KEY_1, e);
         });
 
-        assert_eq!(&new_content, backend.domains[TEST_DOMAIN].file(TEST_KEY_1).unwrap().content.as_ref().unwrap());
+        assert_eq!(&new_content, &**backend.domains[TEST_DOMAIN].file(TEST_KEY_1).unwrap().content.as_ref().unwrap());
     }
 
     #[test]
@@ -564,60 +3054,184 @@ mod tests {
         let mut backend = backend_fixture();
         let new_content = Vec::from("This is new test content");
 
-        backend.store_reader_content(TEST_DOMAIN, TEST_KEY_2, &mut Cursor::new(new_content.clone())).unwrap_or_else(|e| {
+        backend.store_reader_content(&StoragePath::new(TEST_DOMAIN, TEST_KEY_2), &mut Cursor::new(new_content.clone())).unwrap_or_else(|e| {
             panic!("Error storing content to {:?}: {}", TEST_KEY_2, e);
         });
 
-        assert_eq!(&new_content, backend.domains[TEST_DOMAIN].file(TEST_KEY_2).unwrap().content.as_ref().unwrap());
+        assert_eq!(&new_content, &**backend.domains[TEST_DOMAIN].file(TEST_KEY_2).unwrap().content.as_ref().unwrap());
     }
 
     #[test]
     fn store_content_to_unknown_key() {
         let mut backend = backend_fixture();
         let new_content: &'static [u8] = b"This is new test content";
-        assert!(matches!(backend.store_reader_content(TEST_DOMAIN, "test/key/3", &mut Cursor::new(new_content)).unwrap_err(),
+        assert!(matches!(backend.store_reader_content(&StoragePath::new(TEST_DOMAIN, "test/key/3"), &mut Cursor::new(new_content)).unwrap_err(),
                          MogError::UnknownKey(ref k) if k == "test/key/3"));
     }
+
+    #[test]
+    fn backend_data_dir_survives_restart() {
+        let dir = temp_data_dir("restart");
+        let request = CreateOpen {
+            domain: TEST_DOMAIN.to_string(), class: Some("test_class".to_string()),
+            key: "test/key/3".to_string(), multi_dest: false, size: None,
+        };
+
+        let (fid, open_fid) = {
+            let mut backend = data_dir_backend_fixture(&dir);
+            backend.create_domain(&CreateDomain { domain: TEST_DOMAIN.to_string() }).unwrap();
+            backend.create_class(&CreateClass {
+                domain: TEST_DOMAIN.to_string(), class: "test_class".to_string(),
+                mindevcount: 1, replpolicy: None, hashtype: None, update: false,
+            }).unwrap();
+            let fid = upload_file(&mut backend, &request, TEST_CONTENT_1);
+
+            // Some of the changes end up in the snapshot, and some in
+            // the journal after it.
+            backend.compact_journal().unwrap();
+
+            backend.rename(&Rename {
+                domain: TEST_DOMAIN.to_string(),
+                from_key: "test/key/3".to_string(),
+                to_key: "test/key/4".to_string(),
+            }).unwrap();
+            (fid, backend.create_open(&request).unwrap().fid)
+        };
+
+        let mut backend = data_dir_backend_fixture(&dir);
+        let file_info = backend.file_info(&FileInfo { domain: TEST_DOMAIN.to_string(), key: "test/key/4".to_string() });
+        assert!(matches!(file_info, Ok(ref f) if f.fid == fid && f.class == "test_class"), "Restored file info was {:?}", file_info);
+        assert!(backend.file(TEST_DOMAIN, "test/key/3").unwrap().is_none());
+
+        let mut content = vec![];
+        backend.get_content(&StoragePath::new(TEST_DOMAIN, "test/key/4")).unwrap().read_to_end(&mut content).unwrap();
+        assert_eq!(TEST_CONTENT_1, &content[..]);
+
+        // Fids still aren't reused.
+        assert!(backend.uploads.contains_key(&open_fid));
+        assert!(backend.create_open(&request).unwrap().fid > open_fid);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn backend_data_dir_keeps_open_files() {
+        let dir = temp_data_dir("open_files");
+
+        let (co_response, storage_path) = {
+            let mut backend = data_dir_backend_fixture(&dir);
+            let co_response = backend.create_open(&CreateOpen {
+                domain: TEST_DOMAIN.to_string(), class: None,
+                key: "test/key/3".to_string(), multi_dest: false, size: None,
+            }).unwrap();
+            let mut storage_path = StoragePath::on_device(co_response.paths[0].0, TEST_DOMAIN, "test/key/3");
+            storage_path.fid = Some(co_response.fid);
+            backend.store_bytes_content(&storage_path, TEST_CONTENT_1).unwrap();
+            (co_response, storage_path)
+        };
+
+        let mut backend = data_dir_backend_fixture(&dir);
+        let (devid, ref path) = co_response.paths[0];
+        backend.create_close(&CreateClose {
+            domain: TEST_DOMAIN.to_string(), key: "test/key/3".to_string(),
+            fid: co_response.fid, devid: devid, path: path.clone(), checksum: None,
+        }).unwrap();
+
+        let mut content = vec![];
+        backend.get_content(&storage_path).unwrap().read_to_end(&mut content).unwrap();
+        assert_eq!(TEST_CONTENT_1, &content[..]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn backend_data_dir_keeps_received_ranges() {
+        let dir = temp_data_dir("open_ranges");
+
+        let (co_response, storage_path) = {
+            let mut backend = data_dir_backend_fixture(&dir);
+            let co_response = backend.create_open(&CreateOpen {
+                domain: TEST_DOMAIN.to_string(), class: None,
+                key: "test/key/3".to_string(), multi_dest: false, size: Some(TEST_CONTENT_1.len() as u64),
+            }).unwrap();
+            let mut storage_path = StoragePath::on_device(co_response.paths[0].0, TEST_DOMAIN, "test/key/3");
+            storage_path.fid = Some(co_response.fid);
+            backend.store_bytes_range(&storage_path, 0, &TEST_CONTENT_1[..5], None).unwrap();
+            backend.store_bytes_range(&storage_path, 15, &TEST_CONTENT_1[15..], None).unwrap();
+            backend.compact_journal().unwrap();
+            backend.store_bytes_range(&storage_path, 5, &TEST_CONTENT_1[5..10], None).unwrap();
+            (co_response, storage_path)
+        };
+
+        let mut backend = data_dir_backend_fixture(&dir);
+        assert_eq!(vec![ (0, 10), (15, 20) ], backend.uploads[&co_response.fid].received);
+        backend.store_bytes_range(&storage_path, 10, &TEST_CONTENT_1[10..15], None).unwrap();
+
+        let (devid, ref path) = co_response.paths[0];
+        backend.create_close(&CreateClose {
+            domain: TEST_DOMAIN.to_string(), key: "test/key/3".to_string(),
+            fid: co_response.fid, devid: devid, path: path.clone(), checksum: None,
+        }).unwrap();
+
+        let mut content = vec![];
+        backend.get_content(&storage_path).unwrap().read_to_end(&mut content).unwrap();
+        assert_eq!(TEST_CONTENT_1, &content[..]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
 
 #[cfg(test)]
 pub mod test_support {
-    use std::collections::HashMap;
     use super::*;
-    use super::super::MemDomain;
     use super::super::model::test_support::{domain_fixture, full_domain_fixture};
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use time;
     use url::Url;
 
     pub static TEST_HOST: &'static str = "test.host";
     pub static TEST_BASE_PATH: &'static str = "base_path";
+    pub static TEST_DEVICE_COUNT: u64 = 4;
 
     lazy_static!{
         static ref TEST_BASE_URL: Url = Url::parse(&format!("http://{}/{}", TEST_HOST, TEST_BASE_PATH)).unwrap();
     }
 
     pub fn backend_fixture() -> MemBackend {
-        let mut backend = MemBackend {
-            domains: HashMap::new(),
-            empty_domain: MemDomain::new(""),
-            base_url: TEST_BASE_URL.clone(),
-        };
-        let domain = domain_fixture();
-        backend.domains.insert(domain.name().to_string(), domain);
+        let mut backend = MemBackend::new(TEST_BASE_URL.clone());
+        backend.insert_domain(domain_fixture());
         backend
     }
 
     pub fn full_backend_fixture() -> MemBackend {
-        let mut backend = MemBackend {
-            domains: HashMap::new(),
-            empty_domain: MemDomain::new(""),
-            base_url: TEST_BASE_URL.clone(),
-        };
-        let domain = full_domain_fixture();
-        backend.domains.insert(domain.name().to_string(), domain);
+        let mut backend = MemBackend::new(TEST_BASE_URL.clone());
+        backend.insert_domain(full_domain_fixture());
+        backend
+    }
+
+    pub fn multi_device_backend_fixture() -> MemBackend {
+        let mut backend = MemBackend::with_devices(TEST_BASE_URL.clone(), TEST_DEVICE_COUNT);
+        backend.insert_domain(domain_fixture());
         backend
     }
 
     pub fn sync_backend_fixture() -> SyncMemBackend {
         SyncMemBackend::new(backend_fixture())
     }
+
+    /// An empty backend, keeping its data in `dir`.
+    pub fn data_dir_backend_fixture(dir: &Path) -> MemBackend {
+        let mut backend = MemBackend::new(TEST_BASE_URL.clone());
+        backend.open_data_dir(dir).unwrap();
+        backend
+    }
+
+    /// A fresh, empty directory to keep a backend's data in.
+    pub fn temp_data_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("filament-{}-{}", name, time::precise_time_ns()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
 }
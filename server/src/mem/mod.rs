@@ -1,10 +1,15 @@
 //! In-memory implementations of MogileFS, for testing purposes.
 
-pub use self::mem_backend::{MemBackend, SyncMemBackend};
-pub use self::model::{MemDomain, MemFileInfo};
+pub use self::journal::{Journal, JournalEntry};
+pub use self::mem_backend::{MemBackend, SharedContent, SyncMemBackend, UrlScheme, DEFAULT_UPLOAD_EXPIRY};
+pub use self::model::{MemClass, MemDomain, MemFileInfo, DEFAULT_CLASS, DEFAULT_MINDEVCOUNT};
+pub use self::model::{DeviceState, HostStatus, MemDevice, MemHost, MemUpload};
+pub use self::reaper::UploadReaper;
 
+mod journal;
 mod mem_backend;
 mod model;
+mod reaper;
 
 #[cfg(test)]
 pub mod test_support {
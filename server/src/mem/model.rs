@@ -1,17 +1,33 @@
 use mogilefs_common::{MogError, MogResult};
-use std::collections::{btree_map, BTreeMap};
+use std::cmp;
+use std::collections::{btree_map, BTreeMap, BTreeSet};
+use std::str::FromStr;
+use std::sync::Arc;
 use time::Tm;
+use url::Url;
+
+/// The name of the class every domain starts out with, and which
+/// files are put in when no class is requested.
+pub static DEFAULT_CLASS: &'static str = "default";
+
+/// The mindevcount of a domain's default class, as in MogileFS.
+pub const DEFAULT_MINDEVCOUNT: u64 = 2;
 
 #[derive(Debug, Default)]
 pub struct MemDomain {
     name: String,
+    classes: BTreeMap<String, MemClass>,
     files: BTreeMap<String, MemFileInfo>,
 }
 
 impl MemDomain {
     pub fn new(name: &str) -> MemDomain {
+        let mut classes = BTreeMap::new();
+        classes.insert(DEFAULT_CLASS.to_string(), MemClass::default_class());
+
         MemDomain {
             name: name.to_string(),
+            classes: classes,
             files: BTreeMap::new(),
         }
     }
@@ -20,6 +36,52 @@ impl MemDomain {
         &self.name
     }
 
+    /// Whether this domain has no files, and no classes other than
+    /// the default class.
+    pub fn is_empty(&self) -> bool {
+        self.classes.keys().all(|c| c == DEFAULT_CLASS) && self.files.is_empty()
+    }
+
+    pub fn class(&self, name: &str) -> Option<&MemClass> {
+        self.classes.get(name)
+    }
+
+    pub fn class_mut(&mut self, name: &str) -> Option<&mut MemClass> {
+        self.classes.get_mut(name)
+    }
+
+    pub fn classes<'a>(&'a self) -> btree_map::Values<'a, String, MemClass> {
+        self.classes.values()
+    }
+
+    pub fn add_class(&mut self, class: MemClass) -> MogResult<&MemClass> {
+        let name = class.name.clone();
+        if self.classes.contains_key(&name) {
+            return Err(MogError::ClassExists(name));
+        }
+
+        self.classes.insert(name.clone(), class);
+        Ok(self.class(&name).unwrap())
+    }
+
+    /// Check that a class could be removed, without removing it.
+    pub fn check_remove_class(&self, name: &str) -> MogResult<()> {
+        if name == DEFAULT_CLASS {
+            Err(MogError::Other("nodel_default_class".to_string(), Some("Cannot delete the default class".to_string())))
+        } else if !self.classes.contains_key(name) {
+            Err(MogError::ClassNotFound(name.to_string()))
+        } else if self.files.values().any(|f| f.class == name) {
+            Err(MogError::ClassHasFiles(name.to_string()))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn remove_class(&mut self, name: &str) -> MogResult<MemClass> {
+        try!(self.check_remove_class(name));
+        Ok(self.classes.remove(name).unwrap())
+    }
+
     pub fn file(&self, key: &str) -> Option<&MemFileInfo> {
         self.files.get(key)
     }
@@ -32,6 +94,18 @@ impl MemDomain {
         Files { inner: self.files.iter(), }
     }
 
+    /// Forget every replica stored on a device. Files left with no
+    /// replicas at all lose their content.
+    pub fn drop_device(&mut self, devid: u64) {
+        for file in self.files.values_mut() {
+            if file.devids.remove(&devid) && file.devids.is_empty() {
+                file.content = None;
+                file.size = None;
+                file.mtime = None;
+            }
+        }
+    }
+
     pub fn add_file(&mut self, key: &str, info: MemFileInfo) -> MogResult<&MemFileInfo> {
         self.files.insert(key.to_string(), info);
         Ok(self.file(key).unwrap())
@@ -71,12 +145,212 @@ impl<'a> Iterator for Files<'a> {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct MemClass {
+    name: String,
+    pub mindevcount: u64,
+    pub replpolicy: Option<String>,
+    pub hashtype: Option<String>,
+}
+
+impl MemClass {
+    pub fn new(name: &str, mindevcount: u64) -> MemClass {
+        MemClass {
+            name: name.to_string(),
+            mindevcount: mindevcount,
+            replpolicy: None,
+            hashtype: None,
+        }
+    }
+
+    pub fn default_class() -> MemClass {
+        let mut class = MemClass::new(DEFAULT_CLASS, DEFAULT_MINDEVCOUNT);
+        class.replpolicy = Some("MultipleHosts()".to_string());
+        class
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Set the hash type from a request argument. "NONE" (or blank)
+    /// means no hash type at all.
+    pub fn set_hashtype(&mut self, hashtype: &str) {
+        self.hashtype = match hashtype.to_uppercase().as_ref() {
+            "" | "NONE" => None,
+            h @ _ => Some(h.to_string()),
+        };
+    }
+}
+
+/// The status of a storage host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostStatus {
+    Alive,
+    Dead,
+    Down,
+}
+
+impl HostStatus {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            HostStatus::Alive => "alive",
+            HostStatus::Dead => "dead",
+            HostStatus::Down => "down",
+        }
+    }
+}
+
+impl FromStr for HostStatus {
+    type Err = MogError;
+
+    fn from_str(s: &str) -> MogResult<HostStatus> {
+        match s {
+            "alive" => Ok(HostStatus::Alive),
+            "dead" => Ok(HostStatus::Dead),
+            "down" => Ok(HostStatus::Down),
+            _ => Err(MogError::UnknownState(s.to_string())),
+        }
+    }
+}
+
+/// The state of a storage device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceState {
+    Alive,
+    Dead,
+    Down,
+    Readonly,
+    Drain,
+}
+
+impl DeviceState {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            DeviceState::Alive => "alive",
+            DeviceState::Dead => "dead",
+            DeviceState::Down => "down",
+            DeviceState::Readonly => "readonly",
+            DeviceState::Drain => "drain",
+        }
+    }
+
+    /// Whether new files may be stored on a device in this state.
+    pub fn is_writeable(&self) -> bool {
+        *self == DeviceState::Alive
+    }
+
+    /// Whether files may be read from a device in this state.
+    pub fn is_readable(&self) -> bool {
+        match *self {
+            DeviceState::Alive | DeviceState::Readonly | DeviceState::Drain => true,
+            DeviceState::Dead | DeviceState::Down => false,
+        }
+    }
+
+    /// What the monitor would report for a device in this state.
+    pub fn observed_state(&self) -> &'static str {
+        match *self {
+            DeviceState::Alive => "writeable",
+            DeviceState::Readonly | DeviceState::Drain => "readonly",
+            DeviceState::Dead | DeviceState::Down => "unreachable",
+        }
+    }
+}
+
+impl FromStr for DeviceState {
+    type Err = MogError;
+
+    fn from_str(s: &str) -> MogResult<DeviceState> {
+        match s {
+            "alive" => Ok(DeviceState::Alive),
+            "dead" => Ok(DeviceState::Dead),
+            "down" => Ok(DeviceState::Down),
+            "readonly" => Ok(DeviceState::Readonly),
+            "drain" => Ok(DeviceState::Drain),
+            _ => Err(MogError::UnknownState(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MemHost {
+    hostid: u64,
+    pub hostname: String,
+    pub ip: String,
+    pub http_port: u64,
+    pub http_get_port: Option<u64>,
+    pub altip: Option<String>,
+    pub altmask: Option<String>,
+    pub status: HostStatus,
+}
+
+impl MemHost {
+    pub fn new(hostid: u64, hostname: &str, ip: &str, http_port: u64) -> MemHost {
+        MemHost {
+            hostid: hostid,
+            hostname: hostname.to_string(),
+            ip: ip.to_string(),
+            http_port: http_port,
+            http_get_port: None,
+            altip: None,
+            altmask: None,
+            status: HostStatus::Down,
+        }
+    }
+
+    /// A live host standing in for the storage server at `url`.
+    pub fn from_url(hostid: u64, url: &Url) -> MemHost {
+        let host = url.host_str().unwrap_or("localhost");
+        let port = url.port_or_known_default().unwrap_or(80) as u64;
+        let mut mem_host = MemHost::new(hostid, host, host, port);
+        mem_host.status = HostStatus::Alive;
+        mem_host
+    }
+
+    pub fn hostid(&self) -> u64 {
+        self.hostid
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MemDevice {
+    devid: u64,
+    hostid: u64,
+    pub state: DeviceState,
+    pub weight: u64,
+}
+
+impl MemDevice {
+    pub fn new(devid: u64, hostid: u64) -> MemDevice {
+        MemDevice {
+            devid: devid,
+            hostid: hostid,
+            state: DeviceState::Alive,
+            weight: 100,
+        }
+    }
+
+    pub fn devid(&self) -> u64 {
+        self.devid
+    }
+
+    pub fn hostid(&self) -> u64 {
+        self.hostid
+    }
+}
+
 #[derive(Debug)]
 pub struct MemFileInfo {
     fid: u64,
     key: String,
-    pub content: Option<Vec<u8>>,
+    pub class: String,
+    pub devids: BTreeSet<u64>,
+    /// Shared with whoever's reading it, rather than copied.
+    pub content: Option<Arc<Vec<u8>>>,
     pub size: Option<u64>,
+    /// The hex MD5 digest of the content.
+    pub md5: Option<String>,
     pub mtime: Option<Tm>,
 }
 
@@ -85,8 +359,11 @@ impl MemFileInfo {
         MemFileInfo {
             fid: fid,
             key: key.to_string(),
+            class: DEFAULT_CLASS.to_string(),
+            devids: BTreeSet::new(),
             content: None,
             size: None,
+            md5: None,
             mtime: None,
         }
     }
@@ -100,10 +377,93 @@ impl MemFileInfo {
     }
 }
 
+/// A file which has been opened with `create_open`, but not yet
+/// closed. It only shows up in its domain once it's closed.
+#[derive(Debug)]
+pub struct MemUpload {
+    pub domain: String,
+    pub file: MemFileInfo,
+    /// The size `create_open` was told the file would be.
+    pub declared_size: Option<u64>,
+    /// The byte ranges, from the first byte up to (but not including)
+    /// the last, of the content which has been sent a piece at a time
+    /// so far. Empty if it was sent all at once.
+    pub received: Vec<(u64, u64)>,
+    pub paths: Vec<(u64, Url)>,
+    pub created: Tm,
+}
+
+impl MemUpload {
+    /// Record that bytes `from` up to `to` of the content have
+    /// arrived.
+    pub fn receive_range(&mut self, from: u64, to: u64) {
+        self.received = self.received_with(from, to);
+    }
+
+    /// Whether there are no gaps in the content that's arrived so far.
+    pub fn is_contiguous(&self) -> bool {
+        is_contiguous(&self.received)
+    }
+
+    /// Whether bytes `from` up to `to` arriving would fill in the last
+    /// of the gaps in the content.
+    pub fn completed_by(&self, from: u64, to: u64) -> bool {
+        is_contiguous(&self.received_with(from, to))
+    }
+
+    /// How many bytes from the start of the content have arrived
+    /// without a gap.
+    pub fn received_length(&self) -> u64 {
+        match self.received.first() {
+            Some(&(0, to)) => to,
+            Some(_) => 0,
+            None => self.file.size.unwrap_or(0),
+        }
+    }
+
+    fn received_with(&self, from: u64, to: u64) -> Vec<(u64, u64)> {
+        let mut ranges = self.received.clone();
+
+        // Content that was sent all at once counts as one big range.
+        if ranges.is_empty() {
+            if let Some(size) = self.file.size {
+                ranges.push((0, size));
+            }
+        }
+
+        if from < to {
+            ranges.push((from, to));
+        }
+
+        ranges.sort();
+        let mut coalesced: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+        for (from, to) in ranges {
+            if let Some(last) = coalesced.last_mut() {
+                if from <= last.1 {
+                    last.1 = cmp::max(last.1, to);
+                    continue;
+                }
+            }
+            coalesced.push((from, to));
+        }
+        coalesced
+    }
+}
+
+fn is_contiguous(ranges: &[(u64, u64)]) -> bool {
+    match ranges.len() {
+        0 => true,
+        1 => ranges[0].0 == 0,
+        _ => false,
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use mogilefs_common::MogError;
     use super::*;
     use super::super::super::test_support::*;
+    use time;
 
     #[test]
     fn create_domain() {
@@ -146,12 +506,12 @@ mod tests {
 
         {   // Modify the content of the file.
             let mut_file = domain.file_mut(TEST_KEY_1).unwrap();
-            mut_file.content = Some(new_content.clone());
+            mut_file.content = Some(Arc::new(new_content.clone()));
         }
 
         {   // Pull it back out and make sure that it's the same.
             let file = domain.file(TEST_KEY_1).unwrap();
-            assert_eq!(Some(new_content.clone()), file.content);
+            assert_eq!(Some(Arc::new(new_content.clone())), file.content);
         }
     }
 
@@ -179,7 +539,7 @@ mod tests {
 
         {   // Add a new file to the domain.
             let mut file = MemFileInfo::new(5, new_key);
-            file.content = Some(content.clone());
+            file.content = Some(Arc::new(content.clone()));
             file.size = Some(content.len() as u64);
             domain.add_file(new_key, file).unwrap();
         }
@@ -190,7 +550,7 @@ mod tests {
             let file = file.unwrap();
             assert_eq!(5, file.fid());
             assert_eq!(new_key, file.key());
-            assert_eq!(Some(&content), file.content.as_ref());
+            assert_eq!(Some(&content), file.content.as_ref().map(|c| &**c));
             assert_eq!(Some(content.len() as u64), file.size);
         }
 
@@ -206,6 +566,69 @@ mod tests {
         }
     }
 
+    #[test]
+    fn domain_add_class() {
+        let mut domain = domain_fixture();
+        assert!(domain.class(DEFAULT_CLASS).is_some());
+
+        assert!(matches!(domain.add_class(MemClass::new("test_class", 3)), Ok(ref c) if c.mindevcount == 3));
+        assert!(matches!(domain.add_class(MemClass::new("test_class", 1)), Err(MogError::ClassExists(ref c)) if c == "test_class"));
+        assert_eq!(3, domain.class("test_class").unwrap().mindevcount);
+    }
+
+    #[test]
+    fn domain_remove_class() {
+        let mut domain = domain_fixture();
+        domain.add_class(MemClass::new("test_class", 1)).unwrap();
+        domain.add_class(MemClass::new("other_class", 1)).unwrap();
+        domain.file_mut(TEST_KEY_1).unwrap().class = "other_class".to_string();
+
+        assert!(matches!(domain.remove_class("test_class"), Ok(ref c) if c.name() == "test_class"));
+        assert!(domain.class("test_class").is_none());
+        assert!(matches!(domain.remove_class("test_class"), Err(MogError::ClassNotFound(ref c)) if c == "test_class"));
+        assert!(matches!(domain.remove_class("other_class"), Err(MogError::ClassHasFiles(ref c)) if c == "other_class"));
+        assert!(domain.class("other_class").is_some());
+        assert!(matches!(domain.remove_class(DEFAULT_CLASS), Err(MogError::Other(ref op, _)) if op == "nodel_default_class"));
+    }
+
+    #[test]
+    fn class_set_hashtype() {
+        let mut class = MemClass::new("test_class", 1);
+        class.set_hashtype("md5");
+        assert_eq!(Some("MD5".to_string()), class.hashtype);
+        class.set_hashtype("NONE");
+        assert_eq!(None, class.hashtype);
+    }
+
+    #[test]
+    fn upload_receive_range() {
+        let mut upload = MemUpload {
+            domain: TEST_DOMAIN.to_string(),
+            file: MemFileInfo::new(5, "test/key/3"),
+            declared_size: None,
+            received: vec![],
+            paths: vec![],
+            created: time::now_utc(),
+        };
+        assert!(upload.is_contiguous());
+        assert_eq!(0, upload.received_length());
+
+        upload.receive_range(10, 20);
+        assert!(!upload.is_contiguous());
+        assert!(!upload.completed_by(0, 5));
+        assert!(upload.completed_by(0, 10));
+        assert_eq!(0, upload.received_length());
+
+        upload.receive_range(0, 5);
+        upload.receive_range(15, 25);
+        assert_eq!(vec![ (0, 5), (10, 25) ], upload.received);
+        assert_eq!(5, upload.received_length());
+
+        upload.receive_range(5, 10);
+        assert!(upload.is_contiguous());
+        assert_eq!(25, upload.received_length());
+    }
+
     #[test]
     fn domain_remove_file() {
         let mut domain = domain_fixture();
@@ -232,6 +655,7 @@ mod tests {
 #[cfg(test)]
 pub mod test_support {
     use super::*;
+    use super::super::super::checksum::md5_hex;
     use time;
 
     pub static TEST_DOMAIN: &'static str = "test_domain";
@@ -258,18 +682,24 @@ pub mod test_support {
             let key_p2 = format!("{}/key/{}", TEST_KEY_PREFIX_2, i+1);
 
             domain.files.insert(key_p1.clone(), MemFileInfo {
-                fid: 1,
+                fid: (2 * i + 101) as u64,
                 key: key_p1,
+                class: "default".to_string(),
+                devids: BTreeSet::new(),
                 content: None,
                 size: None,
+                md5: None,
                 mtime: None,
             });
 
             domain.files.insert(key_p2.clone(), MemFileInfo {
-                fid: 2,
+                fid: (2 * i + 102) as u64,
                 key: key_p2,
+                class: "default".to_string(),
+                devids: BTreeSet::new(),
                 content: None,
                 size: None,
+                md5: None,
                 mtime: None,
             });
         }
@@ -281,8 +711,11 @@ pub mod test_support {
         MemFileInfo {
             fid: 3,
             key: TEST_KEY_1.to_string(),
-            content: Some(Vec::from(TEST_CONTENT_1)),
+            class: "default".to_string(),
+            devids: vec![ 1 ].into_iter().collect(),
+            content: Some(Arc::new(Vec::from(TEST_CONTENT_1))),
             size: Some(TEST_CONTENT_1.len() as u64),
+            md5: Some(md5_hex(TEST_CONTENT_1)),
             mtime: Some(time::now_utc()),
         }
     }
@@ -291,8 +724,11 @@ pub mod test_support {
         MemFileInfo {
             fid: 4,
             key: TEST_KEY_2.to_string(),
+            class: "default".to_string(),
+            devids: BTreeSet::new(),
             content: None,
             size: None,
+            md5: None,
             mtime: None,
         }
     }
@@ -0,0 +1,92 @@
+//! A background thread that throws away files which were opened with
+//! `create_open` but never closed, like the tempfile cleanup in
+//! MogileFS.
+
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use super::SyncMemBackend;
+
+pub struct UploadReaper {
+    running: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl UploadReaper {
+    /// Start reaping expired uploads from `backend`, checking every
+    /// `interval`.
+    pub fn spawn(backend: SyncMemBackend, interval: Duration) -> io::Result<UploadReaper> {
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+
+        let handle = try!(thread::Builder::new().name("upload reaper".to_string()).spawn(move|| {
+            while thread_running.load(Ordering::SeqCst) {
+                thread::park_timeout(interval);
+
+                if !thread_running.load(Ordering::SeqCst) {
+                    break;
+                }
+
+                match backend.reap_uploads() {
+                    Ok(0) => {},
+                    Ok(n) => info!("Reaped {} abandoned upload(s)", n),
+                    Err(e) => error!("Error reaping abandoned uploads: {}", e),
+                }
+            }
+        }));
+
+        Ok(UploadReaper {
+            running: running,
+            handle: Some(handle),
+        })
+    }
+
+    /// Stop the reaper thread, and wait for it to finish.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for UploadReaper {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mogilefs_common::Backend;
+    use mogilefs_common::requests::*;
+    use std::thread;
+    use std::time::Duration;
+    use super::*;
+    use super::super::super::test_support::*;
+    use time;
+
+    #[test]
+    fn reaper_reaps_expired_uploads() {
+        let backend = sync_backend_fixture();
+        backend.set_upload_expiry(time::Duration::seconds(0)).unwrap();
+        backend.create_open(&CreateOpen {
+            domain: TEST_DOMAIN.to_string(), class: None,
+            key: "test/key/3".to_string(), multi_dest: false, size: None,
+        }).unwrap();
+
+        let reaper = UploadReaper::spawn(backend.clone(), Duration::from_millis(10)).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        reaper.stop();
+
+        assert_eq!(0, backend.reap_uploads().unwrap());
+    }
+}
@@ -1,2 +1,3 @@
+pub mod sidechannel;
 pub mod tracker;
 pub mod storage;
@@ -0,0 +1,184 @@
+//! The line-based side channel mogstored runs next to its HTTP
+//! server, which trackers and fsck use to check on the content a
+//! storage node holds without downloading it.
+//!
+//! Each command is a line naming a storage path, and so is each
+//! response:
+//!
+//! ```text
+//! command = "size /dev1/0/000/001/0000001927.fid\r\n"
+//! response = "/dev1/0/000/001/0000001927.fid 4\r\n"
+//! command = "md5 /dev1/0/000/001/0000001927.fid fsck\r\n"
+//! response = "/dev1/0/000/001/0000001927.fid md5=e2fc714c4727ee9395f324cd2e7f331f\r\n"
+//! ```
+//!
+//! Content which isn't there has a size, or digest, of `-1`.
+
+use mogilefs_common::{MogError, MogResult};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread;
+use super::super::backend::{parse_url_path, StorageBackend, StoragePath, UrlPath};
+use super::super::checksum::Md5Writer;
+use url::percent_encoding;
+
+pub struct SideChannelListener<B: StorageBackend> {
+    listener: TcpListener,
+    backend: Arc<B>,
+}
+
+impl<B: 'static + StorageBackend> SideChannelListener<B> {
+    pub fn new<S: ToSocketAddrs>(addr: S, backend: B) -> Result<SideChannelListener<B>, io::Error> {
+        Ok(SideChannelListener {
+            listener: try!(TcpListener::bind(addr)),
+            backend: Arc::new(backend),
+        })
+    }
+
+    pub fn run(&self) {
+        for stream in self.listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let conn_backend = self.backend.clone();
+
+                    thread::spawn(move|| {
+                        let peer_addr = stream.peer_addr();
+                        info!("New side channel connection from {:?}", peer_addr);
+                        match handle_connection(stream, conn_backend) {
+                            Ok(_) => {},
+                            Err(e) => {
+                                error!("Error handling side channel connection from {:?}: {}", peer_addr, e);
+                            }
+                        }
+                        info!("Shutting down side channel connection from {:?}", peer_addr);
+                    });
+                },
+                Err(e) => {
+                    error!("Side channel connection failed: {}", e);
+                }
+            }
+        }
+    }
+}
+
+fn handle_connection<B: StorageBackend>(mut writer: TcpStream, backend: Arc<B>) -> Result<(), io::Error> {
+    let reader = BufReader::new(try!(writer.try_clone()));
+
+    for line in reader.lines() {
+        let line = try!(line);
+        let command = line.trim();
+        if command.is_empty() {
+            continue;
+        }
+
+        debug!("side channel command = {:?}", command);
+        let response = handle_command(&*backend, command);
+        debug!("side channel response = {:?}", response);
+        try!(write!(writer, "{}\r\n", response));
+    }
+
+    Ok(())
+}
+
+/// The response to a single side channel command, without its line
+/// ending.
+pub fn handle_command<B: StorageBackend>(backend: &B, command: &str) -> String {
+    let mut args = command.split_whitespace();
+
+    // The md5 command may also say why it's being asked, which
+    // doesn't make any difference here.
+    match (args.next(), args.next()) {
+        (Some("size"), Some(uri)) => {
+            match uri_storage_path(backend, uri).and_then(|path| backend.file_metadata(&path)) {
+                Ok(metadata) => format!("{} {}", uri, metadata.size),
+                Err(ref e) if is_missing(e) => format!("{} -1", uri),
+                Err(e) => format!("ERROR: {}", e),
+            }
+        },
+        (Some("md5"), Some(uri)) => {
+            match uri_storage_path(backend, uri).and_then(|path| content_md5(backend, &path)) {
+                Ok(md5) => format!("{} md5={}", uri, md5),
+                Err(ref e) if is_missing(e) => format!("{} md5=-1", uri),
+                Err(e) => format!("ERROR: {}", e),
+            }
+        },
+        _ => format!("ERROR: unknown command {:?}", command),
+    }
+}
+
+/// The storage path a side channel command names, which is laid out
+/// like a storage URL's path.
+fn uri_storage_path<B: StorageBackend>(backend: &B, uri: &str) -> MogResult<StoragePath> {
+    let segments: Vec<String> = uri.split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| percent_encoding::percent_decode(s.as_bytes()).decode_utf8_lossy().into_owned())
+        .collect();
+
+    match parse_url_path(&segments) {
+        Ok(UrlPath::Fid(devid, fid)) => backend.fid_storage_path(devid, fid),
+        Ok(UrlPath::DomainKey(path)) => Ok(path),
+        Err(e) => Err(MogError::Other("bad_params".to_string(), Some(e))),
+    }
+}
+
+fn content_md5<B: StorageBackend>(backend: &B, path: &StoragePath) -> MogResult<String> {
+    match try!(backend.file_metadata(path)).md5 {
+        Some(md5) => Ok(md5),
+        None => {
+            // Content stored before digests were kept has to be read
+            // to work it out.
+            let mut writer = Md5Writer::new(io::sink());
+            try!(io::copy(&mut try!(backend.get_content(path)), &mut writer));
+            Ok(writer.md5_hex())
+        },
+    }
+}
+
+fn is_missing(err: &MogError) -> bool {
+    match *err {
+        MogError::UnknownKey(..) | MogError::UnknownFid(..) | MogError::UnknownDevice(..) | MogError::NoContent(..) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::checksum::md5_hex;
+    use super::super::super::test_support::*;
+
+    #[test]
+    fn size_command() {
+        let backend = sync_backend_fixture();
+        assert_eq!(format!("/dev1/d/test_domain/k/test/key/1 {}", TEST_CONTENT_1.len()),
+                   handle_command(&backend, "size /dev1/d/test_domain/k/test/key/1"));
+        assert_eq!(format!("/dev1/0/000/000/0000000003.fid {}", TEST_CONTENT_1.len()),
+                   handle_command(&backend, "size /dev1/0/000/000/0000000003.fid"));
+    }
+
+    #[test]
+    fn md5_command() {
+        let backend = sync_backend_fixture();
+        assert_eq!(format!("/dev1/0/000/000/0000000003.fid md5={}", md5_hex(TEST_CONTENT_1)),
+                   handle_command(&backend, "md5 /dev1/0/000/000/0000000003.fid fsck"));
+    }
+
+    #[test]
+    fn missing_content() {
+        let backend = sync_backend_fixture();
+        assert_eq!("/dev1/0/000/000/0000000099.fid -1", handle_command(&backend, "size /dev1/0/000/000/0000000099.fid"));
+        assert_eq!("/dev1/d/test_domain/k/test/key/2 -1", handle_command(&backend, "size /dev1/d/test_domain/k/test/key/2"));
+        assert_eq!("/dev2/d/test_domain/k/test/key/1 md5=-1", handle_command(&backend, "md5 /dev2/d/test_domain/k/test/key/1"));
+        // A key that ends like a fid's path is still a key.
+        assert_eq!("/dev1/d/test_domain/k/0/000/000/0000000003.fid -1", handle_command(&backend, "size /dev1/d/test_domain/k/0/000/000/0000000003.fid"));
+    }
+
+    #[test]
+    fn bad_commands() {
+        let backend = sync_backend_fixture();
+        assert!(handle_command(&backend, "size /nonsense").starts_with("ERROR: "));
+        assert!(handle_command(&backend, "sha1 /dev1/0/000/000/0000000003.fid").starts_with("ERROR: "));
+        assert!(handle_command(&backend, "size").starts_with("ERROR: "));
+    }
+}
@@ -2,89 +2,373 @@ use iron::headers;
 use iron::method::Method;
 use iron::modifiers::Header;
 use iron::status::Status;
-use iron::{Handler, IronError, IronResult, Request, Response};
-use mogilefs_common::MogError;
+use iron::{Handler, IronError, IronResult, Request, Response, Url};
+use mogilefs_common::{MogError, MogResult};
+use rustc_serialize::base64::FromBase64;
 use std::any::Any;
+use std::cmp;
 use std::error::Error;
+use std::io::{Read, Seek, SeekFrom};
 use std::ops::Deref;
-use super::super::backend::StorageBackend;
+use std::u64;
+use super::super::backend::{parse_url_path, too_large, StorageBackend, StorageMetadata, StoragePath, UrlPath};
+use super::super::checksum::Md5CheckReader;
+use super::super::range::{byte_ranges, if_range_matches, ByteRanges, ContentBody, MultipartBody};
+use super::super::signing::UrlSigner;
+use url::form_urlencoded;
 
 pub struct StorageHandler<B: StorageBackend> {
     backend: B,
+    delete_files: bool,
+    url_signer: Option<UrlSigner>,
+    read_url_signer: Option<UrlSigner>,
 }
 
 impl<B: StorageBackend> StorageHandler<B> {
     pub fn new(backend: B) -> StorageHandler<B> {
         StorageHandler {
             backend: backend,
+            delete_files: false,
+            url_signer: None,
+            read_url_signer: None,
         }
     }
 
-    fn handle_get(&self, _request: &Request, domain: &str, key: &str) -> IronResult<Response> {
-        let metadata = try!(self.backend.file_metadata(domain, key).map_err(|e| coerce_mogerror(e)));
-        let mut content = vec![];
-        try!(self.backend.get_content(domain, key, &mut content).map_err(|e| coerce_mogerror(e)));
-        Ok(Response::with((
+    /// Set whether a DELETE request removes the file from the tracker
+    /// as well as its content. By default, only the content goes.
+    pub fn set_delete_files(&mut self, delete_files: bool) {
+        self.delete_files = delete_files;
+    }
+
+    /// Set the signer that checks PUT and DELETE requests use a path
+    /// the tracker signed with the same secret when the file was
+    /// opened. With no signer, any PUT for an open file, and any
+    /// DELETE, is accepted.
+    pub fn set_url_signer(&mut self, signer: Option<UrlSigner>) {
+        self.url_signer = signer;
+    }
+
+    /// Set the signer that checks GET and HEAD requests use a URL
+    /// `get_paths` signed with the same secret, which hasn't expired.
+    /// A file that's still open may also be read through the URL it's
+    /// being stored through. With no signer, anyone may read any
+    /// file.
+    pub fn set_read_url_signer(&mut self, signer: Option<UrlSigner>) {
+        self.read_url_signer = signer;
+    }
+
+    fn handle_get(&self, request: &Request, path: &StoragePath) -> IronResult<Response> {
+        let metadata = try!(self.backend.file_metadata(path).map_err(|e| coerce_mogerror(e)));
+        let etag = headers::EntityTag::strong(metadata.etag.clone());
+
+        if !modified_since(request, &metadata, &etag) {
+            return Ok(Response::with((
+                Status::NotModified,
+                Header(headers::LastModified(headers::HttpDate(metadata.mtime))),
+                Header(headers::ETag(etag)),)));
+        }
+
+        // Only as much of a file that's being uploaded a piece at a
+        // time as has arrived without any gaps is served, so that a
+        // HEAD request says where to carry on from.
+        let mut content = try!(self.backend.get_content(path).map_err(|e| coerce_mogerror(e)));
+        let end = try!(content.seek(SeekFrom::End(0)).map_err(|e| coerce_mogerror(MogError::from(e))));
+        let size = cmp::min(end, metadata.size);
+
+        let mut response = Response::with((
             Status::Ok,
             Header(headers::LastModified(headers::HttpDate(metadata.mtime))),
-            Header(headers::ContentLength(metadata.size as u64)),
-            content,)))
+            Header(headers::ETag(etag)),
+            Header(headers::AcceptRanges(vec![ headers::RangeUnit::Bytes ])),));
+
+        // A Range header only counts if the content hasn't changed
+        // since the If-Range header says it was fetched.
+        let ranges = match (request.headers.get::<headers::Range>(), request.headers.get::<headers::IfRange>()) {
+            (Some(_), Some(if_range)) if !if_range_matches(if_range, &metadata) => ByteRanges::Whole,
+            (Some(range), _) => byte_ranges(range, size),
+            (None, _) => ByteRanges::Whole,
+        };
+
+        // The body's attached for HEAD requests too, so that the
+        // Content-Length isn't reset to 0; it isn't sent.
+        match ranges {
+            ByteRanges::Whole => {
+                response.headers.set(headers::ContentLength(size));
+                response.body = Some(Box::new(ContentBody::new(content, 0, size)));
+            },
+            ByteRanges::Partial(ref ranges) if ranges.len() == 1 => {
+                let (from, to) = ranges[0];
+                debug!("Limiting response to range {}-{} of {}", from, to, size);
+                response.status = Some(Status::PartialContent);
+                response.headers.set(headers::ContentRange(headers::ContentRangeSpec::Bytes {
+                    range: Some((from, to)),
+                    instance_length: Some(size),
+                }));
+                response.headers.set(headers::ContentLength(to - from + 1));
+                response.body = Some(Box::new(ContentBody::new(content, from, to - from + 1)));
+            },
+            ByteRanges::Partial(ranges) => {
+                debug!("Limiting response to ranges {:?} of {}", ranges, size);
+                let body = MultipartBody::new(content, ranges, size);
+                response.status = Some(Status::PartialContent);
+                response.headers.set_raw("Content-Type", vec![ body.content_type().into_bytes() ]);
+                response.headers.set(headers::ContentLength(body.content_length()));
+                response.body = Some(Box::new(body));
+            },
+            ByteRanges::Unsatisfiable => {
+                response.status = Some(Status::RangeNotSatisfiable);
+                response.headers.set(headers::ContentRange(headers::ContentRangeSpec::Bytes {
+                    range: None,
+                    instance_length: Some(size),
+                }));
+                response.headers.set(headers::ContentLength(0));
+            },
+        }
+
+        Ok(response)
     }
 
-    fn handle_put(&self, request: &mut Request, domain: &str, key: &str) -> IronResult<Response> {
-        match self.backend.store_reader_content(domain, key, &mut request.body) {
+    fn handle_put(&self, request: &mut Request, path: &StoragePath) -> IronResult<Response> {
+        // Content may only be stored for a file that's been opened,
+        // and not closed yet, through the tracker.
+        match (path.fid, try!(self.backend.is_open_upload(path).map_err(|e| coerce_mogerror(e)))) {
+            (Some(_), true) => {},
+            _ => return Ok(Response::with((Status::Forbidden, format!("{:?} is not an open file\n", path.key)))),
+        }
+
+        if let Err(e) = self.verify_store_signature(request, path) {
+            return Ok(Response::with((Status::Forbidden, format!("{}\n", e))));
+        }
+
+        // A Content-Range header means the body is just one piece of
+        // the content of a file that's still open, so that an upload
+        // can be resumed.
+        let range = match request.headers.get::<headers::ContentRange>() {
+            Some(&headers::ContentRange(headers::ContentRangeSpec::Bytes { range: Some((from, to)), instance_length })) if from <= to => {
+                match (to - from).checked_add(1) {
+                    Some(length) => Some((from, length, instance_length)),
+                    None => return Ok(Response::with((Status::RangeNotSatisfiable, "Content-Range is larger than any file can be\n"))),
+                }
+            },
+            Some(_) => return Ok(Response::with((Status::BadRequest, "Invalid Content-Range header\n"))),
+            None => None,
+        };
+
+        // Don't bother reading a body that's too big to keep.
+        let offset = range.map(|(offset, _, _)| offset).unwrap_or(0);
+        let limit = try!(self.backend.content_limit(path).map_err(|e| coerce_mogerror(e)));
+        match (limit, range, request.headers.get::<headers::ContentLength>()) {
+            (_, Some((_, range_length, _)), Some(&headers::ContentLength(length))) if length != range_length => {
+                return Ok(Response::with((Status::BadRequest, "Content-Length does not match Content-Range\n")));
+            },
+            (Some(limit), _, Some(&headers::ContentLength(length))) if offset.checked_add(length).map_or(true, |end| end > limit) => {
+                return Ok(Response::with((Status::PayloadTooLarge, format!("{}\n", too_large(path, limit)))));
+            },
+            _ => {},
+        }
+
+        let expected_md5 = match request.headers.get_raw("Content-MD5") {
+            Some(values) => match values.get(0).and_then(|v| v[..].from_base64().ok()) {
+                Some(digest) if digest.len() == 16 => Some(digest),
+                _ => return Ok(Response::with((Status::BadRequest, "Invalid Content-MD5 header\n"))),
+            },
+            None => None,
+        };
+
+        // The content's only kept if it matches its Content-MD5
+        // header, which can't be known until the last of it arrives.
+        let mut body = (&mut request.body).take(range.map(|(_, length, _)| length).unwrap_or(u64::MAX));
+        let result = match expected_md5 {
+            Some(expected_md5) => {
+                let mut checked_body = Md5CheckReader::new(&mut body, expected_md5);
+                match self.store(path, &mut checked_body, range) {
+                    Err(_) if checked_body.mismatched() => {
+                        return Ok(Response::with((Status::BadRequest, "Content does not match its Content-MD5 header\n")));
+                    },
+                    result => result,
+                }
+            },
+            None => self.store(path, &mut body, range),
+        };
+
+        match result {
             Ok(_) => Ok(Response::with((Status::Ok,))),
+            Err(ref e @ MogError::Other(..)) if e.error_kind() == "too_large" => {
+                return Ok(Response::with((Status::PayloadTooLarge, format!("{}\n", e))));
+            },
+            Err(ref e @ MogError::Other(..)) if e.error_kind() == "size_mismatch" || e.error_kind() == "size_unknown" => {
+                return Ok(Response::with((Status::BadRequest, format!("{}\n", e))));
+            },
+            Err(ref e @ MogError::Other(..)) if e.error_kind() == "bad_range" => {
+                return Ok(Response::with((Status::RangeNotSatisfiable, format!("{}\n", e))));
+            },
+            Err(ref e @ MogError::Other(..)) if e.error_kind() == "no_temp_file" => {
+                return Ok(Response::with((Status::Conflict, format!("{}\n", e))));
+            },
             Err(MogError::UnknownKey(ref k)) => {
                 return Ok(Response::with((Status::NotFound, format!("Unknown key: {:?}\n", k))));
             },
+            Err(MogError::UnknownDevice(ref d)) => {
+                return Ok(Response::with((Status::NotFound, format!("Unknown device: {}\n", d))));
+            },
+            Err(MogError::UnknownFid(ref f)) => {
+                return Ok(Response::with((Status::NotFound, format!("Unknown fid: {}\n", f))));
+            },
             Err(e) => {
                 let modifier = (Status::InternalServerError, format!("{}\n", e.description()));
                 return Err(IronError::new(e, modifier));
             },
         }
     }
+
+    /// Store the body of a PUT request, or the piece of it `range`
+    /// (the offset, length and total length) says it is.
+    fn store<R: Read>(&self, path: &StoragePath, reader: &mut R, range: Option<(u64, u64, Option<u64>)>) -> MogResult<()> {
+        match range {
+            Some((offset, _, total)) => self.backend.store_reader_range(path, reader, offset, total),
+            None => self.backend.store_reader_content(path, reader),
+        }
+    }
+
+    /// Check that a request was signed to store the content of the
+    /// file it refers to, if requests have to be.
+    fn verify_store_signature(&self, request: &Request, path: &StoragePath) -> Result<(), String> {
+        match self.url_signer {
+            Some(ref signer) => signer.verify_store_path(request.url.query.as_ref().map(|q| &q[..]), path),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether a request is for a file that's still open, through the
+    /// URL it's being stored through. Whoever's uploading it may see
+    /// how much has arrived, so that they can resume the upload,
+    /// without a read signature.
+    fn is_signed_open_upload(&self, request: &Request) -> bool {
+        let signer = match self.url_signer {
+            Some(ref signer) => signer,
+            None => return false,
+        };
+
+        let path = match parse_url_path(&request.url.path) {
+            Ok(UrlPath::Fid(devid, fid)) => self.backend.fid_storage_path(devid, fid).map_err(|e| e.to_string()),
+            Ok(UrlPath::DomainKey(path)) => with_query_fid(path, &request.url),
+            Err(e) => Err(e),
+        };
+
+        match path {
+            Ok(path) => signer.verify_open_upload(&self.backend, request.url.query.as_ref().map(|q| &q[..]), &path).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    fn handle_delete(&self, request: &Request, path: &StoragePath) -> IronResult<Response> {
+        // Whoever may store a file's content may remove it, and no-one
+        // else.
+        if let Err(e) = self.verify_store_signature(request, path) {
+            return Ok(Response::with((Status::Forbidden, format!("{}\n", e))));
+        }
+
+        let result = if self.delete_files {
+            self.backend.delete_file(path)
+        } else {
+            self.backend.delete_content(path)
+        };
+
+        try!(result.map_err(|e| coerce_mogerror(e)));
+        Ok(Response::with((Status::NoContent,)))
+    }
 }
 
 impl<B: 'static + StorageBackend + Any> Handler for StorageHandler<B> {
     fn handle(&self, request: &mut Request) -> IronResult<Response> {
-        let dk = domain_and_key_from_path(&request.url.path);
+        match request.method {
+            Method::Get | Method::Head | Method::Put | Method::Delete => {},
+            // Directories are made as they're needed, so there's
+            // nothing to do, but mogilefsd likes to ask.
+            Method::Extension(ref m) if m == "MKCOL" => {
+                return Ok(Response::with((Status::Created,)));
+            },
+            _ => {
+                let allowed = vec![
+                    Method::Get, Method::Head, Method::Put, Method::Delete,
+                    Method::Extension("MKCOL".to_string()),
+                ];
+                return Ok(Response::with((Status::MethodNotAllowed, Header(headers::Allow(allowed)))));
+            },
+        }
 
-        if dk.is_err() {
+        // This is checked before the file's looked up, so that an
+        // unsigned request can't find out what's there.
+        match (&request.method, self.read_url_signer.as_ref()) {
+            (&Method::Get, Some(signer)) | (&Method::Head, Some(signer)) => {
+                let query = request.url.query.as_ref().map(|q| &q[..]);
+                if let Err(e) = signer.verify_read(&request.url.path, query) {
+                    if !self.is_signed_open_upload(request) {
+                        return Ok(Response::with((Status::Forbidden, format!("{}\n", e))));
+                    }
+                }
+            },
+            _ => {},
+        }
+
+        let sp = match parse_url_path(&request.url.path) {
+            Ok(UrlPath::Fid(devid, fid)) => Ok(try!(self.backend.fid_storage_path(devid, fid).map_err(|e| coerce_mogerror(e)))),
+            Ok(UrlPath::DomainKey(path)) => with_query_fid(path, &request.url),
+            Err(e) => Err(e),
+        };
+
+        if sp.is_err() {
             info!("BAD Storage request: {:?} {:?} (body = {} bytes) from {:?}",
                   request.method, request.url,
                   request.headers.get::<headers::ContentLength>().map(|h| h.deref()).unwrap_or(&0),
                   request.remote_addr);
-            return Ok(Response::with((Status::BadRequest, format!("{}\n", dk.unwrap_err()))));
+            return Ok(Response::with((Status::BadRequest, format!("{}\n", sp.unwrap_err()))));
         }
 
-        let (domain, key) = dk.unwrap();
-        info!("Storage request: {:?} domain = {} key = {} (body = {} bytes) from {:?}",
-              request.method, domain, key,
+        let path = sp.unwrap();
+        info!("Storage request: {:?} devid = {:?} fid = {:?} domain = {} key = {} (body = {} bytes) from {:?}",
+              request.method, path.devid, path.fid, path.domain, path.key,
               request.headers.get::<headers::ContentLength>().map(|h| h.deref()).unwrap_or(&0),
               request.remote_addr);
 
         match request.method {
-            Method::Get | Method::Head => self.handle_get(request, &domain, &key),
-            Method::Put => self.handle_put(request, &domain, &key),
-            _ => Ok(Response::with((Status::BadRequest, "Unknown request type.\n"))),
+            Method::Get | Method::Head => self.handle_get(request, &path),
+            Method::Put => self.handle_put(request, &path),
+            Method::Delete => self.handle_delete(request, &path),
+            _ => unreachable!(),
         }
     }
 }
 
-fn domain_and_key_from_path(path: &Vec<String>) -> Result<(String, String), String> {
-    let d_index = path.iter().position(|p| p == "d");
-    let k_index = path.iter().position(|p| p == "k");
+/// Whether the content has changed since the version the request's
+/// `If-None-Match` or `If-Modified-Since` header refers to. If there
+/// are neither, it's assumed to have.
+fn modified_since(request: &Request, metadata: &StorageMetadata, etag: &headers::EntityTag) -> bool {
+    // If-Modified-Since is ignored when there's an If-None-Match.
+    match request.headers.get::<headers::IfNoneMatch>() {
+        Some(&headers::IfNoneMatch::Any) => return false,
+        Some(&headers::IfNoneMatch::Items(ref tags)) => return !tags.iter().any(|t| t.weak_eq(etag)),
+        None => {},
+    }
 
-    match (d_index, k_index) {
-        (Some(d), Some(k)) => {
-            let domain = path[(d+1)..k].join("/");
-            let key = path[(k+1)..].join("/");
-            (Ok((domain, key)))
-        },
-        _ => {
-            Err(format!("Could not extract domain or key from path: {:?}", path))
+    match request.headers.get::<headers::IfModifiedSince>() {
+        Some(&headers::IfModifiedSince(ref date)) => metadata.mtime.to_timespec().sec > date.0.to_timespec().sec,
+        None => true,
+    }
+}
+
+/// A domain and key storage path, with the fid of the version its
+/// URL's `fid` query parameter asks for, if any.
+fn with_query_fid(mut path: StoragePath, url: &Url) -> Result<StoragePath, String> {
+    if let Some(ref query) = url.query {
+        for (name, value) in form_urlencoded::parse(query.as_bytes()) {
+            if name == "fid" {
+                path.fid = Some(try!(value.parse().map_err(|_| format!("Bad fid: {:?}", value))));
+            }
         }
     }
+
+    Ok(path)
 }
 
 fn coerce_mogerror(err: MogError) -> IronError {
@@ -97,6 +381,15 @@ fn coerce_mogerror(err: MogError) -> IronError {
         NoContent(ref k) => {
             (Status::NotFound, format!("No content key: {:?}\n", k))
         },
+        UnknownDevice(ref d) => {
+            (Status::NotFound, format!("Unknown device: {}\n", d))
+        },
+        UnknownFid(ref f) => {
+            (Status::NotFound, format!("Unknown fid: {}\n", f))
+        },
+        ref e @ Other(..) if e.error_kind() == "too_large" => {
+            (Status::PayloadTooLarge, format!("{}\n", e))
+        },
         ref e @ _ => {
             (Status::InternalServerError, format!("{}\n", e.description()))
         }
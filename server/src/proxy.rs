@@ -1,51 +1,383 @@
 //! A backend implementation which proxies the requests to a set of
 //! real trackers, possibly doing some monkeying with them in the
 //! process.
+//!
+//! Each tracker has a pool of connections, which all the threads
+//! handling requests share. A background thread sends every tracker a
+//! `noop` now and then, and a tracker that doesn't answer is left out
+//! until it does again.
 
-use mogilefs_client::MogClient;
+use mogilefs_client::{MogClient, Timeouts};
 use mogilefs_common::requests::*;
 use mogilefs_common::{Backend, Request, MogError, MogResult};
+use r2d2::{self, ManageConnection};
+use statsd::client::{Client as StatsdClient};
 use std::any::Any;
-use std::cell::RefCell;
+use std::io;
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use super::r2d2_statsd::StatsdConnectionManager;
 
-thread_local!{
-    static CONNECTION: RefCell<Option<MogClient>> = RefCell::new(None)
+/// How many connections to each tracker are pooled, by default.
+pub const DEFAULT_POOL_SIZE: u32 = 10;
+
+/// How long, in seconds, to wait to connect to a tracker, by default.
+pub const DEFAULT_CONNECT_TIMEOUT: u64 = 5;
+
+/// How long, in seconds, to wait for a tracker to take or answer a
+/// request, by default.
+pub const DEFAULT_IO_TIMEOUT: u64 = 30;
+
+/// How long to wait for a connection to a tracker before trying
+/// another one.
+const CONNECTION_TIMEOUT: u64 = 5;
+
+/// How the proxy talks to the real trackers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProxyConfig {
+    /// How many connections to keep open to each tracker.
+    pub pool_size: u32,
+    /// How long to wait on a tracker before giving up on it, and
+    /// leaving it out until it answers a health check.
+    pub timeouts: Timeouts,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> ProxyConfig {
+        ProxyConfig {
+            pool_size: DEFAULT_POOL_SIZE,
+            timeouts: Timeouts {
+                connect: Some(Duration::from_secs(DEFAULT_CONNECT_TIMEOUT)),
+                read: Some(Duration::from_secs(DEFAULT_IO_TIMEOUT)),
+                write: Some(Duration::from_secs(DEFAULT_IO_TIMEOUT)),
+            },
+        }
+    }
+}
+
+/// Opens the connections in a tracker's pool.
+pub struct TrackerConnectionManager {
+    addr: SocketAddr,
+    timeouts: Timeouts,
+}
+
+impl ManageConnection for TrackerConnectionManager {
+    type Connection = MogClient;
+    type Error = MogError;
+
+    fn connect(&self) -> MogResult<MogClient> {
+        // Trying another tracker is up to the backend, which knows
+        // which ones are healthy.
+        let mut client = MogClient::builder(&[self.addr])
+            .timeouts(self.timeouts)
+            .max_tries(1)
+            .build();
+        // The client doesn't connect until it has something to send.
+        try!(client.request(&Noop));
+        Ok(client)
+    }
+
+    fn is_valid(&self, client: &mut MogClient) -> MogResult<()> {
+        client.request(&Noop).map(|_| ())
+    }
+
+    fn has_broken(&self, client: &mut MogClient) -> bool {
+        !client.is_connected()
+    }
+}
+
+/// How a tracker's connection pool is being used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackerPoolStats {
+    pub addr: SocketAddr,
+    pub healthy: bool,
+    pub connections: u32,
+    pub idle_connections: u32,
+    /// How many times the tracker has been left out for not
+    /// answering.
+    pub ejections: usize,
+}
+
+struct TrackerPool {
+    addr: SocketAddr,
+    pool: r2d2::Pool<TrackerConnectionManager>,
+    /// Opens the connections health checks are made over, which
+    /// aren't taken from the pool, so that a tracker that's busy
+    /// answering requests doesn't look like it's not answering.
+    checker: TrackerConnectionManager,
+    healthy: AtomicBool,
+    ejections: AtomicUsize,
+}
+
+impl TrackerPool {
+    fn new(addr: SocketAddr, proxy_config: &ProxyConfig) -> MogResult<TrackerPool> {
+        let config = r2d2::Config::builder()
+            .pool_size(proxy_config.pool_size)
+            // A tracker that's down shouldn't stop the proxy from
+            // starting; it'll be ejected when it's checked.
+            .initialization_fail_fast(false)
+            // That's what the health checks are for.
+            .test_on_check_out(false)
+            .connection_timeout(Duration::from_secs(CONNECTION_TIMEOUT))
+            .build();
+
+        let manager = TrackerConnectionManager { addr: addr, timeouts: proxy_config.timeouts };
+        match r2d2::Pool::new(config, manager) {
+            Ok(pool) => {
+                Ok(TrackerPool {
+                    addr: addr,
+                    pool: pool,
+                    checker: TrackerConnectionManager { addr: addr, timeouts: proxy_config.timeouts },
+                    healthy: AtomicBool::new(true),
+                    ejections: AtomicUsize::new(0),
+                })
+            },
+            Err(e) => {
+                let msg = format!("Error creating connection pool to tracker {}: {}", addr, e);
+                error!("{}", msg);
+                Err(MogError::Other("Tracker pool error".to_string(), Some(msg)))
+            },
+        }
+    }
+
+    fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::SeqCst)
+    }
+
+    fn eject(&self) {
+        if self.healthy.swap(false, Ordering::SeqCst) {
+            warn!("Ejecting tracker {} until it answers again", self.addr);
+            self.ejections.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn readmit(&self) {
+        if !self.healthy.swap(true, Ordering::SeqCst) {
+            info!("Re-admitting tracker {}", self.addr);
+        }
+    }
+
+    fn check_health(&self) {
+        // Connecting sends a noop.
+        match self.checker.connect() {
+            Ok(_) => self.readmit(),
+            Err(e) => {
+                debug!("Health check of tracker {} failed: {}", self.addr, e);
+                self.eject();
+            },
+        }
+    }
+
+    fn stats(&self) -> TrackerPoolStats {
+        let state = self.pool.state();
+        TrackerPoolStats {
+            addr: self.addr,
+            healthy: self.is_healthy(),
+            connections: state.connections,
+            idle_connections: state.idle_connections,
+            ejections: self.ejections.load(Ordering::SeqCst),
+        }
+    }
 }
 
 /// The main `Backend` implementation for a proxy backend.
 pub struct ProxyTrackerBackend {
-    trackers: Vec<SocketAddr>,
+    pools: Arc<Vec<TrackerPool>>,
+    next_tracker: AtomicUsize,
+    statsd_pool: Option<r2d2::Pool<StatsdConnectionManager>>,
+    health_checker: Option<HealthChecker>,
 }
 
 impl ProxyTrackerBackend {
     pub fn new(trackers: &[SocketAddr]) -> MogResult<ProxyTrackerBackend> {
+        ProxyTrackerBackend::with_config(trackers, ProxyConfig::default())
+    }
+
+    /// Create a backend which talks to the trackers as `config` says.
+    pub fn with_config(trackers: &[SocketAddr], config: ProxyConfig) -> MogResult<ProxyTrackerBackend> {
+        let mut pools = vec![];
+        for &addr in trackers {
+            pools.push(try!(TrackerPool::new(addr, &config)));
+        }
+
         let backend = ProxyTrackerBackend {
-            trackers: trackers.to_owned(),
+            pools: Arc::new(pools),
+            next_tracker: AtomicUsize::new(0),
+            statsd_pool: None,
+            health_checker: None,
         };
         Ok(backend)
     }
 
-    fn send_request<Req: Request + ?Sized, Res: Any>(&self, req: &Req) -> MogResult<Res> {
-        CONNECTION.with(|conn_cell| {
-            let mut conn_opt = conn_cell.borrow_mut();
+    /// Report how the connection pools are being used to statsd, each
+    /// time the trackers' health is checked in the background.
+    pub fn report_stats_to(&mut self, host: &str, prefix: &str) -> MogResult<()> {
+        debug!("Reporting stats to statsd at {:?} with prefix {:?}", host, prefix);
+
+        let config = r2d2::Config::builder().build();
+        let manager = StatsdConnectionManager::new(host, prefix);
+
+        match r2d2::Pool::new(config, manager) {
+            Ok(pool) => {
+                self.statsd_pool = Some(pool);
+                Ok(())
+            },
+            Err(e) => {
+                let msg = format!("Error creating connection pool to statsd: {}", e);
+                error!("{}", msg);
+                Err(MogError::Other("Statsd error".to_string(), Some(msg)))
+            }
+        }
+    }
+
+    /// Start checking on the trackers in the background, every
+    /// `interval`. Stats are only reported to statsd if
+    /// `report_stats_to` was called first.
+    pub fn spawn_health_checks(&mut self, interval: Duration) -> io::Result<()> {
+        let pools = self.pools.clone();
+        let statsd_pool = self.statsd_pool.clone();
 
-            if conn_opt.is_none() {
-                let client = MogClient::new(&self.trackers);
-                *conn_opt = Some(client);
+        let checker = try!(HealthChecker::spawn(interval, move|| {
+            check_health(&pools);
+
+            match statsd_pool.as_ref().map(|p| p.get()) {
+                Some(Ok(mut statsd)) => report_pool_stats(&pools, &mut *statsd),
+                Some(Err(e)) => warn!("Error retrieving statsd connection: {}", e),
+                None => {},
             }
+        }));
+
+        self.health_checker = Some(checker);
+        Ok(())
+    }
 
-            let conn = conn_opt.as_mut().unwrap();
-            debug!("Sending request {:?} to {:?}", req, conn.peer_addr());
+    /// Check on every tracker right away, ejecting the ones which
+    /// don't answer and re-admitting the ones which do.
+    pub fn check_health(&self) {
+        check_health(&self.pools);
+    }
+
+    /// How each tracker's connection pool is being used.
+    pub fn pool_stats(&self) -> Vec<TrackerPoolStats> {
+        self.pools.iter().map(|p| p.stats()).collect()
+    }
+
+    fn send_request<Req: Request + ?Sized, Res: Any>(&self, req: &Req) -> MogResult<Res> {
+        let mut last_err = None;
+
+        for pool in self.pools_to_try() {
+            let mut conn = match pool.pool.get() {
+                Ok(conn) => conn,
+                Err(e) => {
+                    // Nothing's been sent yet, so it's safe to try the
+                    // next tracker. Every connection may just be busy,
+                    // so it's up to the health checks whether the
+                    // tracker's ejected.
+                    warn!("Could not get a connection to tracker {}: {}", pool.addr, e);
+                    last_err = Some(MogError::NoConnection);
+                    continue;
+                },
+            };
+
+            debug!("Sending request {:?} to {:?}", req, pool.addr);
             let response_rslt = conn.request(req);
-            debug!("Got response {:?} from {:?}", response_rslt, conn.peer_addr());
-            response_rslt.and_then(|response| {
+            debug!("Got response {:?} from {:?}", response_rslt, pool.addr);
+
+            // The request may have been carried out before the
+            // connection went wrong, so it isn't sent again, but the
+            // tracker's left out until it passes a health check.
+            match response_rslt {
+                Err(MogError::Io(..)) | Err(MogError::Timeout(..)) => pool.eject(),
+                _ => {},
+            }
+
+            return response_rslt.and_then(|response| {
                 response.downcast::<Res>().ok_or(MogError::BadResponse)
-            })
+            });
+        }
+
+        Err(last_err.unwrap_or(MogError::NoTrackers))
+    }
+
+    /// The trackers to send a request to, in the order to try them.
+    /// Requests take turns between the healthy trackers; if none of
+    /// them are healthy, they're all tried anyway, in case they've
+    /// come back since they were last checked.
+    fn pools_to_try(&self) -> Vec<&TrackerPool> {
+        let start = self.next_tracker.fetch_add(1, Ordering::Relaxed);
+        let count = self.pools.len();
+        let all: Vec<&TrackerPool> = (0..count).map(|i| &self.pools[(start + i) % count]).collect();
+        let healthy: Vec<&TrackerPool> = all.iter().cloned().filter(|p| p.is_healthy()).collect();
+
+        if healthy.is_empty() { all } else { healthy }
+    }
+}
+
+fn check_health(pools: &[TrackerPool]) {
+    for pool in pools.iter() {
+        pool.check_health();
+    }
+}
+
+fn report_pool_stats(pools: &[TrackerPool], statsd: &mut StatsdClient) {
+    for stats in pools.iter().map(|p| p.stats()) {
+        let prefix = format!("mogilefs_server.proxy.trackers.{}", stat_name(&stats.addr));
+        statsd.gauge(&format!("{}.healthy", prefix), if stats.healthy { 1.0 } else { 0.0 });
+        statsd.gauge(&format!("{}.connections", prefix), stats.connections as f64);
+        statsd.gauge(&format!("{}.idle_connections", prefix), stats.idle_connections as f64);
+        statsd.gauge(&format!("{}.active_connections", prefix), (stats.connections - stats.idle_connections) as f64);
+        statsd.gauge(&format!("{}.ejections", prefix), stats.ejections as f64);
+    }
+}
+
+/// A tracker's address, made fit to go in a statsd stat name.
+fn stat_name(addr: &SocketAddr) -> String {
+    addr.to_string()
+        .chars()
+        .filter(|&c| c != '[' && c != ']')
+        .map(|c| if c == '.' || c == ':' { '_' } else { c })
+        .collect()
+}
+
+/// A background thread that checks on the trackers.
+struct HealthChecker {
+    running: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl HealthChecker {
+    fn spawn<F: FnMut() + Send + 'static>(interval: Duration, mut check: F) -> io::Result<HealthChecker> {
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+
+        let handle = try!(thread::Builder::new().name("tracker health checker".to_string()).spawn(move|| {
+            while thread_running.load(Ordering::SeqCst) {
+                check();
+                thread::park_timeout(interval);
+            }
+        }));
+
+        Ok(HealthChecker {
+            running: running,
+            handle: Some(handle),
         })
     }
 }
 
+impl Drop for HealthChecker {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+    }
+}
+
 impl Backend for ProxyTrackerBackend {
     fn create_domain(&self, req: &CreateDomain) -> MogResult<CreateDomain> {
         self.send_request(req)
@@ -82,11 +414,71 @@ impl Backend for ProxyTrackerBackend {
     fn list_keys(&self, req: &ListKeys) -> MogResult<ListKeysResponse> {
         self.send_request(req)
     }
+
+    fn update_class(&self, req: &UpdateClass) -> MogResult<()> {
+        self.send_request(req)
+    }
+
+    fn delete_domain(&self, req: &DeleteDomain) -> MogResult<DeleteDomain> {
+        self.send_request(req)
+    }
+
+    fn delete_class(&self, req: &DeleteClass) -> MogResult<DeleteClass> {
+        self.send_request(req)
+    }
+
+    fn get_domains(&self, req: &GetDomains) -> MogResult<GetDomainsResponse> {
+        self.send_request(req)
+    }
+
+    fn get_hosts(&self, req: &GetHosts) -> MogResult<GetHostsResponse> {
+        self.send_request(req)
+    }
+
+    fn get_devices(&self, req: &GetDevices) -> MogResult<GetDevicesResponse> {
+        self.send_request(req)
+    }
+
+    fn create_host(&self, req: &CreateHost) -> MogResult<CreateHostResponse> {
+        self.send_request(req)
+    }
+
+    fn update_host(&self, req: &UpdateHost) -> MogResult<CreateHostResponse> {
+        self.send_request(req)
+    }
+
+    fn delete_host(&self, req: &DeleteHost) -> MogResult<()> {
+        self.send_request(req)
+    }
+
+    fn create_device(&self, req: &CreateDevice) -> MogResult<()> {
+        self.send_request(req)
+    }
+
+    fn set_state(&self, req: &SetState) -> MogResult<()> {
+        self.send_request(req)
+    }
+
+    fn set_weight(&self, req: &SetWeight) -> MogResult<()> {
+        self.send_request(req)
+    }
+
+    fn list_fids(&self, req: &ListFids) -> MogResult<ListFidsResponse> {
+        self.send_request(req)
+    }
+
+    fn file_debug(&self, req: &FileDebug) -> MogResult<FileDebugResponse> {
+        self.send_request(req)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::net::{SocketAddr, ToSocketAddrs};
+    use mogilefs_common::Backend;
+    use mogilefs_common::requests::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+    use std::thread;
     use super::*;
 
     fn tracker_addr_list() -> Vec<SocketAddr> {
@@ -98,10 +490,146 @@ mod tests {
         rv
     }
 
+    /// A tracker that says OK to everything.
+    fn fake_tracker(listener: TcpListener) -> SocketAddr {
+        let addr = listener.local_addr().unwrap();
+
+        thread::spawn(move|| {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => break,
+                };
+
+                thread::spawn(move|| {
+                    let mut writer = stream.try_clone().unwrap();
+                    for line in BufReader::new(stream).lines() {
+                        if line.is_err() || writer.write_all(b"OK \r\n").is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        addr
+    }
+
+    /// A tracker that answers the `noop` a new connection starts with,
+    /// and then stops answering.
+    fn hung_tracker() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        thread::spawn(move|| {
+            let mut streams = vec![];
+            for stream in listener.incoming() {
+                let mut stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => break,
+                };
+
+                let mut line = String::new();
+                BufReader::new(stream.try_clone().unwrap()).read_line(&mut line).unwrap();
+                stream.write_all(b"OK \r\n").unwrap();
+                streams.push(stream);
+            }
+        });
+
+        addr
+    }
+
+    /// The address of a tracker that isn't there.
+    fn dead_tracker() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+    }
+
+    fn delete_request() -> Delete {
+        Delete { domain: "test_domain".to_string(), key: "test/key/1".to_string() }
+    }
+
     #[test]
     fn initial_state() {
         let addr_list = tracker_addr_list();
         let backend = ProxyTrackerBackend::new(&addr_list).unwrap();
-        assert_eq!(addr_list, backend.trackers);
+        let stats = backend.pool_stats();
+        assert_eq!(addr_list, stats.iter().map(|s| s.addr).collect::<Vec<_>>());
+        assert!(stats.iter().all(|s| s.healthy));
+    }
+
+    #[test]
+    fn requests_share_pooled_connections() {
+        let addr = fake_tracker(TcpListener::bind("127.0.0.1:0").unwrap());
+        let backend = ProxyTrackerBackend::with_config(&[addr], ProxyConfig { pool_size: 2, ..ProxyConfig::default() }).unwrap();
+
+        for _ in 0..5 {
+            assert!(backend.delete(&delete_request()).is_ok());
+        }
+
+        let stats = backend.pool_stats();
+        assert_eq!(1, stats.len());
+        assert_eq!(addr, stats[0].addr);
+        assert!(stats[0].healthy);
+        assert!(stats[0].connections >= 1 && stats[0].connections <= 2);
+        assert_eq!(stats[0].connections, stats[0].idle_connections);
+    }
+
+    #[test]
+    fn unhealthy_trackers_are_ejected_and_readmitted() {
+        let live = fake_tracker(TcpListener::bind("127.0.0.1:0").unwrap());
+        let dead = dead_tracker();
+        let backend = ProxyTrackerBackend::new(&[dead, live]).unwrap();
+
+        backend.check_health();
+        let stats = backend.pool_stats();
+        assert!(!stats[0].healthy);
+        assert_eq!(1, stats[0].ejections);
+        assert!(stats[1].healthy);
+
+        // Only the healthy tracker gets sent anything.
+        for _ in 0..4 {
+            assert!(backend.delete(&delete_request()).is_ok());
+        }
+
+        fake_tracker(TcpListener::bind(dead).unwrap());
+        backend.check_health();
+        assert!(backend.pool_stats().iter().all(|s| s.healthy));
+    }
+
+    #[test]
+    fn busy_trackers_are_not_ejected() {
+        let addr = fake_tracker(TcpListener::bind("127.0.0.1:0").unwrap());
+        let backend = ProxyTrackerBackend::with_config(&[addr], ProxyConfig { pool_size: 1, ..ProxyConfig::default() }).unwrap();
+
+        // Every pooled connection is in use.
+        let _conn = backend.pools[0].pool.get().unwrap();
+        backend.check_health();
+        assert!(backend.pool_stats()[0].healthy);
+        assert_eq!(0, backend.pool_stats()[0].ejections);
+    }
+
+    #[test]
+    fn no_trackers_answering() {
+        let backend = ProxyTrackerBackend::new(&[dead_tracker()]).unwrap();
+        assert!(matches!(backend.delete(&delete_request()), Err(MogError::NoConnection)));
+        backend.check_health();
+        assert!(!backend.pool_stats()[0].healthy);
+    }
+
+    #[test]
+    fn hung_trackers_time_out() {
+        let addr = hung_tracker();
+        let mut config = ProxyConfig::default();
+        config.timeouts.read = Some(Duration::from_millis(100));
+        let backend = ProxyTrackerBackend::with_config(&[addr], config).unwrap();
+
+        assert!(matches!(backend.delete(&delete_request()), Err(MogError::Timeout(..))));
+        assert!(!backend.pool_stats()[0].healthy);
+    }
+
+    #[test]
+    fn stat_names() {
+        assert_eq!("127_0_0_1_7001", stat_name(&"127.0.0.1:7001".parse().unwrap()));
+        assert_eq!("__1_7001", stat_name(&"[::1]:7001".parse().unwrap()));
     }
 }
@@ -1,173 +1,252 @@
-//! Iron middleware and modifier for handling HTTP Range headers.
-
-use iron::headers::{Range, ByteRangeSpec, ContentRange, ContentRangeSpec, ContentLength};
-use iron::modifier::{Modifier, Set};
-use iron::response::ResponseBody;
-use iron::status::Status;
-use iron::{typemap, AroundMiddleware, Handler, IronResult, Request, Response};
-use std::io::{self, Read, Cursor};
-use std::iter;
-use plugin::{Plugin, Pluggable};
-
-struct RangeHandler<H: Handler> {
-    handler: H
+//! Serving HTTP Range requests (RFC 7233) from seekable content.
+
+use iron::headers::{ByteRangeSpec, EntityTag, IfRange, Range};
+use iron::response::{ResponseBody, WriteBody};
+use std::cmp;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use super::backend::StorageMetadata;
+use time;
+
+/// The content type each part of a `multipart/byteranges` response is
+/// labelled with.
+pub static PART_CONTENT_TYPE: &'static str = "application/octet-stream";
+
+/// What part of the content to send back for a `Range` header.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ByteRanges {
+    /// The header should be ignored, and the whole content sent.
+    Whole,
+    /// The first and last byte (inclusive) of each range to send, in
+    /// order, with overlapping and adjacent ranges coalesced.
+    Partial(Vec<(u64, u64)>),
+    /// None of the ranges asked for overlap the content.
+    Unsatisfiable,
 }
 
-impl<H: Handler> Handler for RangeHandler<H> {
-    fn handle(&self, request: &mut Request) -> IronResult<Response> {
-        let modifier = RangeModifier::from_request(request);
-        let response_rslt = self.handler.handle(request);
-        match (response_rslt, modifier) {
-            (Ok(response), Some(range_mod)) => Ok(response.set(range_mod)),
-            (response_rslt, _) => response_rslt,
+/// Work out which parts of content `length` bytes long a `Range`
+/// header asks for.
+pub fn byte_ranges(header: &Range, length: u64) -> ByteRanges {
+    let specs = match header {
+        &Range::Bytes(ref specs) if !specs.is_empty() => specs,
+        _ => return ByteRanges::Whole,
+    };
+
+    let mut ranges = vec![];
+    for spec in specs.iter() {
+        match *spec {
+            // A range that ends before it starts makes the whole
+            // header invalid, so it's ignored.
+            ByteRangeSpec::FromTo(from, to) if from > to => return ByteRanges::Whole,
+            ByteRangeSpec::FromTo(from, to) if from < length => {
+                ranges.push((from, cmp::min(to, length - 1)));
+            },
+            ByteRangeSpec::AllFrom(from) if from < length => {
+                ranges.push((from, length - 1));
+            },
+            ByteRangeSpec::Last(n) if n > 0 && length > 0 => {
+                ranges.push((length - cmp::min(n, length), length - 1));
+            },
+            _ => {},
         }
     }
-}
 
-/// "Around" middleware which handles a `Range` header.
-///
-/// Captures the `Range` header from the request, and then breaks up
-/// the response into the specified ranges, assuming the response is
-/// the whole content.
-pub struct RangeMiddleware;
+    if ranges.is_empty() {
+        return ByteRanges::Unsatisfiable;
+    }
 
-impl AroundMiddleware for RangeMiddleware {
-    fn around(self, handler: Box<Handler>) -> Box<Handler> {
-        Box::new(RangeHandler { handler: handler })
+    ranges.sort();
+    let mut coalesced: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (from, to) in ranges {
+        if let Some(last) = coalesced.last_mut() {
+            if from <= last.1 + 1 {
+                last.1 = cmp::max(last.1, to);
+                continue;
+            }
+        }
+        coalesced.push((from, to));
     }
+
+    ByteRanges::Partial(coalesced)
 }
 
-/// A `Modifier` for `Response`s which slices up the body of the
-/// response into the range(s) specified in the request.
+/// Whether the content is still the version an `If-Range` header
+/// refers to, so that the `Range` header should be honoured.
 ///
-/// Currently does not handle multiple `Range`s specified in the
-/// request. If there isn't exactly one range in the request, this
-/// modifier returns the original response, unmodified.
-#[derive(Debug)]
-pub struct RangeModifier(Vec<ByteRangeSpec>);
-
-impl RangeModifier {
-    /// Create a `RangeModifier` from the request.
-    ///
-    /// If there is no `Range` header present, or the `Range` header
-    /// does not use bytes as its unit, returns `None`.
-    pub fn from_request(request: &Request) -> Option<RangeModifier> {
-        request.headers.get::<Range>().and_then(|r| RangeModifier::from_range_header(r))
-    }
-
-    /// Create a `RangeModifier` from a `Range` header.
-    ///
-    /// If the `Range` header does not use bytes as its unit, returns
-    /// `None`.
-    pub fn from_range_header(header: &Range) -> Option<RangeModifier> {
-        match header {
-            &Range::Bytes(ref spec_vec) => Some(RangeModifier(spec_vec.clone())),
-            _ => None,
-        }
+/// Only an exact match on the modification time, or a strong match
+/// on the entity tag, counts.
+pub fn if_range_matches(header: &IfRange, metadata: &StorageMetadata) -> bool {
+    match *header {
+        IfRange::Date(ref date) => date.0.to_timespec().sec == metadata.mtime.to_timespec().sec,
+        IfRange::EntityTag(ref tag) => tag.strong_eq(&EntityTag::strong(metadata.etag.clone())),
     }
 }
 
-impl Modifier<Response> for RangeModifier {
-    fn modify(self, response: &mut Response) {
-        if self.0.len() != 1 { return; }
-        let range = self.0.into_iter().next().unwrap();
-
-        let orig_body = response.get::<BodyExtractor>();
-        if orig_body.is_err() { return; }
-        let orig_body = orig_body.unwrap();
-
-        debug!("Limiting response to range: {:?} of {:?}", range, orig_body.len());
+/// A response body which streams `length` bytes of `reader`, starting
+/// `offset` bytes in.
+pub struct ContentBody<R> {
+    reader: R,
+    offset: u64,
+    length: u64,
+}
 
-        response.status = Some(Status::PartialContent);
-        match range {
-            ByteRangeSpec::FromTo(from, to) => modify_from_to(response, orig_body, from, to),
-            ByteRangeSpec::AllFrom(from) => modify_all_from(response, orig_body, from),
-            ByteRangeSpec::Last(to) => modify_last(response, orig_body, to),
+impl<R: Read + Seek + Send> ContentBody<R> {
+    pub fn new(reader: R, offset: u64, length: u64) -> ContentBody<R> {
+        ContentBody {
+            reader: reader,
+            offset: offset,
+            length: length,
         }
     }
 }
 
-fn modify_from_to(response: &mut Response, orig_body: Vec<u8>, from: u64, to: u64) {
-    let orig_len = orig_body.len();
-    let req_len = to - from + 1;
-    let new_body: Vec<u8> = orig_body.into_iter()
-        .skip(from as usize)
-        .take(req_len as usize)
-        .collect();
-
-    let res_from = from;
-    let res_to = res_from + (new_body.len() as u64) - 1;
-    response.headers.set(ContentRange(ContentRangeSpec::Bytes {
-        range: Some((res_from, res_to)),
-        instance_length: Some(orig_len as u64),
-    }));
-
-    response.set_mut(new_body);
+impl<R: Read + Seek + Send> WriteBody for ContentBody<R> {
+    fn write_body(&mut self, res: &mut ResponseBody) -> io::Result<()> {
+        try!(self.reader.seek(SeekFrom::Start(self.offset)));
+        try!(io::copy(&mut (&mut self.reader).take(self.length), res));
+        Ok(())
+    }
 }
 
-fn modify_all_from(response: &mut Response, orig_body: Vec<u8>, from: u64) {
-    let orig_len = orig_body.len();
-    let new_body: Vec<u8> = orig_body.into_iter()
-        .skip(from as usize)
-        .collect();
+/// A `multipart/byteranges` response body, which streams several
+/// ranges of `reader`, each in its own part.
+pub struct MultipartBody<R> {
+    reader: R,
+    ranges: Vec<(u64, u64)>,
+    instance_length: u64,
+    boundary: String,
+}
 
-    let res_from = from;
-    let res_to = res_from + (new_body.len() as u64) - 1;
-    response.headers.set(ContentRange(ContentRangeSpec::Bytes {
-        range: Some((res_from, res_to)),
-        instance_length: Some(orig_len as u64),
-    }));
+impl<R: Read + Seek + Send> MultipartBody<R> {
+    /// Send `ranges` of `reader`, which is `instance_length` bytes
+    /// long in all.
+    pub fn new(reader: R, ranges: Vec<(u64, u64)>, instance_length: u64) -> MultipartBody<R> {
+        MultipartBody {
+            reader: reader,
+            ranges: ranges,
+            instance_length: instance_length,
+            boundary: format!("filament-{:016x}", time::precise_time_ns()),
+        }
+    }
 
-    response.set_mut(new_body);
-}
+    /// The value of the response's `Content-Type` header.
+    pub fn content_type(&self) -> String {
+        format!("multipart/byteranges; boundary={}", self.boundary)
+    }
 
-fn modify_last(response: &mut Response, orig_body: Vec<u8>, to: u64) {
-    let orig_len = orig_body.len();
+    /// The length of the whole body, in bytes.
+    pub fn content_length(&self) -> u64 {
+        self.ranges.iter()
+            .map(|&(from, to)| self.part_header(from, to).len() as u64 + (to - from + 1) + 2)
+            .fold(0, |sum, part_len| sum + part_len) + self.trailer().len() as u64
+    }
 
-    if to > (orig_len as u64) {
-        response.headers.set(ContentRange(ContentRangeSpec::Bytes {
-            range: Some((0, (orig_len as u64) - 1)),
-            instance_length: Some(orig_len as u64),
-        }));
+    fn part_header(&self, from: u64, to: u64) -> String {
+        format!("--{}\r\nContent-Type: {}\r\nContent-Range: bytes {}-{}/{}\r\n\r\n",
+                self.boundary, PART_CONTENT_TYPE, from, to, self.instance_length)
+    }
 
-        response.set_mut(orig_body);
-    } else {
-        let req_from = (orig_len as u64) - to;
-        let new_body: Vec<u8> = orig_body.into_iter()
-            .skip(req_from as usize)
-            .collect();
+    fn trailer(&self) -> String {
+        format!("--{}--\r\n", self.boundary)
+    }
 
-        let res_from = req_from;
-        let res_to = res_from + (new_body.len() as u64) - 1;
-        response.headers.set(ContentRange(ContentRangeSpec::Bytes {
-            range: Some((res_from, res_to)),
-            instance_length: Some(orig_len as u64),
-        }));
+    fn write_to<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        for &(from, to) in self.ranges.iter() {
+            try!(out.write_all(self.part_header(from, to).as_bytes()));
+            try!(self.reader.seek(SeekFrom::Start(from)));
+            try!(io::copy(&mut (&mut self.reader).take(to - from + 1), out));
+            try!(out.write_all(b"\r\n"));
+        }
 
-        response.set_mut(new_body);
+        out.write_all(self.trailer().as_bytes())
     }
 }
 
-struct BodyExtractor;
-
-impl typemap::Key for BodyExtractor {
-    type Value = Vec<u8>;
+impl<R: Read + Seek + Send> WriteBody for MultipartBody<R> {
+    fn write_body(&mut self, res: &mut ResponseBody) -> io::Result<()> {
+        self.write_to(res)
+    }
 }
 
-impl Plugin<Response> for BodyExtractor {
-    type Error = io::Error;
+#[cfg(test)]
+mod tests {
+    use iron::headers::{ByteRangeSpec, EntityTag, HttpDate, IfRange, Range};
+    use std::io::Cursor;
+    use super::*;
+    use super::super::backend::StorageMetadata;
+    use time;
 
-    fn eval(response: &mut Response) -> Result<Vec<u8>, io::Error> {
-        match &mut response.body {
-            &mut Some(ref mut writer) => {
-                let content_length = response.headers.get::<ContentLength>().map(|h| h.0).unwrap_or(0);
-                let mut body: Vec<u8> = iter::repeat(0u8).take(content_length as usize).collect();
-                try!(writer.write_body(&mut ResponseBody::new(Cursor::new(body.as_mut()))));
-                Ok(body)
-            },
-            &mut None => {
-                Ok(vec![])
-            }
-        }
+    fn bytes(specs: Vec<ByteRangeSpec>) -> Range {
+        Range::Bytes(specs)
+    }
+
+    #[test]
+    fn byte_ranges_single() {
+        assert_eq!(ByteRanges::Partial(vec![ (2, 5) ]), byte_ranges(&bytes(vec![ ByteRangeSpec::FromTo(2, 5) ]), 10));
+        assert_eq!(ByteRanges::Partial(vec![ (2, 9) ]), byte_ranges(&bytes(vec![ ByteRangeSpec::FromTo(2, 50) ]), 10));
+        assert_eq!(ByteRanges::Partial(vec![ (4, 9) ]), byte_ranges(&bytes(vec![ ByteRangeSpec::AllFrom(4) ]), 10));
+        assert_eq!(ByteRanges::Partial(vec![ (7, 9) ]), byte_ranges(&bytes(vec![ ByteRangeSpec::Last(3) ]), 10));
+        assert_eq!(ByteRanges::Partial(vec![ (0, 9) ]), byte_ranges(&bytes(vec![ ByteRangeSpec::Last(30) ]), 10));
+    }
+
+    #[test]
+    fn byte_ranges_unsatisfiable() {
+        assert_eq!(ByteRanges::Unsatisfiable, byte_ranges(&bytes(vec![ ByteRangeSpec::FromTo(10, 15) ]), 10));
+        assert_eq!(ByteRanges::Unsatisfiable, byte_ranges(&bytes(vec![ ByteRangeSpec::AllFrom(10) ]), 10));
+        assert_eq!(ByteRanges::Unsatisfiable, byte_ranges(&bytes(vec![ ByteRangeSpec::Last(3) ]), 0));
+        assert_eq!(ByteRanges::Unsatisfiable, byte_ranges(&bytes(vec![ ByteRangeSpec::Last(0) ]), 10));
+
+        // One satisfiable range is enough.
+        assert_eq!(ByteRanges::Partial(vec![ (0, 0) ]),
+                   byte_ranges(&bytes(vec![ ByteRangeSpec::AllFrom(20), ByteRangeSpec::FromTo(0, 0) ]), 10));
+    }
+
+    #[test]
+    fn byte_ranges_invalid() {
+        assert_eq!(ByteRanges::Whole, byte_ranges(&bytes(vec![ ByteRangeSpec::FromTo(5, 2) ]), 10));
+        assert_eq!(ByteRanges::Whole, byte_ranges(&Range::Unregistered("lines".to_string(), "1-2".to_string()), 10));
+    }
+
+    #[test]
+    fn byte_ranges_coalesced() {
+        let header = bytes(vec![
+            ByteRangeSpec::FromTo(6, 7),
+            ByteRangeSpec::FromTo(0, 1),
+            ByteRangeSpec::FromTo(1, 3),
+            ByteRangeSpec::FromTo(4, 4),
+            ByteRangeSpec::Last(1),
+        ]);
+        assert_eq!(ByteRanges::Partial(vec![ (0, 4), (6, 7), (9, 9) ]), byte_ranges(&header, 10));
+    }
+
+    #[test]
+    fn if_range_date() {
+        let mtime = time::now_utc();
+        let metadata = StorageMetadata { size: 10, mtime: mtime, etag: "1-2".to_string(), md5: None };
+        assert!(if_range_matches(&IfRange::Date(HttpDate(mtime)), &metadata));
+        assert!(!if_range_matches(&IfRange::Date(HttpDate(mtime - time::Duration::seconds(5))), &metadata));
+    }
+
+    #[test]
+    fn if_range_etag() {
+        let metadata = StorageMetadata { size: 10, mtime: time::now_utc(), etag: "1-2".to_string(), md5: None };
+        assert!(if_range_matches(&IfRange::EntityTag(EntityTag::strong("1-2".to_string())), &metadata));
+        assert!(!if_range_matches(&IfRange::EntityTag(EntityTag::weak("1-2".to_string())), &metadata));
+        assert!(!if_range_matches(&IfRange::EntityTag(EntityTag::strong("1-3".to_string())), &metadata));
+    }
+
+    #[test]
+    fn multipart_body() {
+        let mut body = MultipartBody::new(Cursor::new(b"0123456789".to_vec()), vec![ (0, 1), (8, 9) ], 10);
+        let boundary = body.boundary.clone();
+        let mut written = vec![];
+        body.write_to(&mut written).unwrap();
+
+        let expected = format!(
+            "--{b}\r\nContent-Type: application/octet-stream\r\nContent-Range: bytes 0-1/10\r\n\r\n01\r\n\
+             --{b}\r\nContent-Type: application/octet-stream\r\nContent-Range: bytes 8-9/10\r\n\r\n89\r\n\
+             --{b}--\r\n", b = boundary);
+        assert_eq!(expected, String::from_utf8(written).unwrap());
+        assert_eq!(expected.len() as u64, body.content_length());
+        assert_eq!(format!("multipart/byteranges; boundary={}", boundary), body.content_type());
     }
 }
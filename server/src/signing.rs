@@ -0,0 +1,436 @@
+//! Signed storage URLs, which only whoever was handed them by the
+//! tracker can use, and only until they expire.
+//!
+//! A signature is the hex HMAC-SHA256, under a secret the tracker and
+//! storage server share, of what the URL may be used for, what it
+//! refers to, and when it expires. It goes in the URL's query string
+//! along with the expiry time:
+//!
+//! ```text
+//! /dev1/d/test_domain/k/test/key/1?fid=3&expires=1476662400&signature=6f1c...
+//! ```
+//!
+//! A URL for storing content is signed for the fid, domain and key of
+//! the file that was opened, and is also what it takes to delete that
+//! file's content. A URL for reading content is signed for
+//! its path, since that's all there is to go on for the URLs a proxied
+//! tracker hands out.
+
+use crypto::hmac::Hmac;
+use crypto::mac::{Mac, MacResult};
+use crypto::sha2::Sha256;
+use mogilefs_common::{AroundMiddleware, Backend, MogResult};
+use mogilefs_common::requests::*;
+use rustc_serialize::hex::{FromHex, ToHex};
+use std::fmt;
+use super::backend::{StorageBackend, StoragePath};
+use time::{self, Duration};
+use url::{form_urlencoded, percent_encoding, Url};
+
+/// What a signed URL may be used for. A URL signed for one can't be
+/// used for the other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UrlAction {
+    Store,
+    Read,
+}
+
+impl UrlAction {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            UrlAction::Store => "store",
+            UrlAction::Read => "read",
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct UrlSigner {
+    secret: Vec<u8>,
+}
+
+impl UrlSigner {
+    pub fn new<S: AsRef<[u8]>>(secret: S) -> UrlSigner {
+        UrlSigner { secret: secret.as_ref().to_vec() }
+    }
+
+    /// Sign a URL for storing content for the open file with this fid,
+    /// domain and key until `expires`, in seconds since the epoch.
+    pub fn sign_store_url(&self, url: &mut Url, fid: u64, domain: &str, key: &str, expires: i64) {
+        self.sign(url, UrlAction::Store, &store_resource(fid, domain, key), expires);
+    }
+
+    /// Check that a request to store content for the open file with
+    /// this fid, domain and key was signed to, returning why not if it
+    /// wasn't.
+    pub fn verify_store(&self, query: Option<&str>, fid: u64, domain: &str, key: &str) -> Result<(), String> {
+        self.verify(query, UrlAction::Store, &store_resource(fid, domain, key))
+    }
+
+    /// Check that a request for the file a storage path refers to was
+    /// signed to store its content. The path has to pick out the fid
+    /// it was signed for.
+    pub fn verify_store_path(&self, query: Option<&str>, path: &StoragePath) -> Result<(), String> {
+        match path.fid {
+            Some(fid) => self.verify_store(query, fid, &path.domain, &path.key),
+            None => Err(format!("No fid to check the signature for {:?} against", path.key)),
+        }
+    }
+
+    /// Check that a request was signed to store the content of a file
+    /// that's still open. That's also what it takes to read what's
+    /// arrived of it so far, so that an upload can be resumed.
+    pub fn verify_open_upload<B: StorageBackend>(&self, backend: &B, query: Option<&str>, path: &StoragePath) -> Result<(), String> {
+        match backend.is_open_upload(path) {
+            Ok(true) => self.verify_store_path(query, path),
+            Ok(false) => Err(format!("{:?} is not an open file", path.key)),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Sign a URL for reading its content until `expires`, in seconds
+    /// since the epoch.
+    pub fn sign_read_url(&self, url: &mut Url, expires: i64) {
+        let resource = {
+            let segments: Vec<&str> = url.path_segments().map(|s| s.collect()).unwrap_or(vec![]);
+            read_resource(&segments)
+        };
+        self.sign(url, UrlAction::Read, &resource, expires);
+    }
+
+    /// Check that a request to read the content at the path with these
+    /// segments was signed to, returning why not if it wasn't.
+    pub fn verify_read<S: AsRef<str>>(&self, path: &[S], query: Option<&str>) -> Result<(), String> {
+        self.verify(query, UrlAction::Read, &read_resource(path))
+    }
+
+    fn sign(&self, url: &mut Url, action: UrlAction, resource: &str, expires: i64) {
+        let signature = self.mac(action, resource, expires).code().to_hex();
+        url.query_pairs_mut()
+            .append_pair("expires", &expires.to_string())
+            .append_pair("signature", &signature);
+    }
+
+    fn verify(&self, query: Option<&str>, action: UrlAction, resource: &str) -> Result<(), String> {
+        let mut expires = None;
+        let mut signature = None;
+
+        for (name, value) in form_urlencoded::parse(query.unwrap_or("").as_bytes()) {
+            match &name[..] {
+                "expires" => expires = Some(try!(value.parse::<i64>().map_err(|_| format!("Bad expiry time: {:?}", value)))),
+                "signature" => signature = Some(try!(value.from_hex().map_err(|_| format!("Bad signature: {:?}", value)))),
+                _ => {},
+            }
+        }
+
+        match (expires, signature) {
+            (Some(expires), Some(signature)) => {
+                // Compared in constant time, so that how long it takes
+                // doesn't give the signature away a byte at a time.
+                if self.mac(action, resource, expires) != MacResult::new(&signature) {
+                    Err("Bad signature".to_string())
+                } else if expires <= time::now_utc().to_timespec().sec {
+                    Err("URL has expired".to_string())
+                } else {
+                    Ok(())
+                }
+            },
+            _ => Err("URL is not signed".to_string()),
+        }
+    }
+
+    fn mac(&self, action: UrlAction, resource: &str, expires: i64) -> MacResult {
+        let message = format!("{}\n{}\n{}", action.as_str(), expires, resource);
+        let mut hmac = Hmac::new(Sha256::new(), &self.secret);
+        hmac.input(message.as_bytes());
+        hmac.result()
+    }
+}
+
+impl fmt::Debug for UrlSigner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Keep the secret out of the logs.
+        write!(f, "UrlSigner {{ .. }}")
+    }
+}
+
+fn store_resource(fid: u64, domain: &str, key: &str) -> String {
+    // The domain's length goes first so that the domain and key can't
+    // be split differently to give the same message.
+    format!("{}\n{}:{}{}", fid, domain.len(), domain, key)
+}
+
+fn read_resource<S: AsRef<str>>(path: &[S]) -> String {
+    // The segments are decoded, so that it doesn't matter how the
+    // client chose to encode them.
+    let segments: Vec<String> = path.iter()
+        .map(|s| s.as_ref())
+        .filter(|s| !s.is_empty())
+        .map(|s| percent_encoding::percent_decode(s.as_bytes()).decode_utf8_lossy().into_owned())
+        .collect();
+    format!("/{}", segments.join("/"))
+}
+
+/// Middleware that signs the URLs `get_paths` returns, so that they
+/// can only be read from for a while.
+pub struct SignReadUrls {
+    signer: UrlSigner,
+    expiry: Duration,
+}
+
+impl SignReadUrls {
+    pub fn new(signer: UrlSigner, expiry: Duration) -> SignReadUrls {
+        SignReadUrls {
+            signer: signer,
+            expiry: expiry,
+        }
+    }
+}
+
+impl AroundMiddleware for SignReadUrls {
+    fn around(self, backend: Box<Backend>) -> Box<Backend> {
+        Box::new(SignedReadUrlsBackend {
+            backend: backend,
+            signer: self.signer,
+            expiry: self.expiry,
+        })
+    }
+}
+
+struct SignedReadUrlsBackend {
+    backend: Box<Backend>,
+    signer: UrlSigner,
+    expiry: Duration,
+}
+
+impl Backend for SignedReadUrlsBackend {
+    fn create_domain(&self, req: &CreateDomain) -> MogResult<CreateDomain> {
+        self.backend.create_domain(req)
+    }
+
+    fn create_open(&self, req: &CreateOpen) -> MogResult<CreateOpenResponse> {
+        self.backend.create_open(req)
+    }
+
+    fn create_close(&self, req: &CreateClose) -> MogResult<()> {
+        self.backend.create_close(req)
+    }
+
+    fn create_class(&self, req: &CreateClass) -> MogResult<CreateClassResponse> {
+        self.backend.create_class(req)
+    }
+
+    fn get_paths(&self, req: &GetPaths) -> MogResult<GetPathsResponse> {
+        let GetPathsResponse(mut urls) = try!(self.backend.get_paths(req));
+        let expires = (time::now_utc() + self.expiry).to_timespec().sec;
+        for url in urls.iter_mut() {
+            self.signer.sign_read_url(url, expires);
+        }
+        Ok(GetPathsResponse(urls))
+    }
+
+    fn file_info(&self, req: &FileInfo) -> MogResult<FileInfoResponse> {
+        self.backend.file_info(req)
+    }
+
+    fn delete(&self, req: &Delete) -> MogResult<()> {
+        self.backend.delete(req)
+    }
+
+    fn rename(&self, req: &Rename) -> MogResult<()> {
+        self.backend.rename(req)
+    }
+
+    fn list_keys(&self, req: &ListKeys) -> MogResult<ListKeysResponse> {
+        self.backend.list_keys(req)
+    }
+
+    fn update_class(&self, req: &UpdateClass) -> MogResult<()> {
+        self.backend.update_class(req)
+    }
+
+    fn delete_domain(&self, req: &DeleteDomain) -> MogResult<DeleteDomain> {
+        self.backend.delete_domain(req)
+    }
+
+    fn delete_class(&self, req: &DeleteClass) -> MogResult<DeleteClass> {
+        self.backend.delete_class(req)
+    }
+
+    fn get_domains(&self, req: &GetDomains) -> MogResult<GetDomainsResponse> {
+        self.backend.get_domains(req)
+    }
+
+    fn get_hosts(&self, req: &GetHosts) -> MogResult<GetHostsResponse> {
+        self.backend.get_hosts(req)
+    }
+
+    fn get_devices(&self, req: &GetDevices) -> MogResult<GetDevicesResponse> {
+        self.backend.get_devices(req)
+    }
+
+    fn create_host(&self, req: &CreateHost) -> MogResult<CreateHostResponse> {
+        self.backend.create_host(req)
+    }
+
+    fn update_host(&self, req: &UpdateHost) -> MogResult<CreateHostResponse> {
+        self.backend.update_host(req)
+    }
+
+    fn delete_host(&self, req: &DeleteHost) -> MogResult<()> {
+        self.backend.delete_host(req)
+    }
+
+    fn create_device(&self, req: &CreateDevice) -> MogResult<()> {
+        self.backend.create_device(req)
+    }
+
+    fn set_state(&self, req: &SetState) -> MogResult<()> {
+        self.backend.set_state(req)
+    }
+
+    fn set_weight(&self, req: &SetWeight) -> MogResult<()> {
+        self.backend.set_weight(req)
+    }
+
+    fn list_fids(&self, req: &ListFids) -> MogResult<ListFidsResponse> {
+        self.backend.list_fids(req)
+    }
+
+    fn file_debug(&self, req: &FileDebug) -> MogResult<FileDebugResponse> {
+        self.backend.file_debug(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use mogilefs_common::{Backend, BackendStack};
+    use mogilefs_common::requests::*;
+    use std::io::Cursor;
+    use super::*;
+    use super::super::backend::{parse_url_path, StorageBackend, StoragePath, UrlPath};
+    use super::super::test_support::*;
+    use time::{self, Duration};
+    use url::Url;
+
+    fn store_url(signer: &UrlSigner, expires: i64) -> Url {
+        let mut url = Url::parse("http://127.0.0.1:7503/dev1/d/test_domain/k/test/key/1?fid=3").unwrap();
+        signer.sign_store_url(&mut url, 3, "test_domain", "test/key/1", expires);
+        url
+    }
+
+    fn path_segments(url: &Url) -> Vec<String> {
+        url.path_segments().unwrap().map(|s| s.to_string()).collect()
+    }
+
+    fn later() -> i64 {
+        time::now_utc().to_timespec().sec + 60
+    }
+
+    #[test]
+    fn signed_store_urls() {
+        let signer = UrlSigner::new("secret");
+        let url = store_url(&signer, later());
+        assert_eq!(Ok(()), signer.verify_store(url.query(), 3, "test_domain", "test/key/1"));
+
+        assert!(signer.verify_store(url.query(), 4, "test_domain", "test/key/1").is_err());
+        assert!(signer.verify_store(url.query(), 3, "test_domai", "ntest/key/1").is_err());
+        assert!(signer.verify_store(url.query(), 3, "test_domain", "test/key/2").is_err());
+        assert!(signer.verify_read(&path_segments(&url), url.query()).is_err());
+        assert!(UrlSigner::new("other").verify_store(url.query(), 3, "test_domain", "test/key/1").is_err());
+
+        let tampered = url.query().unwrap().replace("expires=", "expires=1");
+        assert!(signer.verify_store(Some(&tampered), 3, "test_domain", "test/key/1").is_err());
+    }
+
+    #[test]
+    fn signed_store_paths() {
+        let signer = UrlSigner::new("secret");
+        let url = store_url(&signer, later());
+        let mut path = StoragePath::on_device(1, "test_domain", "test/key/1");
+        assert!(signer.verify_store_path(url.query(), &path).is_err());
+
+        path.fid = Some(3);
+        assert_eq!(Ok(()), signer.verify_store_path(url.query(), &path));
+        assert!(signer.verify_store_path(None, &path).is_err());
+
+        path.fid = Some(4);
+        assert!(signer.verify_store_path(url.query(), &path).is_err());
+    }
+
+    #[test]
+    fn signed_read_urls() {
+        let signer = UrlSigner::new("secret");
+        let mut url = Url::parse("http://127.0.0.1:7503/dev1/d/test_domain/k/test%20key/1").unwrap();
+        signer.sign_read_url(&mut url, later());
+
+        // However the path is encoded, it's the same path.
+        assert_eq!(Ok(()), signer.verify_read(&path_segments(&url), url.query()));
+        assert_eq!(Ok(()), signer.verify_read(&["dev1", "d", "test_domain", "k", "test key", "1"], url.query()));
+
+        assert!(signer.verify_read(&["dev2", "d", "test_domain", "k", "test key", "1"], url.query()).is_err());
+        assert!(signer.verify_store(url.query(), 3, "test_domain", "test key/1").is_err());
+    }
+
+    #[test]
+    fn expired_and_unsigned_urls() {
+        let signer = UrlSigner::new("secret");
+        let url = store_url(&signer, time::now_utc().to_timespec().sec - 1);
+        assert_eq!(Err("URL has expired".to_string()), signer.verify_store(url.query(), 3, "test_domain", "test/key/1"));
+        assert!(signer.verify_store(Some("fid=3"), 3, "test_domain", "test/key/1").is_err());
+        assert!(signer.verify_store(None, 3, "test_domain", "test/key/1").is_err());
+        assert!(signer.verify_store(Some("expires=soon&signature=zz"), 3, "test_domain", "test/key/1").is_err());
+    }
+
+    #[test]
+    fn resume_upload_with_both_secrets() {
+        let store_signer = UrlSigner::new("store secret");
+        let read_signer = UrlSigner::new("read secret");
+        let backend = sync_backend_fixture();
+        backend.set_url_signer(Some(store_signer.clone())).unwrap();
+
+        let co_response = backend.create_open(&CreateOpen {
+            domain: TEST_DOMAIN.to_string(), class: None,
+            key: "test/key/3".to_string(), multi_dest: false, size: Some(TEST_CONTENT_1.len() as u64),
+        }).unwrap();
+        let (devid, ref url) = co_response.paths[0];
+        let mut path = match parse_url_path(&path_segments(url)) {
+            Ok(UrlPath::DomainKey(path)) => path,
+            p => panic!("create_open path was {:?}", p),
+        };
+        path.fid = Some(co_response.fid);
+
+        // Part of the content arrives, then the upload's interrupted.
+        assert_eq!(Ok(()), store_signer.verify_open_upload(&backend, url.query(), &path));
+        let total = Some(TEST_CONTENT_1.len() as u64);
+        backend.store_reader_range(&path, &mut Cursor::new(&TEST_CONTENT_1[..8]), 0, total).unwrap();
+
+        // The uploader can't read the file with the URL they have, but
+        // they can find out how much of it has arrived.
+        assert!(read_signer.verify_read(&path_segments(url), url.query()).is_err());
+        assert_eq!(Ok(()), store_signer.verify_open_upload(&backend, url.query(), &path));
+        assert_eq!(8, backend.file_metadata(&path).unwrap().size);
+
+        backend.store_reader_range(&path, &mut Cursor::new(&TEST_CONTENT_1[8..]), 8, total).unwrap();
+        backend.create_close(&CreateClose {
+            domain: TEST_DOMAIN.to_string(), key: "test/key/3".to_string(),
+            fid: co_response.fid, devid: devid, path: url.clone(), checksum: None,
+        }).unwrap();
+
+        // Once it's closed, reading it takes a read signature.
+        assert!(store_signer.verify_open_upload(&backend, url.query(), &path).is_err());
+    }
+
+    #[test]
+    fn middleware_signs_get_paths() {
+        let signer = UrlSigner::new("secret");
+        let mut stack = BackendStack::new(sync_backend_fixture());
+        stack.around(SignReadUrls::new(signer.clone(), Duration::seconds(60)));
+
+        let urls = stack.get_paths(&GetPaths { domain: TEST_DOMAIN.to_string(), key: TEST_KEY_1.to_string(), noverify: true, pathcount: None }).unwrap().0;
+        assert!(!urls.is_empty());
+        for url in urls.iter() {
+            assert_eq!(Ok(()), signer.verify_read(&path_segments(url), url.query()));
+        }
+    }
+}
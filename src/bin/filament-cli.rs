@@ -40,6 +40,12 @@ pub fn main() {
         client.request(&CreateDomain {
             domain: opts.arg_domain.expect("No domain provided."),
         })
+    } else if opts.cmd_delete_domain {
+        client.request(&DeleteDomain {
+            domain: opts.arg_domain.expect("No domain provided."),
+        })
+    } else if opts.cmd_get_domains {
+        client.request(&GetDomains)
     } else if opts.cmd_create_open {
         client.request(&CreateOpen {
             domain: opts.arg_domain.expect("No domain provided."),
@@ -75,6 +81,11 @@ pub fn main() {
             hashtype: opts.flag_hashtype,
             update: opts.flag_update,
         })
+    } else if opts.cmd_delete_class {
+        client.request(&DeleteClass {
+            domain: opts.arg_domain.expect("No domain provided."),
+            class: opts.arg_class.expect("No class name provided."),
+        })
     } else if opts.cmd_file_info {
         client.request(&FileInfo {
             domain: opts.arg_domain.expect("No domain provided."),
@@ -106,6 +117,67 @@ pub fn main() {
             after: opts.flag_after,
             limit: opts.flag_limit,
         })
+    } else if opts.cmd_list_fids {
+        client.request(&ListFids {
+            from: opts.arg_from.expect("No starting fid provided."),
+            to: opts.arg_to.expect("No ending fid provided."),
+        })
+    } else if opts.cmd_file_debug {
+        client.request(&FileDebug {
+            fid: opts.flag_fid,
+            domain: opts.flag_domain,
+            key: opts.flag_key,
+        })
+    } else if opts.cmd_get_hosts {
+        client.request(&GetHosts {
+            hostid: opts.flag_hostid,
+        })
+    } else if opts.cmd_get_devices {
+        client.request(&GetDevices {
+            devid: opts.flag_devid,
+        })
+    } else if opts.cmd_create_host {
+        client.request(&CreateHost {
+            host: opts.arg_host.expect("No host provided."),
+            ip: opts.arg_ip.expect("No IP address provided."),
+            port: opts.arg_port.expect("No port provided."),
+            getport: opts.flag_getport,
+            altip: opts.flag_altip,
+            altmask: opts.flag_altmask,
+            status: opts.flag_status,
+        })
+    } else if opts.cmd_update_host {
+        client.request(&UpdateHost {
+            host: opts.arg_host.expect("No host provided."),
+            ip: opts.flag_ip,
+            port: opts.flag_port,
+            getport: opts.flag_getport,
+            altip: opts.flag_altip,
+            altmask: opts.flag_altmask,
+            status: opts.flag_status,
+        })
+    } else if opts.cmd_delete_host {
+        client.request(&DeleteHost {
+            host: opts.arg_host.expect("No host provided."),
+        })
+    } else if opts.cmd_create_device {
+        client.request(&CreateDevice {
+            hostname: opts.arg_host.expect("No host provided."),
+            devid: opts.arg_devid.expect("No devid provided."),
+            state: opts.flag_state,
+        })
+    } else if opts.cmd_set_state {
+        client.request(&SetState {
+            host: opts.arg_host.expect("No host provided."),
+            device: opts.arg_devid.expect("No devid provided."),
+            state: opts.arg_state.expect("No state provided."),
+        })
+    } else if opts.cmd_set_weight {
+        client.request(&SetWeight {
+            host: opts.arg_host.expect("No host provided."),
+            device: opts.arg_devid.expect("No devid provided."),
+            weight: opts.arg_weight.expect("No weight provided."),
+        })
     } else if opts.cmd_noop {
         client.request(&Noop)
     } else {
@@ -123,14 +195,27 @@ A command-line tool for querying a MogileFS system.
 
 Usage:
   filament-cli [options] create-domain <domain>
+  filament-cli [options] delete-domain <domain>
+  filament-cli [options] get-domains
   filament-cli [options] create-open <domain> <key> [--class=STRING --multi-dest --size=N]
   filament-cli [options] create-close <domain> <key> <fid> <devid> <path> [--checksum=STRING]
   filament-cli [options] create-class <domain> <class> <mindevcount> [--replpolicy=STRING --hashtype=STRING --update]
+  filament-cli [options] delete-class <domain> <class>
   filament-cli [options] file-info <domain> <key>
   filament-cli [options] get-paths <domain> <key> [--no-verify --path-count=N]
   filament-cli [options] rename <domain> <from-key> <to-key>
   filament-cli [options] update-class <domain> <key> <new-class>
   filament-cli [options] list-keys <domain> [--prefix=PREFIX --after=AFTER --limit=N]
+  filament-cli [options] list-fids <from> <to>
+  filament-cli [options] file-debug (--fid=N | --domain=DOMAIN --key=KEY)
+  filament-cli [options] get-hosts [--hostid=N]
+  filament-cli [options] get-devices [--devid=N]
+  filament-cli [options] create-host <host> <ip> <port> [--getport=N --altip=IP --altmask=MASK --status=STATUS]
+  filament-cli [options] update-host <host> [--ip=IP --port=N --getport=N --altip=IP --altmask=MASK --status=STATUS]
+  filament-cli [options] delete-host <host>
+  filament-cli [options] create-device <host> <devid> [--state=STATE]
+  filament-cli [options] set-state <host> <devid> <state>
+  filament-cli [options] set-weight <host> <devid> <weight>
   filament-cli [options] noop
   filament-cli (-h | --help | -v | --version)
 
@@ -155,6 +240,18 @@ struct Options {
     flag_limit: Option<u64>,
     flag_no_verify: bool,
     flag_path_count: Option<u64>,
+    flag_fid: Option<u64>,
+    flag_domain: Option<String>,
+    flag_key: Option<String>,
+    flag_hostid: Option<u64>,
+    flag_devid: Option<u64>,
+    flag_ip: Option<String>,
+    flag_port: Option<u64>,
+    flag_getport: Option<u64>,
+    flag_altip: Option<String>,
+    flag_altmask: Option<String>,
+    flag_status: Option<String>,
+    flag_state: Option<String>,
 
     arg_domain: Option<String>,
     arg_key: Option<String>,
@@ -166,15 +263,35 @@ struct Options {
     arg_class: Option<String>,
     arg_mindevcount: Option<u64>,
     arg_new_class: Option<String>,
+    arg_from: Option<u64>,
+    arg_to: Option<u64>,
+    arg_host: Option<String>,
+    arg_ip: Option<String>,
+    arg_port: Option<u64>,
+    arg_state: Option<String>,
+    arg_weight: Option<u64>,
 
     cmd_create_domain: bool,
+    cmd_delete_domain: bool,
+    cmd_get_domains: bool,
     cmd_create_open: bool,
     cmd_create_close: bool,
     cmd_create_class: bool,
+    cmd_delete_class: bool,
     cmd_file_info: bool,
     cmd_get_paths: bool,
     cmd_rename: bool,
     cmd_update_class: bool,
     cmd_list_keys: bool,
+    cmd_list_fids: bool,
+    cmd_file_debug: bool,
+    cmd_get_hosts: bool,
+    cmd_get_devices: bool,
+    cmd_create_host: bool,
+    cmd_update_host: bool,
+    cmd_delete_host: bool,
+    cmd_create_device: bool,
+    cmd_set_state: bool,
+    cmd_set_weight: bool,
     cmd_noop: bool,
 }
@@ -8,6 +8,7 @@ extern crate libc;
 extern crate mogilefs_common;
 extern crate mogilefs_server;
 extern crate rustc_serialize;
+extern crate time;
 extern crate url;
 
 #[macro_use] extern crate lazy_static;
@@ -15,19 +16,24 @@ extern crate url;
 
 use docopt::Docopt;
 use filament_ext::{MyOpts, AlternateFinderBackend, PublicFinder, SongFinder};
-use iron::{Chain, Iron, Protocol};
+use iron::{Handler, Iron, Protocol};
 use mogilefs_common::{BackendStack, AroundMiddleware};
-use mogilefs_server::mem::{MemBackend, SyncMemBackend};
+use mogilefs_server::backend::StorageBackend;
+use mogilefs_server::disk::DiskStorageBackend;
+use mogilefs_server::mem::{MemBackend, SyncMemBackend, UploadReaper, UrlScheme};
+use mogilefs_server::net::sidechannel::SideChannelListener;
 use mogilefs_server::net::storage::StorageHandler;
 use mogilefs_server::net::tracker::Tracker;
-use mogilefs_server::proxy::ProxyTrackerBackend;
-use mogilefs_server::range::RangeMiddleware;
+use mogilefs_server::proxy::{ProxyConfig, ProxyTrackerBackend};
+use mogilefs_server::signing::{SignReadUrls, UrlSigner};
 use rustc_serialize::{Decodable, Decoder};
 use std::default::Default;
+use std::fs;
 use std::net::SocketAddr;
 use std::thread;
+use std::time::Duration;
 use url::Url;
-use util::{SocketAddrList, WrapSocketAddr};
+use util::{FileSizeLimits, SocketAddrList, WrapSocketAddr};
 
 pub mod lookup;
 pub mod util;
@@ -61,16 +67,76 @@ fn main() {
         ..Default::default()
     });
 
+    let read_url_signer = opts.flag_read_secret.as_ref().map(UrlSigner::new);
+    let read_expiry = time::Duration::seconds(opts.flag_read_expiry as i64);
+
+    // Kept here so that it lives as long as the tracker does.
+    let mut _upload_reaper = None;
+
     let tracker = if opts.cmd_mem_tracker {
-        let backend = SyncMemBackend::new(MemBackend::new(opts.flag_base_url.clone()));
-        let stack = BackendStack::new(backend.clone());
+        let mut mem_backend = MemBackend::with_devices(opts.flag_base_url.clone(), opts.flag_devices);
+        mem_backend.set_upload_expiry(time::Duration::seconds(opts.flag_upload_expiry as i64));
+        mem_backend.set_url_scheme(match opts.flag_url_scheme {
+            UrlSchemeType::DomainKey => UrlScheme::DomainKey,
+            UrlSchemeType::Fid => UrlScheme::Fid,
+        });
+        let url_signer = opts.flag_upload_secret.as_ref().map(UrlSigner::new);
+        mem_backend.set_url_signer(url_signer.clone());
+        if let Some(ref limits) = opts.flag_max_file_size {
+            mem_backend.set_max_file_size(limits.default);
+            for &(ref domain, max) in limits.domains.iter() {
+                mem_backend.set_domain_max_file_size(domain, Some(max));
+            }
+        }
+        if let Some(ref dir) = opts.flag_data_dir {
+            mem_backend.open_data_dir(dir).unwrap_or_else(|e| {
+                panic!("Error opening data directory {:?}: {}", dir, e);
+            });
+        }
+        let backend = SyncMemBackend::new(mem_backend);
+        let mut stack = BackendStack::new(backend.clone());
+        if let Some(ref signer) = read_url_signer {
+            stack.around(SignReadUrls::new(signer.clone(), read_expiry));
+        }
+
+        // Even files that expire straight away are only reaped once a
+        // second, so the reaper doesn't spin holding the backend lock.
+        let reap_interval = Duration::from_secs(std::cmp::max(1, std::cmp::min(opts.flag_upload_expiry, 60)));
+        _upload_reaper = Some(UploadReaper::spawn(backend.clone(), reap_interval).unwrap());
+
+        let storage_handler: Box<Handler> = match opts.flag_content_dir {
+            Some(ref dir) => {
+                if let Some(ref data_dir) = opts.flag_data_dir {
+                    check_content_dir(dir, data_dir);
+                }
+                let disk_backend = DiskStorageBackend::new(dir, backend.clone()).unwrap_or_else(|e| {
+                    panic!("Error opening content directory {:?}: {}", dir, e);
+                });
+                if let Some(ref addr) = opts.flag_sidechannel_ip {
+                    spawn_sidechannel(addr.0, disk_backend.clone());
+                }
+                let mut handler = StorageHandler::new(disk_backend);
+                handler.set_delete_files(opts.flag_delete_files);
+                handler.set_url_signer(url_signer);
+                handler.set_read_url_signer(read_url_signer);
+                Box::new(handler)
+            },
+            None => {
+                if let Some(ref addr) = opts.flag_sidechannel_ip {
+                    spawn_sidechannel(addr.0, backend.clone());
+                }
+                let mut handler = StorageHandler::new(backend.clone());
+                handler.set_delete_files(opts.flag_delete_files);
+                handler.set_url_signer(url_signer);
+                handler.set_read_url_signer(read_url_signer);
+                Box::new(handler)
+            },
+        };
 
         let storage_addr = opts.flag_storage_ip.0.clone();
         let storage_threads = opts.flag_storage_threads;
         thread::spawn(move|| {
-            let mut chain = Chain::new(StorageHandler::new(backend));
-            chain.around(RangeMiddleware);
-            let iron = Iron::new(chain);
+            let iron = Iron::new(storage_handler);
             println!("Storage server (Iron) listening on {:?}", storage_addr);
             iron.listen_with(storage_addr, storage_threads, Protocol::Http, None).unwrap();
         });
@@ -86,7 +152,24 @@ fn main() {
 
         Some(tracker)
     } else if opts.cmd_proxy_tracker {
-        let backend = ProxyTrackerBackend::new(&opts.flag_real_trackers.0).unwrap();
+        let mut config = ProxyConfig::default();
+        config.pool_size = opts.flag_tracker_pool_size;
+        config.timeouts.connect = timeout_secs(opts.flag_tracker_connect_timeout);
+        config.timeouts.read = timeout_secs(opts.flag_tracker_read_timeout);
+        config.timeouts.write = timeout_secs(opts.flag_tracker_write_timeout);
+        let mut backend = ProxyTrackerBackend::with_config(&opts.flag_real_trackers.0, config).unwrap();
+        if let Some(ref host) = opts.flag_statsd_host {
+            if let Err(e) = backend.report_stats_to(
+                &format!("{}", host.0),
+                opts.flag_statsd_prefix.as_ref().unwrap_or(&"".to_string())) {
+                error!("Could not create statsd client: {}", e);
+            }
+        }
+        // At most once a second, so the checks don't spin.
+        let health_check_interval = Duration::from_secs(std::cmp::max(1, opts.flag_health_check_interval));
+        backend.spawn_health_checks(health_check_interval).unwrap_or_else(|e| {
+            panic!("Error starting tracker health checks: {}", e);
+        });
         let mut stack = BackendStack::new(backend);
 
         if let Some(ref url) = opts.flag_alternate_base_url {
@@ -103,6 +186,12 @@ fn main() {
             stack.around(sf_backend);
         }
 
+        // Outermost, so that the paths the alternate finders come up
+        // with are signed too.
+        if let Some(signer) = read_url_signer {
+            stack.around(SignReadUrls::new(signer, read_expiry));
+        }
+
         let mut tracker = Tracker::new(stack);
         if let Some(ref host) = opts.flag_statsd_host {
             if let Err(e) = tracker.report_stats_to(
@@ -124,6 +213,40 @@ fn main() {
     }
 }
 
+/// A timeout given in seconds on the command line, where 0 means not
+/// to time out at all; sockets won't take a timeout of zero.
+fn timeout_secs(secs: u64) -> Option<Duration> {
+    match secs {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    }
+}
+
+/// The disk backend removes files it doesn't recognise from the
+/// content directory, so it mustn't be the data directory, or have it
+/// inside.
+fn check_content_dir(content_dir: &str, data_dir: &str) {
+    // A content directory that isn't there yet can't contain anything.
+    if let (Ok(content_dir), Ok(data_dir)) = (fs::canonicalize(content_dir), fs::canonicalize(data_dir)) {
+        if data_dir.starts_with(&content_dir) {
+            panic!("Content directory {:?} must not be, or contain, the data directory {:?}", content_dir, data_dir);
+        }
+    }
+}
+
+/// Answer mogstored's side channel commands about the content in
+/// `backend`, in the background.
+fn spawn_sidechannel<B: 'static + StorageBackend>(addr: SocketAddr, backend: B) {
+    let listener = SideChannelListener::new(addr, backend).unwrap_or_else(|e| {
+        panic!("Error creating side channel listener on {:?}: {}", addr, e);
+    });
+
+    thread::spawn(move|| {
+        println!("Storage side channel listening on {:?}", addr);
+        listener.run();
+    });
+}
+
 fn run_evented(opts: &Options, tracker: Tracker<BackendStack>) {
     use mogilefs_server::net::tracker::evented::EventedListener;
 
@@ -176,11 +299,16 @@ General Tracker Options:
   -t N, --tracker-threads=N  How many tracker threads to run.          [default: 4]
   -i T, --tracker-io=T       Which I/O model the tracker should use.   [default: Evented]
                              (can be Threaded or Evented)
+  --read-secret=SECRET       Sign the paths get_paths returns with this, and
+                             only serve content through a signed path.
+  --read-expiry=SECS         Seconds a signed get_paths path works for. [default: 3600]
 
 General Storage Options:
   --storage-ip=IP            The ip:port for the storage server to listen on. [default: 0.0.0.0:7503]
   -s N, --storage-threads=N  How many storage threads to run.                 [default: 4]
   -u URL, --base-url=URL     The base URL for the storage server.             [default: http://127.0.0.1:7503/]
+  --sidechannel-ip=IP        The ip:port to answer mogstored's side channel
+                             commands (size and md5) on, if any.
 
 Database Options:
   (These can also be specified as environment variables prefixed by
@@ -193,13 +321,34 @@ Database Options:
 
 In-Memory Tracker (mem-tracker) Options:
   (all General Tracker Options and General Storage Options supported)
+  --devices=N                How many storage devices to simulate.            [default: 1]
+  --upload-expiry=SECS       Seconds a file may stay open before it's reaped. [default: 3600]
+  --url-scheme=S             How storage URLs are laid out.                   [default: DomainKey]
+                             (can be DomainKey for /devN/d/DOMAIN/k/KEY, or
+                             Fid for mogstored's /devN/0/000/001/FID.fid)
+  --max-file-size=LIMITS     The largest file that may be stored, in bytes.
+                             (BYTES for every domain, or DOMAIN=BYTES for one
+                             domain, comma-separated)
+  --data-dir=DIR             Where to keep data so it survives a restart.
+  --content-dir=DIR          Keep file content on disk here, not in memory.
+                             (must not be, or contain, --data-dir)
+  --delete-files             Make storage DELETE requests delete the file,
+                             not just its content.
+  --upload-secret=SECRET     Sign the paths create_open hands out with this,
+                             and only store or delete content through a
+                             signed path.
 
 Proxy Tracker (proxy-tracker) Options:
   (all General Tracker Options and Database Options supported)
   Tracker Options:
-    --real-trackers=IPS           A comma-separated list of actual trackers that we're proxying for.    [default: 127.0.0.1:7001]
-    --alternate-base-url=URL      The base public URL to look for files that are missing on the real trackers.
-    --alternate-song-api-url=URL  The base API URL to find missing song files.
+    --real-trackers=IPS             A comma-separated list of actual trackers that we're proxying for.    [default: 127.0.0.1:7001]
+    --alternate-base-url=URL        The base public URL to look for files that are missing on the real trackers.
+    --alternate-song-api-url=URL    The base API URL to find missing song files.
+    --tracker-pool-size=N           How many connections to keep open to each real tracker.               [default: 10]
+    --health-check-interval=SECS    Seconds between checks that the real trackers are answering.          [default: 5]
+    --tracker-connect-timeout=SECS  Seconds to wait to connect to a real tracker (0 waits forever).       [default: 5]
+    --tracker-read-timeout=SECS     Seconds to wait for a real tracker's answer (0 waits forever).        [default: 30]
+    --tracker-write-timeout=SECS    Seconds to wait to send a real tracker a request (0 waits forever).   [default: 30]
 ";
 
 #[derive(Debug, RustcDecodable)]
@@ -213,10 +362,21 @@ struct Options {
     flag_tracker_ip: WrapSocketAddr,
     flag_tracker_threads: usize,
     flag_tracker_io: TrackerIoType,
+    flag_read_secret: Option<String>,
+    flag_read_expiry: u64,
 
     flag_storage_ip: WrapSocketAddr,
     flag_storage_threads: usize,
     flag_base_url: Url,
+    flag_sidechannel_ip: Option<WrapSocketAddr>,
+    flag_devices: u64,
+    flag_upload_expiry: u64,
+    flag_url_scheme: UrlSchemeType,
+    flag_max_file_size: Option<FileSizeLimits>,
+    flag_data_dir: Option<String>,
+    flag_content_dir: Option<String>,
+    flag_delete_files: bool,
+    flag_upload_secret: Option<String>,
 
     flag_db_host: Option<WrapSocketAddr>,
     flag_db_user: String,
@@ -226,6 +386,11 @@ struct Options {
     flag_real_trackers: SocketAddrList,
     flag_alternate_base_url: Option<Url>,
     flag_alternate_song_api_url: Option<Url>,
+    flag_tracker_pool_size: u32,
+    flag_health_check_interval: u64,
+    flag_tracker_connect_timeout: u64,
+    flag_tracker_read_timeout: u64,
+    flag_tracker_write_timeout: u64,
 }
 
 #[derive(Debug, RustcDecodable)]
@@ -233,3 +398,9 @@ enum TrackerIoType {
     Threaded,
     Evented,
 }
+
+#[derive(Debug, RustcDecodable)]
+enum UrlSchemeType {
+    DomainKey,
+    Fid,
+}
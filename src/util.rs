@@ -59,3 +59,34 @@ impl Decodable for SocketAddrList {
         Ok(SocketAddrList(addrs))
     }
 }
+
+/// Limits on how big stored files may be, given as a comma-separated
+/// list of `BYTES` for every domain, or `DOMAIN=BYTES` for a
+/// particular domain.
+#[derive(Debug)]
+pub struct FileSizeLimits {
+    pub default: Option<u64>,
+    pub domains: Vec<(String, u64)>,
+}
+
+impl Decodable for FileSizeLimits {
+    fn decode<D: Decoder>(d: &mut D) -> Result<Self, D::Error> {
+        let limits_str = try!(d.read_str());
+        let mut limits = FileSizeLimits { default: None, domains: Vec::new() };
+
+        for limit_str in limits_str.split(',') {
+            let (domain, bytes_str) = match limit_str.rfind('=') {
+                Some(i) => (Some(&limit_str[..i]), &limit_str[(i + 1)..]),
+                None => (None, limit_str),
+            };
+            let bytes = try!(bytes_str.parse::<u64>().map_err(|e| d.error(&format!("Unable to parse file size limit {:?}: {}", limit_str, e))));
+
+            match domain {
+                Some(domain) => limits.domains.push((domain.to_string(), bytes)),
+                None => limits.default = Some(bytes),
+            }
+        }
+
+        Ok(limits)
+    }
+}